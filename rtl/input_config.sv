@@ -85,6 +85,11 @@ module input_config (
       23: out = button_y;
       24: out = button_a;
 
+      // Mode and Pause unhandled
+      // Dedicated Time and Alarm keys, matching Select and Service2
+      32: out = button_trig_l;
+      33: out = button_trig_r;
+
       // This input is unused
       7'h7F: out = 0;
       // Other values unhandled
@@ -27,10 +27,15 @@ module rom_loader (
   localparam MASK_CONFIG_ADDR = 25'h17BB80;
   localparam ROM_DATA_ADDR = 25'h192920;
 
-  // wire config_data = ioctl_addr < IMAGE_START_ADDR;
+  // Byte addresses
+  localparam ROM_LENGTH_ADDR = 26'h58;
+  localparam MAX_ROM_LENGTH = 32'h1000;
+
+  wire config_data = ioctl_addr < IMAGE_START_ADDR;
+  wire rom_region = ioctl_addr >= ROM_DATA_ADDR;
+
   assign image_download = ioctl_addr >= IMAGE_START_ADDR && ioctl_addr < MASK_CONFIG_ADDR;
   assign mask_config_download = ioctl_addr >= MASK_CONFIG_ADDR && ioctl_addr < ROM_DATA_ADDR;
-  assign rom_download = ioctl_addr >= ROM_DATA_ADDR;
 
   always_comb begin
     base_addr = ioctl_addr;
@@ -39,7 +44,7 @@ module rom_loader (
       base_addr = ioctl_addr - IMAGE_START_ADDR;
     end else if (mask_config_download) begin
       base_addr = ioctl_addr - MASK_CONFIG_ADDR;
-    end else if (rom_download) begin
+    end else if (rom_region) begin
       base_addr = ioctl_addr - ROM_DATA_ADDR;
     end
   end
@@ -53,6 +58,24 @@ module rom_loader (
   // Byte address
   assign addr_8bit = {base_addr[24:0], read_count == 2'h1};
 
+  // Position of the current byte within its region of the file, undoing the word byte swap
+  wire [25:0] file_byte_addr = {addr_8bit[25:1], ~addr_8bit[0]};
+
+  // Anything stored after the ROM (melody data, extension chunks) must not reach the CPU ROM,
+  // so stop at the ROM length from the config. A length of 0 is treated as a full ROM
+  reg [31:0] rom_length = 0;
+
+  wire [31:0] rom_bound = rom_length == 0 || rom_length > MAX_ROM_LENGTH ? MAX_ROM_LENGTH : rom_length;
+
+  assign rom_download = rom_region && {6'h0, file_byte_addr} < rom_bound;
+
+  always @(posedge clk) begin
+    if (wr_8bit && config_data && file_byte_addr >= ROM_LENGTH_ADDR && file_byte_addr < ROM_LENGTH_ADDR + 26'h4) begin
+      // Little endian
+      rom_length[8*(file_byte_addr[1:0])+:8] <= data_8bit;
+    end
+  end
+
   always @(posedge clk) begin
     wr_8bit <= 0;
 
@@ -74,26 +97,36 @@ module rom_loader (
   ////////////////////////////////////////////////////////////////////////////////////////
   // State machine
 
-  localparam VERSION = 0;
-  localparam MPU = 1;
-  localparam SCREEN_CONFIG = 2;
-  localparam SCREEN_SIZE = 3;
-  localparam SCREEN_RESERVED = 4;
-  localparam INPUT_MAP = 5;
-  localparam DONE = 6;
+  localparam MAGIC = 0;
+  localparam VERSION = 1;
+  localparam MPU = 2;
+  localparam SCREEN_CONFIG = 3;
+  localparam SCREEN_SIZE = 4;
+  localparam SCREEN_RESERVED = 5;
+  localparam INPUT_MAP = 6;
+  localparam DONE = 7;
 
-  reg [7:0] state = VERSION;
+  reg [7:0] state = MAGIC;
   reg [5:0] byte_count = 0;
 
   always @(posedge clk) begin
     if (state == DONE && ~ioctl_download) begin
       // Reset to load another ROM
-      state <= VERSION;
+      state <= MAGIC;
       byte_count <= 6'h0;
     end
 
     if (wr_8bit) begin
       case (state)
+        MAGIC: begin
+          // Skip the 4 byte "GNW1" signature
+          byte_count <= byte_count + 6'h1;
+
+          if (byte_count == 6'h3) begin
+            state <= VERSION;
+            byte_count <= 0;
+          end
+        end
         VERSION: begin
           // Check for version number 1, though we can't do anything about it now
           state <= MPU;
@@ -3,6 +3,6 @@ use vergen::EmitBuilder;
 
 fn main() -> Result<(), Box<dyn Error>> {
     // Emit the instructions
-    EmitBuilder::builder().git_sha(true).emit()?;
+    EmitBuilder::builder().git_sha(false).emit()?;
     Ok(())
 }
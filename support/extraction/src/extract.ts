@@ -188,8 +188,14 @@ const run = () => {
         }
       }
 
+      const hasRightJoystick = !!portMap?.ports.some((port) =>
+        port.type === "s"
+          ? port.bitmap.some((bit) => bit?.action.startsWith("rightJoy"))
+          : port.bit?.action.startsWith("rightJoy"),
+      );
+
       consoles[device] = {
-        device: preset,
+        device: hasRightJoystick ? { ...preset, joysticks: 2 } : preset,
         portMap: !!portMap
           ? portMap
           : {
@@ -199,6 +205,7 @@ const run = () => {
         rom: {
           rom: rom.rom.name,
           melody: rom.melody?.name,
+          melodyHash: rom.melody?.sha,
           romHash: rom.rom.sha,
         },
       };
@@ -383,9 +383,9 @@ const parseButton = (button: string): Action | undefined => {
     case "IPT_KEYPAD":
       return "keypad";
 
-    // Custom cannot be handled in an automated way
+    // Custom cannot be handled in an automated way. Give it the generic custom code, to be edited by hand
     case "IPT_CUSTOM":
-      return "custom";
+      return { custom: 29 };
 
     case "IPT_UNUSED":
       return "unused";
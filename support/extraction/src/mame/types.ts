@@ -46,6 +46,11 @@ export interface PresetDefinition {
   cpu: CPUType;
 
   screen: Screen;
+
+  /**
+   * The number of joysticks on the unit. Omitted for units with at most one
+   */
+  joysticks?: number;
 }
 
 export interface PlatformSpecification {
@@ -53,19 +58,34 @@ export interface PlatformSpecification {
   portMap: PlatformPortMapping;
   metadata: Metadata;
   rom: ROMName;
+  background?: string;
 }
 
 export interface ROMName {
   rom: string;
   melody: string | undefined;
+  melodyHash?: string;
+  melodyBanks?: MelodyBanks;
   romOwner?: string;
+  parent?: string;
   romHash: string;
+  romSize?: number;
+}
+
+export interface MelodyBanks {
+  count: number;
+  size: number;
 }
 
 export interface Metadata {
   year: string;
   company: string;
   name: string;
+  status?: "working" | "imperfect" | "broken";
+  displayName?: string;
+  fileName?: string;
+  sortName?: string;
+  region?: "unknown" | "japan" | "usa" | "europe" | "asia" | "soviet";
 }
 
 /* Inputs */
@@ -101,7 +121,13 @@ export type Action =
   | "powerOff"
   // Keypad is not supported
   | "keypad"
-  | "custom"
+  // A raw core input code
+  | { custom: number }
+  // Dedicated keys, which MAME only names on custom inputs. Set by hand
+  | "mode"
+  | "pause"
+  | "time"
+  | "alarm"
   | "unused";
 
 export interface NamedAction {
@@ -132,4 +158,12 @@ export interface PlatformPortMapping {
   ports: Port[];
   include?: string;
   groundLastIndex?: number;
+  /**
+   * Overrides the CPU's 8 S ports, for devices wired with additional strobe lines. At most 16
+   */
+  sPortCount?: number;
+  /**
+   * Whether unset B and BA ports are active low. Defaults to true
+   */
+  defaultActiveLow?: boolean;
 }
@@ -1,60 +1,264 @@
-use std::{fs::File, path::Path};
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
 
 use colored::Colorize;
 use zip::ZipArchive;
 
+use crate::{encode_format::find_rom_by_hash, error::BuildError, manifest::ROMName};
+
+/// Written into a cached asset directory once it's complete, holding the ROM hash it was extracted for
+pub const CACHE_MARKER: &str = ".gnw-cache";
+
+/// Written into an asset directory when its files are searched by hash, holding the SHA1 of each file hashed so they
+/// aren't hashed again while unchanged
+pub const HASH_CACHE: &str = ".gnw-hashes";
+
 ///
-/// Extract artwork and ROM assets
+/// Extract artwork and ROM assets. A clone in a merged romset stores its files in its parent's archive, which is
+/// extracted too if the clone's own archive is missing them
 ///
+#[allow(clippy::diverging_sub_expression)]
 pub fn get_assets(
     platform_name: &str,
-    owning_rom_name: &Option<String>,
+    rom: &ROMName,
     mame_path: &Path,
     temp_dir: &Path,
-) -> Result<(), String> {
-    let artwork_path = mame_path
-        .join("artwork/foo")
-        .with_file_name(platform_name)
-        .with_extension("zip");
-
-    let roms_path = mame_path
-        .join("roms/foo")
-        .with_file_name(platform_name)
-        .with_extension("zip");
-
-    let mut has_parent = false;
-
-    if let Some(owning_rom_name) = owning_rom_name {
-        let owning_roms_path = mame_path
-            .join("roms/foo")
-            .with_file_name(owning_rom_name)
-            .with_extension("zip");
-
-        if let Err(message) = extract_path(&owning_roms_path, &temp_dir, "parent ROM") {
-            return Err(format!(
-                "Device is dependent on parent ROM {}\n{message}",
-                owning_rom_name.cyan()
+) -> Result<(), BuildError> {
+    let artwork_path = artwork_archive(platform_name, mame_path);
+    let roms_path = rom_archive(platform_name, mame_path);
+
+    if let Some(owning_rom_name) = &rom.rom_owner {
+        let owning_roms_path = rom_archive(owning_rom_name, mame_path);
+
+        if let Err(err) = extract_path(&owning_roms_path, temp_dir, "parent ROM") {
+            return Err(with_context(
+                err,
+                format!(
+                    "Device is dependent on parent ROM {}",
+                    owning_rom_name.cyan()
+                ),
             ));
         }
+    }
+
+    extract_path(&artwork_path, temp_dir, "artwork")?;
 
-        has_parent = true;
+    let own_roms = extract_path(&roms_path, temp_dir, "ROM");
+
+    guard!(let Some(parent) = &rom.parent else {
+        // If the ROM is owned by another device, this title doesn't need its own
+        return if rom.rom_owner.is_some() { Ok(()) } else { own_roms };
+    });
+
+    if own_roms.is_ok() && has_rom_files(rom, temp_dir) {
+        return Ok(());
     }
 
-    extract_path(&artwork_path, &temp_dir, "artwork")?;
-    match extract_path(&roms_path, &temp_dir, "ROM") {
-        Ok(_) => Ok(()),
-        Err(err) => {
-            // If we found a parent, we don't require a ROM for this title
-            if has_parent {
-                Ok(())
-            } else {
-                Err(err)
+    // The hashes are checked again when encoding, so only matching files from the parent are used
+    extract_path(&rom_archive(parent, mame_path), temp_dir, "parent romset").map_err(|err| {
+        let err = with_context(
+            err,
+            format!(
+                "Device's files were not found in its own archive or its parent romset {}",
+                parent.cyan()
+            ),
+        );
+
+        match own_roms {
+            Ok(()) => err,
+            Err(own_err @ BuildError::AssetMissing(_)) => with_context(err, own_err.to_string()),
+            // The device's own archive is installed, so it's broken rather than missing
+            Err(BuildError::CorruptArchive(own_err)) => {
+                BuildError::CorruptArchive(format!("{own_err}\n{err}"))
             }
+            Err(own_err) => own_err,
         }
+    })
+}
+
+///
+/// Prefixes `context` to the message of `err`, keeping its variant so callers can still tell why it failed
+///
+fn with_context(err: BuildError, context: String) -> BuildError {
+    match err {
+        BuildError::AssetMissing(message) => {
+            BuildError::AssetMissing(format!("{context}\n{message}"))
+        }
+        BuildError::CorruptArchive(message) => {
+            BuildError::CorruptArchive(format!("{context}\n{message}"))
+        }
+        BuildError::Io { message, source } => BuildError::Io {
+            message: format!("{context}\n{message}"),
+            source,
+        },
+        err => err,
     }
 }
 
-fn extract_path(file_path: &Path, outdir: &Path, data_type: &str) -> Result<(), String> {
+///
+/// Whether the device's ROM, and melody ROM if it has one, are in `asset_dir`. Files are found by hash where there is
+/// one, as they are when encoding
+///
+fn has_rom_files(rom: &ROMName, asset_dir: &Path) -> bool {
+    let found = |hash: &str| find_rom_by_hash(hash, asset_dir).is_ok();
+
+    found(&rom.rom_hash)
+        && match (&rom.melody, &rom.melody_hash) {
+            (_, Some(melody_hash)) => found(melody_hash),
+            (Some(melody), None) => asset_dir.join(melody).is_file(),
+            (None, None) => true,
+        }
+}
+
+///
+/// Extract just the artwork, for inspecting a device's layout without its ROM
+///
+pub fn get_artwork(
+    platform_name: &str,
+    mame_path: &Path,
+    temp_dir: &Path,
+) -> Result<(), BuildError> {
+    extract_path(
+        &artwork_archive(platform_name, mame_path),
+        temp_dir,
+        "artwork",
+    )
+}
+
+///
+/// The archives (or unpacked directories) in the MAME directory a device is built from. A device with a parent ROM, or
+/// a clone whose parent romset is installed, only depends on its own ROM archive if it exists, matching `get_assets`
+///
+pub fn source_paths(platform_name: &str, rom: &ROMName, mame_path: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![artwork_archive(platform_name, mame_path)];
+
+    let roms_path = rom_archive(platform_name, mame_path);
+    let mut requires_own_roms = true;
+
+    if let Some(owning_rom_name) = &rom.rom_owner {
+        paths.push(rom_archive(owning_rom_name, mame_path));
+        requires_own_roms = false;
+    }
+
+    if let Some(parent) = &rom.parent {
+        let parent_path = rom_archive(parent, mame_path);
+
+        if parent_path.exists() {
+            paths.push(parent_path);
+            requires_own_roms = false;
+        }
+    }
+
+    if requires_own_roms || roms_path.exists() {
+        paths.push(roms_path);
+    }
+
+    paths
+}
+
+///
+/// Whether the cached assets in `asset_dir` are complete, were extracted for `rom_hash`, contain a ROM matching it, and
+/// are newer than every one of `sources` that exists
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn is_cached(asset_dir: &Path, rom_hash: &str, sources: &[PathBuf]) -> bool {
+    let marker_path = asset_dir.join(CACHE_MARKER);
+
+    guard!(let Ok(stored_hash) = fs::read_to_string(&marker_path) else {
+        return false;
+    });
+
+    if !stored_hash.trim().eq_ignore_ascii_case(rom_hash) {
+        return false;
+    }
+
+    guard!(let Ok(cached_at) = fs::metadata(&marker_path).and_then(|metadata| metadata.modified()) else {
+        return false;
+    });
+
+    let sources_unchanged = sources.iter().all(|source| {
+        fs::metadata(source)
+            .and_then(|metadata| metadata.modified())
+            .map_or(true, |modified| modified < cached_at)
+    });
+
+    sources_unchanged && find_rom_by_hash(rom_hash, asset_dir).is_ok()
+}
+
+///
+/// Removes a stale cache entry, so it can be extracted again
+///
+pub fn clear_cache(asset_dir: &Path) -> Result<(), BuildError> {
+    match fs::remove_dir_all(asset_dir) {
+        Ok(_) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(BuildError::io(
+            format!("Could not clear cached assets at {asset_dir:?}"),
+            err,
+        )),
+    }
+}
+
+///
+/// Marks the assets extracted into `asset_dir` as complete for `rom_hash`
+///
+pub fn mark_cached(asset_dir: &Path, rom_hash: &str) -> Result<(), BuildError> {
+    let marker_path = asset_dir.join(CACHE_MARKER);
+
+    fs::write(&marker_path, rom_hash)
+        .map_err(|err| BuildError::io(format!("Could not write cache marker {marker_path:?}"), err))
+}
+
+pub fn artwork_archive(platform_name: &str, mame_path: &Path) -> PathBuf {
+    archive_or_directory(&mame_path.join("artwork"), platform_name)
+}
+
+pub fn rom_archive(rom_name: &str, mame_path: &Path) -> PathBuf {
+    archive_or_directory(&mame_path.join("roms"), rom_name)
+}
+
+///
+/// MAME accepts sets zipped, compressed with 7-Zip, or unpacked into a directory of the same name. The zip is preferred,
+/// then the 7z, and the zip is the path reported as missing if none exist
+///
+fn archive_or_directory(parent: &Path, name: &str) -> PathBuf {
+    let archive = parent.join(format!("{name}.zip"));
+    let seven_zip_archive = parent.join(format!("{name}.7z"));
+    let directory = parent.join(name);
+
+    if archive.exists() {
+        archive
+    } else if seven_zip_archive.exists() {
+        seven_zip_archive
+    } else if directory.is_dir() {
+        directory
+    } else {
+        archive
+    }
+}
+
+#[allow(clippy::diverging_sub_expression)]
+pub fn extract_path(file_path: &Path, outdir: &Path, data_type: &str) -> Result<(), BuildError> {
+    if file_path.is_dir() {
+        return copy_directory(file_path, outdir).map_err(|err| {
+            BuildError::io(
+                format!("Could not copy {data_type} directory {file_path:?}"),
+                err,
+            )
+        });
+    }
+
+    if file_path
+        .extension()
+        .is_some_and(|extension| extension == "7z")
+    {
+        return sevenz_rust::decompress_file(file_path, outdir).map_err(|err| {
+            BuildError::CorruptArchive(format!("Could not extract 7z at {file_path:?}: {err}"))
+        });
+    }
+
     guard!(let Ok(zip_file) = File::open(file_path) else {
         let name = if let Some(name) = file_path.file_name() {
             format!(" ({name:?})")
@@ -62,16 +266,93 @@ fn extract_path(file_path: &Path, outdir: &Path, data_type: &str) -> Result<(),
             "".to_string()
         };
 
-        return Err(format!("Could not open expected {data_type} file{name} at {file_path:?}"));
+        return Err(BuildError::AssetMissing(format!(
+            "Could not open expected {data_type} file{name} at {file_path:?}"
+        )));
     });
 
     guard!(let Ok(mut archive) = ZipArchive::new(zip_file) else {
-        return Err(format!("Could not open zip at {file_path:?}"));
+        return Err(BuildError::CorruptArchive(format!("Could not open zip at {file_path:?}")));
     });
 
     if archive.extract(outdir).is_err() {
-        return Err(format!("Could not extract zip at {file_path:?}"));
+        return Err(BuildError::CorruptArchive(format!(
+            "Could not extract zip at {file_path:?}"
+        )));
+    }
+
+    Ok(())
+}
+
+fn copy_directory(source: &Path, outdir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(outdir)?;
+
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let destination = outdir.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            copy_directory(&entry.path(), &destination)?;
+        } else {
+            fs::copy(entry.path(), destination)?;
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(parent: Option<&str>) -> ROMName {
+        ROMName {
+            rom: "test.program".to_string(),
+            melody: None,
+            melody_hash: None,
+            melody_banks: None,
+            rom_owner: None,
+            parent: parent.map(str::to_string),
+            rom_hash: "0000000000000000000000000000000000000000".to_string(),
+            rom_size: None,
+        }
+    }
+
+    #[test]
+    fn corrupt_archives_are_not_missing() {
+        let root = std::env::temp_dir().join(format!("gnw_assets_{}", std::process::id()));
+        let mame_path = root.join("mame");
+        let asset_dir = root.join("assets");
+        fs::create_dir_all(mame_path.join("artwork").join("gnw_game")).unwrap();
+        fs::create_dir_all(mame_path.join("roms")).unwrap();
+
+        // Nothing of the device is installed
+        assert!(matches!(
+            get_assets("gnw_other", &rom(None), &mame_path, &asset_dir),
+            Err(BuildError::AssetMissing(_))
+        ));
+
+        // Its artwork is, but not its ROM
+        assert!(matches!(
+            get_assets("gnw_game", &rom(None), &mame_path, &asset_dir),
+            Err(BuildError::AssetMissing(_))
+        ));
+
+        fs::write(mame_path.join("roms").join("gnw_game.zip"), b"not a zip").unwrap();
+
+        let result = get_assets("gnw_game", &rom(None), &mame_path, &asset_dir);
+        assert!(
+            matches!(result, Err(BuildError::CorruptArchive(_))),
+            "{result:?}"
+        );
+
+        // A clone's corrupt archive isn't hidden by its parent romset being missing
+        let result = get_assets("gnw_game", &rom(Some("gnw_parent")), &mame_path, &asset_dir);
+        assert!(
+            matches!(result, Err(BuildError::CorruptArchive(_))),
+            "{result:?}"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
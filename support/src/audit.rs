@@ -0,0 +1,138 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use colored::Colorize;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    encode_format::{self, EncodeOptions},
+    manifest::{self, PlatformSpecification},
+};
+
+/// Length of a SHA1 hash as hex
+const HASH_LENGTH: usize = 40;
+
+///
+/// Runs the encoding checks that don't need any assets against every game in the manifest, printing each game that
+/// can't be encoded and why. Returns `true` if every game can be encoded
+///
+pub fn audit_manifest(
+    manifest: &HashMap<String, PlatformSpecification>,
+    options: &EncodeOptions,
+) -> bool {
+    let mut names = manifest.keys().collect::<Vec<&String>>();
+    names.sort();
+
+    let mut fail_count = 0;
+    let mut warning_count = 0;
+
+    for name in &names {
+        match encode_format::audit(&manifest[*name], options) {
+            Ok(warnings) => {
+                for warning in warnings {
+                    println!("{}", format!("{name}: WARNING: {warning}").yellow());
+                    warning_count += 1;
+                }
+            }
+            Err(err) => {
+                for line in err.lines() {
+                    println!("{}", format!("{name}: {line}").red());
+                }
+
+                fail_count += 1;
+            }
+        }
+    }
+
+    println!("-------------------------");
+    println!(
+        "Audited: {}, Unencodable: {fail_count}, Warnings: {warning_count}",
+        names.len()
+    );
+
+    fail_count == 0
+}
+
+///
+/// Parses each game in the manifest separately, so one malformed game doesn't hide the others, and runs the audit
+/// checks against every game that parses along with checks of the manifest's own invariants. Every problem is printed
+/// with the game it belongs to. Returns `true` if every game is valid
+///
+pub fn validate_manifest(manifest_paths: &[PathBuf], options: &EncodeOptions) -> bool {
+    let games: BTreeMap<String, Value> = match manifest::load_manifests(manifest_paths) {
+        Ok(games) => games.into_iter().collect(),
+        Err(err) => {
+            println!("{}", err.red());
+            return false;
+        }
+    };
+
+    let mut invalid_count = 0;
+    let mut warning_count = 0;
+
+    for (name, game) in &games {
+        let platform = match PlatformSpecification::deserialize(game) {
+            Ok(platform) => platform,
+            Err(err) => {
+                println!("{}", format!("{name}: {err}").red());
+                invalid_count += 1;
+                continue;
+            }
+        };
+
+        let mut errors = check_invariants(&platform);
+
+        match encode_format::audit(&platform, options) {
+            Ok(warnings) => {
+                for warning in warnings {
+                    println!("{}", format!("{name}: WARNING: {warning}").yellow());
+                    warning_count += 1;
+                }
+            }
+            Err(err) => errors.extend(err.lines().map(str::to_string)),
+        }
+
+        for error in &errors {
+            println!("{}", format!("{name}: {error}").red());
+        }
+
+        if !errors.is_empty() {
+            invalid_count += 1;
+        }
+    }
+
+    println!("-------------------------");
+    println!(
+        "Validated: {}, Invalid: {invalid_count}, Warnings: {warning_count}",
+        games.len()
+    );
+
+    invalid_count == 0
+}
+
+///
+/// Checks of the manifest entry that encoding would otherwise only find once the game's assets are loaded. Port
+/// indexes and screen sizes are covered by the audit
+///
+fn check_invariants(platform: &PlatformSpecification) -> Vec<String> {
+    let mut errors = vec![];
+
+    let mut check_hash = |field: &str, hash: &str| {
+        if hash.len() != HASH_LENGTH || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            errors.push(format!(
+                "{field} \"{hash}\" is not a {HASH_LENGTH} character hex SHA1 hash"
+            ));
+        }
+    };
+
+    check_hash("romHash", &platform.rom.rom_hash);
+
+    if let Some(melody_hash) = &platform.rom.melody_hash {
+        check_hash("melodyHash", melody_hash);
+    }
+
+    errors
+}
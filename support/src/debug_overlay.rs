@@ -0,0 +1,166 @@
+use resvg::tiny_skia::{Pixmap, PremultipliedColorU8};
+
+use crate::{manifest::PlatformPortMapping, render::InputGeometry};
+
+const GLYPH_WIDTH: i32 = 5;
+const GLYPH_HEIGHT: i32 = 7;
+const SCALE: i32 = 2;
+const PADDING: i32 = 2;
+
+///
+/// Draws the name of each mapped action at the position of its physical input, so the port map can be visually
+/// checked against the device's controls. Inputs without a mapped action are skipped
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn draw_input_labels(
+    pixmap: &mut Pixmap,
+    input_geometry: &[InputGeometry],
+    port_map: &PlatformPortMapping,
+) {
+    let outline = PremultipliedColorU8::from_rgba(255, 0, 255, 255).unwrap();
+    let label_background = PremultipliedColorU8::from_rgba(0, 0, 0, 255).unwrap();
+    let text = PremultipliedColorU8::from_rgba(255, 255, 255, 255).unwrap();
+
+    for input in input_geometry {
+        guard!(let Some(action) = port_map.action_for_input(&input.tag, input.mask) else {
+            continue;
+        });
+
+        let label = match &action.name {
+            Some(name) => name.clone(),
+            None => format!("{:?}", action.action),
+        }
+        .to_uppercase();
+
+        let dimensions = &input.dimensions;
+        let width = dimensions.width as i32;
+        let height = dimensions.height as i32;
+
+        draw_outline(pixmap, dimensions.x, dimensions.y, width, height, outline);
+
+        // Center the label on the input, letting it overhang small buttons
+        let label_width = label.chars().count() as i32 * (GLYPH_WIDTH + 1) * SCALE - SCALE;
+        let label_height = GLYPH_HEIGHT * SCALE;
+        let label_x = dimensions.x + (width - label_width) / 2;
+        let label_y = dimensions.y + (height - label_height) / 2;
+
+        fill_rect(
+            pixmap,
+            label_x - PADDING,
+            label_y - PADDING,
+            label_width + PADDING * 2,
+            label_height + PADDING * 2,
+            label_background,
+        );
+
+        for (i, character) in label.chars().enumerate() {
+            let glyph_x = label_x + i as i32 * (GLYPH_WIDTH + 1) * SCALE;
+
+            for (row, bits) in glyph(character).iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                        continue;
+                    }
+
+                    fill_rect(
+                        pixmap,
+                        glyph_x + column * SCALE,
+                        label_y + row as i32 * SCALE,
+                        SCALE,
+                        SCALE,
+                        text,
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn draw_outline(
+    pixmap: &mut Pixmap,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    color: PremultipliedColorU8,
+) {
+    fill_rect(pixmap, x, y, width, 1, color);
+    fill_rect(pixmap, x, y + height - 1, width, 1, color);
+    fill_rect(pixmap, x, y, 1, height, color);
+    fill_rect(pixmap, x + width - 1, y, 1, height, color);
+}
+
+/// Fills a rectangle, clipped to the pixmap
+fn fill_rect(
+    pixmap: &mut Pixmap,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    color: PremultipliedColorU8,
+) {
+    let pixmap_width = pixmap.width() as i32;
+    let pixmap_height = pixmap.height() as i32;
+
+    let start_x = x.clamp(0, pixmap_width);
+    let end_x = (x + width).clamp(0, pixmap_width);
+    let start_y = y.clamp(0, pixmap_height);
+    let end_y = (y + height).clamp(0, pixmap_height);
+
+    let pixels = pixmap.pixels_mut();
+
+    for row in start_y..end_y {
+        for column in start_x..end_x {
+            pixels[(row * pixmap_width + column) as usize] = color;
+        }
+    }
+}
+
+/// A 5x7 bitmap glyph, one row per byte with the leftmost pixel in bit 4. Unsupported characters render as `?`
+#[rustfmt::skip]
+fn glyph(character: char) -> [u8; 7] {
+    match character {
+        'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'B' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+        'C' => [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+        'D' => [0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110],
+        'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+        'F' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+        'G' => [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+        'H' => [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'I' => [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        'J' => [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+        'K' => [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+        'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+        'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+        'N' => [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+        'O' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'P' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+        'Q' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101],
+        'R' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+        'S' => [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+        'T' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+        'U' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'V' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+        'W' => [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+        'X' => [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+        'Y' => [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100],
+        'Z' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+        '0' => [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+        '1' => [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        '2' => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+        '3' => [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+        '4' => [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+        '5' => [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+        '6' => [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+        '7' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+        '8' => [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+        '9' => [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+        ' ' => [0; 7],
+        '-' => [0, 0, 0, 0b11111, 0, 0, 0],
+        '_' => [0, 0, 0, 0, 0, 0, 0b11111],
+        '.' => [0, 0, 0, 0, 0, 0b01100, 0b01100],
+        '/' => [0, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0],
+        _ => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0, 0b00100],
+    }
+}
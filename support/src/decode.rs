@@ -0,0 +1,824 @@
+use std::{fs, io::Read, path::Path};
+
+use clap::ValueEnum;
+use flate2::read::ZlibDecoder;
+use resvg::tiny_skia::Pixmap;
+
+use crate::{
+    encode_format::{
+        mask_map_length, mpu_version, MaskEntryFormat, BUILD_OPTIONS_TAG, B_PORT_OFFSET,
+        COMPANY_LENGTH, COMPANY_OFFSET, COMPRESSION_OFFSET, COMPRESSION_ZLIB, CONFIG_LENGTH,
+        CPU_CONFIG_LENGTH, CPU_CONFIG_OFFSET, CPU_FLAG_R_PORT_SOUND, CPU_FLAG_SM5A_CORE,
+        EXTENDED_S_PORTS_OFFSET, EXTENSION_OFFSET, FULL_GIT_SHA_LENGTH, FULL_GIT_SHA_OFFSET,
+        GIT_SHA_LENGTH, GIT_SHA_OFFSET, IMAGE_LENGTH_OFFSET, IMAGE_OFFSET_OFFSET,
+        IMAGE_RESOLUTION_OFFSET, INPUT_FLAGS_OFFSET, INPUT_FLAG_ACL_LATCHED, MAGIC,
+        MASK_ENTRY_COUNT_OFFSET, MASK_ENTRY_FORMAT_OFFSET, MAX_S_PORTS, MELODY_HEADER_LENGTH,
+        METADATA_TAG, MPU_OFFSET, NAME_LENGTH, NAME_OFFSET, PALETTE_SIZE_OFFSET,
+        PAYLOAD_CRC_OFFSET, REGION_OFFSET, RESOLUTION_COUNT_OFFSET, RESOLUTION_TABLE_OFFSET,
+        ROM_CRC_OFFSET, ROM_LENGTH_OFFSET, SCREEN_OFFSET, SCREEN_SIZE_OFFSET,
+        SECOND_SCREEN_SIZE_OFFSET, STANDARD_S_PORTS, S_PORTS_OFFSET, S_PORT_COUNT_OFFSET,
+        VERSION_OFFSET, YEAR_OFFSET,
+    },
+    manifest::{AclMode, Action, CPUType, MelodyBanks, NamedAction, Region},
+    render::Resolution,
+};
+
+/// An extension chunk following the ROM data
+pub struct Chunk<'a> {
+    pub tag: [u8; 4],
+    pub data: &'a [u8],
+}
+
+///
+/// Reads the format version of an encoded file, failing if it doesn't start with the format's signature
+///
+pub fn version(data: &[u8]) -> Result<u8, String> {
+    if !data.starts_with(MAGIC) {
+        return Err(format!(
+            "Not a Game & Watch ROM: missing the {} signature. Files built before version 6 of the format have no \
+             signature, and need to be rebuilt",
+            String::from_utf8_lossy(MAGIC)
+        ));
+    }
+
+    data.get(VERSION_OFFSET)
+        .copied()
+        .ok_or_else(|| "File is too short to contain a format version".to_string())
+}
+
+///
+/// Reads the extension chunks of an encoded file
+///
+pub fn extension_chunks(data: &[u8]) -> Result<Vec<Chunk<'_>>, String> {
+    if data.len() <= rom_offset(data)? {
+        return Err(format!(
+            "File is too short ({} bytes) to contain a ROM",
+            data.len()
+        ));
+    }
+
+    let mut offset = read_u32(data, EXTENSION_OFFSET)? as usize;
+    let mut chunks = vec![];
+
+    if offset == 0 {
+        return Ok(chunks);
+    }
+
+    while offset < data.len() {
+        let tag: [u8; 4] = data
+            .get(offset..offset + 4)
+            .ok_or_else(|| format!("Truncated extension chunk tag at {offset:#x}"))?
+            .try_into()
+            .unwrap();
+
+        let length = read_u32(data, offset + 4)? as usize;
+        let start = offset + 8;
+
+        let chunk = data.get(start..start + length).ok_or_else(|| {
+            format!(
+                "Extension chunk {} at {offset:#x} overruns the file",
+                String::from_utf8_lossy(&tag)
+            )
+        })?;
+
+        chunks.push(Chunk { tag, data: chunk });
+        offset = start + length;
+    }
+
+    Ok(chunks)
+}
+
+///
+/// Extracts the build metadata embedded with `--embed-metadata`, if there is any
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn embedded_metadata(data: &[u8]) -> Result<Option<serde_json::Value>, String> {
+    guard!(let Some(chunk) = extension_chunks(data)?.into_iter().find(|chunk| &chunk.tag == METADATA_TAG) else {
+        return Ok(None);
+    });
+
+    serde_json::from_slice(chunk.data)
+        .map(Some)
+        .map_err(|err| format!("Could not parse embedded metadata: {err}"))
+}
+
+///
+/// Extracts the fingerprint of the options the file was built with, if it has one
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn build_fingerprint(data: &[u8]) -> Result<Option<u32>, String> {
+    guard!(let Some(chunk) = extension_chunks(data)?.into_iter().find(|chunk| &chunk.tag == BUILD_OPTIONS_TAG) else {
+        return Ok(None);
+    });
+
+    read_u32(chunk.data, 0).map(Some)
+}
+
+///
+/// Checks the file against its payload CRC, catching truncated or corrupted files
+///
+pub fn verify_payload_crc(data: &[u8]) -> Result<(), String> {
+    version(data)?;
+
+    let expected = read_u32(data, PAYLOAD_CRC_OFFSET)?;
+    let actual = crc32fast::hash(data.get(CONFIG_LENGTH..).unwrap_or_default());
+
+    if actual != expected {
+        return Err(format!(
+            "Payload CRC {actual:#010x} does not match the stored CRC {expected:#010x}"
+        ));
+    }
+
+    Ok(())
+}
+
+///
+/// The length of the image block as stored in the file, which is compressed if the config says so
+///
+pub fn image_length(data: &[u8]) -> Result<usize, String> {
+    version(data)?;
+
+    Ok(read_u32(data, IMAGE_LENGTH_OFFSET)? as usize)
+}
+
+///
+/// Reads the image block, inflating it if it's compressed. Paletted backgrounds are left as palette indices
+///
+pub fn image_block(data: &[u8]) -> Result<Vec<u8>, String> {
+    let length = image_length(data)?;
+
+    let stored = data
+        .get(CONFIG_LENGTH..CONFIG_LENGTH + length)
+        .ok_or_else(|| format!("Image block of {length:#x} bytes overruns the file"))?;
+
+    let compression = data[COMPRESSION_OFFSET];
+
+    match compression {
+        0 => Ok(stored.to_vec()),
+        COMPRESSION_ZLIB => {
+            let Resolution { width, height } = image_resolution(data)?;
+            let mut inflated = Vec::with_capacity(width * height * 6);
+
+            ZlibDecoder::new(stored)
+                .read_to_end(&mut inflated)
+                .map_err(|err| format!("Could not inflate images: {err}"))?;
+
+            Ok(inflated)
+        }
+        _ => Err(format!("Unknown image compression type {compression}")),
+    }
+}
+
+///
+/// The number of colors in the background palette, or zero if the background is full color. Always zero before
+/// version 10
+///
+pub fn palette_size(data: &[u8]) -> Result<usize, String> {
+    if version(data)? < 10 {
+        return Ok(0);
+    }
+
+    data.get(PALETTE_SIZE_OFFSET..PALETTE_SIZE_OFFSET + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+        .ok_or_else(|| "File is too short to contain a config".to_string())
+}
+
+///
+/// Reads the image block as interleaved full color background and mask images, looking up each pixel of a paletted
+/// background
+///
+pub fn full_color_images(data: &[u8]) -> Result<Vec<u8>, String> {
+    let block = image_block(data)?;
+    let palette_size = palette_size(data)?;
+
+    if palette_size == 0 {
+        return Ok(block);
+    }
+
+    let (palette, pixels) = block
+        .split_at_checked(palette_size * 3)
+        .ok_or_else(|| "Image block is too short to contain its palette".to_string())?;
+
+    let mut images = Vec::with_capacity(pixels.len() / 4 * 6);
+
+    for pixel in pixels.chunks_exact(4) {
+        let index = pixel[0] as usize;
+        let color = palette
+            .get(index * 3..index * 3 + 3)
+            .ok_or_else(|| format!("Palette index {index} is out of range"))?;
+
+        for channel in 0..3 {
+            images.push(color[channel]);
+            images.push(pixel[channel + 1]);
+        }
+    }
+
+    Ok(images)
+}
+
+///
+/// The file offset of the ROM data, which directly follows the images and the fixed size mask config
+///
+pub fn rom_offset(data: &[u8]) -> Result<usize, String> {
+    Ok(CONFIG_LENGTH + image_length(data)? + mask_map_length(image_resolution(data)?))
+}
+
+///
+/// The resolution of the primary images
+///
+pub fn image_resolution(data: &[u8]) -> Result<Resolution, String> {
+    version(data)?;
+
+    let packed = read_u32(data, IMAGE_RESOLUTION_OFFSET)?;
+
+    Ok(Resolution {
+        width: (packed & 0x3FF) as usize,
+        height: ((packed >> 10) & 0x3FF) as usize,
+    })
+}
+
+///
+/// How the file's mask entries are packed. Always compact before version 9
+///
+pub fn mask_entry_format(data: &[u8]) -> Result<MaskEntryFormat, String> {
+    if version(data)? < 9 {
+        return Ok(MaskEntryFormat::Compact);
+    }
+
+    match data.get(MASK_ENTRY_FORMAT_OFFSET) {
+        Some(0) => Ok(MaskEntryFormat::Compact),
+        Some(1) => Ok(MaskEntryFormat::Wide),
+        Some(format) => Err(format!("Unknown mask entry format {format:#x}")),
+        None => Err("File is too short to contain a config".to_string()),
+    }
+}
+
+///
+/// Extracts the ROM data following the mask config
+///
+pub fn rom(data: &[u8]) -> Result<&[u8], String> {
+    let offset = rom_offset(data)?;
+    let length = read_u32(data, ROM_LENGTH_OFFSET)? as usize;
+
+    data.get(offset..offset + length)
+        .ok_or_else(|| format!("ROM of {length:#x} bytes overruns the file"))
+}
+
+///
+/// Extracts the melody ROM following the ROM data. Empty for devices without one
+///
+pub fn melody(data: &[u8]) -> Result<&[u8], String> {
+    let header_offset = rom_offset(data)? + read_u32(data, ROM_LENGTH_OFFSET)? as usize;
+    let length = read_u32(data, header_offset)? as usize;
+    let start = header_offset + MELODY_HEADER_LENGTH;
+
+    data.get(start..start + length)
+        .ok_or_else(|| format!("Melody ROM at {start:#x} overruns the file"))
+}
+
+///
+/// The bank layout of the melody ROM, with each bank's size derived from the melody length. `None` if the layout
+/// wasn't declared
+///
+pub fn melody_banks(data: &[u8]) -> Result<Option<MelodyBanks>, String> {
+    let header_offset = rom_offset(data)? + read_u32(data, ROM_LENGTH_OFFSET)? as usize;
+    let length = read_u32(data, header_offset)? as usize;
+    let count = *data
+        .get(header_offset + 4)
+        .ok_or_else(|| format!("Melody header at {header_offset:#x} overruns the file"))?;
+
+    if count == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(MelodyBanks {
+        count,
+        size: length / count as usize,
+    }))
+}
+
+///
+/// Reads the CPU type from the MPU version in an encoded config
+///
+pub fn mpu(data: &[u8]) -> Result<CPUType, String> {
+    let version = *data
+        .get(MPU_OFFSET)
+        .ok_or_else(|| "Config is too short to contain an MPU version".to_string())?;
+
+    CPUType::value_variants()
+        .iter()
+        .find(|cpu| mpu_version(cpu) == version)
+        .cloned()
+        .ok_or_else(|| format!("Unknown MPU version {version:#x}"))
+}
+
+///
+/// The CPU config, describing quirks of the MPU beyond its version. All zero before version 12
+///
+pub fn cpu_config(data: &[u8]) -> Result<[u8; CPU_CONFIG_LENGTH], String> {
+    if version(data)? < 12 {
+        return Ok([0; CPU_CONFIG_LENGTH]);
+    }
+
+    data.get(CPU_CONFIG_OFFSET..CPU_CONFIG_OFFSET + CPU_CONFIG_LENGTH)
+        .map(|bytes| bytes.try_into().unwrap())
+        .ok_or_else(|| "File is too short to contain a config".to_string())
+}
+
+/// A run of mask pixels on one row, belonging to a single segment
+#[derive(Debug, PartialEq)]
+pub struct MaskEntry {
+    pub id: u16,
+    pub x: usize,
+    pub y: usize,
+    pub length: usize,
+}
+
+///
+/// Unpacks a block of mask entries packed in `format`, ignoring any trailing partial entry
+///
+pub fn mask_entries(block: &[u8], format: MaskEntryFormat) -> Vec<MaskEntry> {
+    let entry_length = format.entry_length();
+    let id_bits = format.id_bits();
+
+    block
+        .chunks_exact(entry_length)
+        .map(|entry| {
+            let mut bytes = [0; 8];
+            bytes[..entry_length].copy_from_slice(entry);
+            let packed = u64::from_le_bytes(bytes);
+            let fields = packed >> id_bits;
+
+            MaskEntry {
+                id: (packed & ((1 << id_bits) - 1)) as u16,
+                x: (fields & 0x3FF) as usize,
+                y: ((fields >> 10) & 0x3FF) as usize,
+                length: ((fields >> 20) & 0x3FF) as usize,
+            }
+        })
+        .collect()
+}
+
+/// The input mapping of an encoded config. Unmapped inputs are `None`
+pub struct InputPorts {
+    /// Every S port, including any beyond the standard 8
+    pub s: Vec<[Option<NamedAction>; 4]>,
+    pub b: Option<NamedAction>,
+    pub ba: Option<NamedAction>,
+    pub acl: Option<NamedAction>,
+    /// Always momentary before version 11
+    pub acl_mode: AclMode,
+    /// The index of the S port grounded last, if set
+    pub ground_last_index: Option<u8>,
+}
+
+///
+/// Reads the input mapping of an encoded config
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn input_ports(data: &[u8]) -> Result<InputPorts, String> {
+    guard!(let Some(config) = data.get(..CONFIG_LENGTH) else {
+        return Err("Config is too short to contain an input mapping".to_string());
+    });
+
+    let s_port_count = match config[S_PORT_COUNT_OFFSET] as usize {
+        // Only devices with extended ports store their count
+        0 => STANDARD_S_PORTS,
+        count if count > MAX_S_PORTS => {
+            return Err(format!(
+                "Config has {count} S ports, but at most {MAX_S_PORTS} are supported"
+            ))
+        }
+        count => count,
+    };
+
+    let mut s = vec![];
+
+    for index in 0..s_port_count {
+        let offset = s_port_offset(index);
+
+        let mut bitmap: [Option<NamedAction>; 4] = Default::default();
+
+        for (bit, action) in bitmap.iter_mut().enumerate() {
+            *action = self::action(config[offset + bit])?;
+        }
+
+        s.push(bitmap);
+    }
+
+    let ground_last_index = match config[B_PORT_OFFSET + 3] {
+        0 => None,
+        // Indexes start at 1
+        index => Some(index - 1),
+    };
+
+    // Reserved before version 11, so may hold the reserved fill
+    let acl_mode =
+        if version(data)? >= 11 && config[INPUT_FLAGS_OFFSET] & INPUT_FLAG_ACL_LATCHED != 0 {
+            AclMode::Latched
+        } else {
+            AclMode::Momentary
+        };
+
+    Ok(InputPorts {
+        s,
+        b: action(config[B_PORT_OFFSET])?,
+        ba: action(config[B_PORT_OFFSET + 1])?,
+        acl: action(config[B_PORT_OFFSET + 2])?,
+        acl_mode,
+        ground_last_index,
+    })
+}
+
+/// The offset of the 4 byte input mapping of the S port at `index`
+fn s_port_offset(index: usize) -> usize {
+    if index < STANDARD_S_PORTS {
+        S_PORTS_OFFSET + index * 4
+    } else {
+        EXTENDED_S_PORTS_OFFSET + (index - STANDARD_S_PORTS) * 4
+    }
+}
+
+///
+/// Lays out the input mapping of an encoded config as a table, with a row for each input of every S port with any
+/// mapped input, and for B, BA, and ACL. Each row has the stored config byte next to the action it decodes as
+///
+pub fn input_map_table(data: &[u8]) -> Result<Vec<String>, String> {
+    let ports = input_ports(data)?;
+
+    let mut rows = vec![[
+        "Port".to_string(),
+        "Bit".to_string(),
+        "Action".to_string(),
+        "Active low".to_string(),
+        "Code".to_string(),
+    ]];
+
+    let mut push_row = |port: String, bit: String, offset: usize| -> Result<(), String> {
+        let value = data[offset];
+        let action = match action(value)? {
+            Some(action) => format!("{:?}", action.action),
+            None => "Unused".to_string(),
+        };
+        let active_low = if value & 0x80 != 0 { "yes" } else { "no" };
+
+        rows.push([
+            port,
+            bit,
+            action,
+            active_low.to_string(),
+            format!("{value:#04x}"),
+        ]);
+
+        Ok(())
+    };
+
+    for (index, bitmap) in ports.s.iter().enumerate() {
+        if bitmap.iter().all(Option::is_none) {
+            continue;
+        }
+
+        for bit in 0..bitmap.len() {
+            push_row(
+                format!("S{}", index + 1),
+                bit.to_string(),
+                s_port_offset(index) + bit,
+            )?;
+        }
+    }
+
+    push_row("B".to_string(), "-".to_string(), B_PORT_OFFSET)?;
+    push_row("BA".to_string(), "-".to_string(), B_PORT_OFFSET + 1)?;
+    let acl = match ports.acl_mode {
+        AclMode::Momentary => "ACL".to_string(),
+        AclMode::Latched => "ACL (latched)".to_string(),
+    };
+    push_row(acl, "-".to_string(), B_PORT_OFFSET + 2)?;
+
+    let widths = (0..rows[0].len())
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect::<Vec<usize>>();
+
+    let mut lines = rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect::<Vec<String>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<String>>();
+
+    if let Some(index) = ports.ground_last_index {
+        lines.push(format!("Grounded last: S{}", index + 1));
+    }
+
+    Ok(lines)
+}
+
+///
+/// Decodes the config byte of a single input. Unused inputs are `None`
+///
+pub fn action(value: u8) -> Result<Option<NamedAction>, String> {
+    let action = match value & 0x7F {
+        0 => Action::JoyUp,
+        1 => Action::JoyDown,
+        2 => Action::JoyLeft,
+        3 => Action::JoyRight,
+        4 => Action::Button1,
+        5 => Action::Button2,
+        6 => Action::Button3,
+        7 => Action::Button4,
+        8 => Action::Button5,
+        9 => Action::Button6,
+        10 => Action::Button7,
+        11 => Action::Button8,
+        12 => Action::Select,
+        13 => Action::Start1,
+        14 => Action::Start2,
+        15 => Action::Service1,
+        16 => Action::Service2,
+        17 => Action::LeftJoyUp,
+        18 => Action::LeftJoyDown,
+        19 => Action::LeftJoyLeft,
+        20 => Action::LeftJoyRight,
+        21 => Action::RightJoyUp,
+        22 => Action::RightJoyDown,
+        23 => Action::RightJoyLeft,
+        24 => Action::RightJoyRight,
+        25 => Action::VolumeDown,
+        26 => Action::PowerOn,
+        27 => Action::PowerOff,
+        28 => Action::Keypad,
+        30 => Action::Mode,
+        31 => Action::Pause,
+        32 => Action::Time,
+        33 => Action::Alarm,
+        0x7F => return Ok(None),
+        // Includes 29, the code the extractor gives custom inputs
+        code => Action::Custom(code),
+    };
+
+    Ok(Some(NamedAction {
+        action,
+        active_low: value & 0x80 != 0,
+        name: None,
+    }))
+}
+
+///
+/// Prints the header of the encoded file at `path`, and extracts its background and mask images, ROM, and melody ROM
+/// into `output_dir`
+///
+pub fn dump(path: &Path, output_dir: &Path) -> Result<(), String> {
+    let data = fs::read(path).map_err(|err| format!("Could not read {path:?}: {err}"))?;
+
+    let rom_offset = rom_offset(&data)?;
+
+    if data.len() <= rom_offset {
+        return Err(format!(
+            "File is too short ({} bytes) to contain a ROM",
+            data.len()
+        ));
+    }
+
+    let full_sha = &data[FULL_GIT_SHA_OFFSET..FULL_GIT_SHA_OFFSET + FULL_GIT_SHA_LENGTH];
+
+    // The full commit is only written with `--embed-full-sha`, and is reserved otherwise
+    let sha = if full_sha.iter().all(u8::is_ascii_hexdigit) {
+        String::from_utf8_lossy(full_sha)
+    } else {
+        String::from_utf8_lossy(&data[GIT_SHA_OFFSET..GIT_SHA_OFFSET + GIT_SHA_LENGTH])
+    };
+
+    println!("Format version: {}", data[VERSION_OFFSET]);
+    println!("Built by: {}", sha.trim_end_matches('\0'));
+    println!("MPU: {:?}", mpu(&data)?);
+
+    let cpu_flags = cpu_config(&data)?[0];
+    let quirks = [
+        (CPU_FLAG_SM5A_CORE, "SM5A core"),
+        (CPU_FLAG_R_PORT_SOUND, "R port sound"),
+    ]
+    .iter()
+    .filter(|(flag, _)| cpu_flags & flag != 0)
+    .map(|(_, name)| *name)
+    .collect::<Vec<&str>>();
+
+    if !quirks.is_empty() {
+        println!("CPU quirks: {}", quirks.join(", "));
+    }
+
+    let text = |offset: usize, length: usize| {
+        String::from_utf8_lossy(&data[offset..offset + length])
+            .trim_end_matches('\0')
+            .to_string()
+    };
+
+    let year = u16::from_le_bytes([data[YEAR_OFFSET], data[YEAR_OFFSET + 1]]);
+
+    println!("Name: {}", text(NAME_OFFSET, NAME_LENGTH));
+    println!("Company: {}", text(COMPANY_OFFSET, COMPANY_LENGTH));
+    println!(
+        "Year: {}",
+        if year == 0 {
+            "Unknown".to_string()
+        } else {
+            year.to_string()
+        }
+    );
+
+    let screen = match data[SCREEN_OFFSET] {
+        0 => "Single",
+        1 => "Dual vertical",
+        2 => "Dual horizontal",
+        _ => "Unknown",
+    };
+
+    let size = read_u32(&data, SCREEN_SIZE_OFFSET)? & 0xFFFFF;
+
+    if data[SCREEN_OFFSET] == 0 {
+        println!("Screen: {screen}, {}x{}", size & 0x3FF, size >> 10);
+    } else {
+        let second_size = read_u32(&data, SECOND_SCREEN_SIZE_OFFSET)? & 0xFFFFF;
+
+        println!(
+            "Screen: {screen}, {}x{} and {}x{}",
+            size & 0x3FF,
+            size >> 10,
+            second_size & 0x3FF,
+            second_size >> 10
+        );
+    }
+
+    let region = match data[REGION_OFFSET] {
+        0 => Region::Unknown,
+        1 => Region::Japan,
+        2 => Region::Usa,
+        3 => Region::Europe,
+        4 => Region::Asia,
+        5 => Region::Soviet,
+        region => return Err(format!("Unknown region {region}")),
+    };
+    println!("Region: {region:?}");
+
+    let ports = input_ports(&data)?;
+
+    println!("Inputs:");
+
+    for (index, bitmap) in ports.s.iter().enumerate() {
+        if bitmap.iter().all(Option::is_none) {
+            continue;
+        }
+
+        let bits = bitmap
+            .iter()
+            .map(|action| describe_action(action.as_ref()))
+            .collect::<Vec<String>>();
+
+        println!("  S{}: {}", index + 1, bits.join(", "));
+    }
+
+    println!("  B: {}", describe_action(ports.b.as_ref()));
+    println!("  BA: {}", describe_action(ports.ba.as_ref()));
+    match ports.acl_mode {
+        AclMode::Momentary => println!("  ACL: {}", describe_action(ports.acl.as_ref())),
+        AclMode::Latched => println!("  ACL: {} (latched)", describe_action(ports.acl.as_ref())),
+    }
+
+    if let Some(index) = ports.ground_last_index {
+        println!("  Grounded last: S{}", index + 1);
+    }
+
+    let rom = rom(&data)?;
+    let rom_length = rom.len();
+    let rom_crc = read_u32(&data, ROM_CRC_OFFSET)?;
+    let rom_crc_status = if crc32fast::hash(rom) == rom_crc {
+        "OK"
+    } else {
+        "MISMATCH"
+    };
+
+    println!("ROM: {rom_length:#x} bytes, CRC {rom_crc:#010x} ({rom_crc_status})");
+
+    let melody = melody(&data)?;
+    println!("Melody ROM: {:#x} bytes", melody.len());
+
+    if let Some(banks) = melody_banks(&data)? {
+        println!("Melody banks: {} of {:#x} bytes", banks.count, banks.size);
+    }
+
+    match verify_payload_crc(&data) {
+        Ok(()) => println!("Payload CRC: OK"),
+        Err(err) => println!("Payload CRC: {err}"),
+    }
+
+    let image_length = image_length(&data)?;
+    let Resolution { width, height } = image_resolution(&data)?;
+
+    let palette_size = palette_size(&data)?;
+    let uncompressed_length = if palette_size == 0 {
+        width * height * 6
+    } else {
+        palette_size * 3 + width * height * 4
+    };
+
+    if image_length == uncompressed_length {
+        println!("Images: {width}x{height}, {image_length:#x} bytes");
+    } else {
+        println!("Images: {width}x{height}, {image_length:#x} bytes compressed");
+    }
+
+    // The offset is reserved before version 8
+    let offset = read_u32(&data, IMAGE_OFFSET_OFFSET)? & 0xFFFFF;
+
+    if data[VERSION_OFFSET] >= 8 && offset != 0 {
+        println!(
+            "Cropped to ({}, {}) on the canvas",
+            offset & 0x3FF,
+            offset >> 10
+        );
+    }
+
+    if palette_size != 0 {
+        println!("Background palette: {palette_size} colors");
+    }
+
+    println!(
+        "Mask entries: {}",
+        read_u32(&data, MASK_ENTRY_COUNT_OFFSET)?
+    );
+
+    if mask_entry_format(&data)? == MaskEntryFormat::Wide {
+        println!("Mask entry format: Wide, with 16 bit ids");
+    }
+
+    for i in 0..data[RESOLUTION_COUNT_OFFSET] as usize {
+        let entry = read_u32(&data, RESOLUTION_TABLE_OFFSET + i * 3)? & 0xFFFFF;
+        println!("Additional resolution: {}x{}", entry & 0x3FF, entry >> 10);
+    }
+
+    for chunk in extension_chunks(&data)? {
+        println!(
+            "Extension chunk {}: {:#x} bytes",
+            String::from_utf8_lossy(&chunk.tag),
+            chunk.data.len()
+        );
+    }
+
+    fs::create_dir_all(output_dir)
+        .map_err(|err| format!("Could not create output directory {output_dir:?}: {err}"))?;
+
+    // The background is the low byte of each interleaved pair
+    let image_block = full_color_images(&data)?;
+
+    for (name, byte) in [("background.png", 0), ("mask.png", 1)] {
+        let mut pixmap = Pixmap::new(width as u32, height as u32).unwrap();
+
+        for (pixel, source) in pixmap
+            .data_mut()
+            .chunks_exact_mut(4)
+            .zip(image_block.chunks_exact(6))
+        {
+            pixel.copy_from_slice(&[source[byte], source[byte + 2], source[byte + 4], 0xFF]);
+        }
+
+        let image_path = output_dir.join(name);
+        pixmap
+            .save_png(&image_path)
+            .map_err(|err| format!("Could not write {image_path:?}: {err}"))?;
+    }
+
+    let mut files = vec![("rom.bin", rom)];
+
+    if !melody.is_empty() {
+        files.push(("melody.bin", melody));
+    }
+
+    for (name, contents) in files {
+        let file_path = output_dir.join(name);
+        fs::write(&file_path, contents)
+            .map_err(|err| format!("Could not write {file_path:?}: {err}"))?;
+    }
+
+    println!("Extracted to {}", output_dir.display());
+
+    Ok(())
+}
+
+fn describe_action(action: Option<&NamedAction>) -> String {
+    match action {
+        Some(action) if action.active_low => format!("{:?} (active low)", action.action),
+        Some(action) => format!("{:?}", action.action),
+        None => "-".to_string(),
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| format!("Could not read u32 at {offset:#x}"))
+}
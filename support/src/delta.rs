@@ -0,0 +1,129 @@
+use std::{
+    collections::HashSet,
+    ffi::{OsStr, OsString},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::encode_format::{
+    FULL_GIT_SHA_LENGTH, FULL_GIT_SHA_OFFSET, GIT_SHA_LENGTH, GIT_SHA_OFFSET,
+};
+
+pub const UNCHANGED_LIST_NAME: &str = "delta_unchanged.txt";
+pub const REMOVED_LIST_NAME: &str = "delta_removed.txt";
+
+///
+/// Compares freshly built files against a previously distributed output set, so only the files that changed need to
+/// be redistributed
+///
+pub struct Delta {
+    reference_dir: PathBuf,
+    extension: String,
+    produced: HashSet<OsString>,
+    pub unchanged: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl Delta {
+    pub fn new(reference_dir: PathBuf, extension: &str) -> Self {
+        Delta {
+            reference_dir,
+            extension: extension.to_string(),
+            produced: HashSet::new(),
+            unchanged: vec![],
+            removed: vec![],
+        }
+    }
+
+    ///
+    /// Compares a built file with the file of the same name in the reference set. Unchanged files are removed from
+    /// the output. Returns `true` if the file is new or changed
+    ///
+    #[allow(clippy::diverging_sub_expression)]
+    pub fn compare(&mut self, path: &Path) -> Result<bool, String> {
+        guard!(let Some(file_name) = path.file_name() else {
+            return Err(format!("Output path {path:?} has no file name"));
+        });
+
+        self.produced.insert(file_name.to_os_string());
+
+        guard!(let Ok(reference) = fs::read(self.reference_dir.join(file_name)) else {
+            // New file
+            return Ok(true);
+        });
+
+        let built = fs::read(path).map_err(|err| format!("Could not reread {path:?}: {err}"))?;
+
+        if !contents_match(&built, &reference) {
+            return Ok(true);
+        }
+
+        fs::remove_file(path)
+            .map_err(|err| format!("Could not remove unchanged output {path:?}: {err}"))?;
+
+        self.unchanged
+            .push(file_name.to_string_lossy().into_owned());
+
+        Ok(false)
+    }
+
+    ///
+    /// Finds the reference files that weren't rebuilt, and writes the lists of unchanged and removed files to
+    /// `output_dir`
+    ///
+    pub fn finish(&mut self, output_dir: &Path) -> Result<(), String> {
+        let entries = fs::read_dir(&self.reference_dir).map_err(|err| {
+            format!(
+                "Could not read delta reference directory {:?}: {err}",
+                self.reference_dir
+            )
+        })?;
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            if path.extension() != Some(OsStr::new(&self.extension)) {
+                continue;
+            }
+
+            if !self.produced.contains(&entry.file_name()) {
+                self.removed
+                    .push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+
+        self.unchanged.sort();
+        self.removed.sort();
+
+        write_list(&output_dir.join(UNCHANGED_LIST_NAME), &self.unchanged)?;
+        write_list(&output_dir.join(REMOVED_LIST_NAME), &self.removed)
+    }
+}
+
+/// Compares two built files, ignoring the generator tool commits, which change on every tool build
+fn contents_match(built: &[u8], reference: &[u8]) -> bool {
+    if built.len() != reference.len() {
+        return false;
+    }
+
+    let sha_range = GIT_SHA_OFFSET..GIT_SHA_OFFSET + GIT_SHA_LENGTH;
+    let full_sha_range = FULL_GIT_SHA_OFFSET..FULL_GIT_SHA_OFFSET + FULL_GIT_SHA_LENGTH;
+
+    built
+        .iter()
+        .zip(reference)
+        .enumerate()
+        .all(|(i, (built, reference))| {
+            built == reference || sha_range.contains(&i) || full_sha_range.contains(&i)
+        })
+}
+
+fn write_list(path: &Path, names: &[String]) -> Result<(), String> {
+    let mut contents = names.join("\n");
+
+    if !contents.is_empty() {
+        contents.push('\n');
+    }
+
+    fs::write(path, contents).map_err(|err| format!("Could not write {path:?}: {err}"))
+}
@@ -1,7 +1,12 @@
 use std::{
+    collections::HashMap,
+    env, fmt,
     fs::{self, File},
-    io::{self, Read, Seek},
+    io::{self, Write},
+    ops::AddAssign,
     path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use bitvec::{
@@ -9,276 +14,1821 @@ use bitvec::{
     prelude::{bitvec, Lsb0},
 };
 
+use color_quant::NeuQuant;
+use colored::Colorize;
+use flate2::{write::ZlibEncoder, Compression};
+use rayon::prelude::*;
+use resvg::tiny_skia::Pixmap;
+use serde::Serialize;
 use sha1::{Digest, Sha1};
 
 use crate::{
-    manifest::{Action, CPUType, NamedAction, PlatformSpecification, Port, Screen},
-    HEIGHT, WIDTH,
+    assets::{CACHE_MARKER, HASH_CACHE},
+    decode,
+    error::BuildError,
+    manifest::{
+        expected_rom_len, AclMode, Action, CPUType, NamedAction, PlatformSpecification, Port,
+        Region, Screen, Size,
+    },
+    name_template::NameTemplate,
+    render::{InputGeometry, RenderedData, Resolution},
+    task_log::logln,
 };
 
+/// The version of the format written by this tool. Version 2 added the payload CRC, version 3 the game's name,
+/// company, and year, version 4 image compression, version 5 the image resolution, version 6 the signature,
+/// version 7 the second screen's size, version 8 the image offset, version 9 the mask entry format, version 10 the
+/// background palette, version 11 the input flags, and version 12 the CPU config
+pub const FORMAT_VERSION: u8 = 12;
+
+/// The signature every file starts with, so the format can be identified. Unchanged between versions
+pub const MAGIC: &[u8; 4] = b"GNW1";
+
+/// Offset of the format version, which follows the signature
+pub const VERSION_OFFSET: usize = 0x4;
+
+/// Length of the config, which is followed by the payload
+pub const CONFIG_LENGTH: usize = 0x100;
+
+/// Offset of the MPU version, which selects the CPU the core emulates
+pub const MPU_OFFSET: usize = 0x5;
+
+/// Offset of the screen configuration: single, dual vertical, or dual horizontal
+pub const SCREEN_OFFSET: usize = 0x6;
+
+/// Offset of the screen width and height, packed as 10 bits each
+pub const SCREEN_SIZE_OFFSET: usize = 0x7;
+
+/// Offset of the input mappings of the standard 8 S ports, 4 bytes each
+pub const S_PORTS_OFFSET: usize = 0xC;
+
+/// Offset of the input mapping of the B port, followed by the BA port, the ACL port, and the last grounded S port
+pub const B_PORT_OFFSET: usize = 0x2C;
+
+/// Offset of the input flags, which follow the input mapping of the grounded S port
+pub const INPUT_FLAGS_OFFSET: usize = 0x30;
+
+/// Set in the input flags if ACL latches the reset on each press, rather than only resetting while held
+pub const INPUT_FLAG_ACL_LATCHED: u8 = 0x1;
+
+/// Offset of the CPU config, settings for quirks of the MPU that its version alone doesn't describe. All zero for CPUs
+/// without any
+pub const CPU_CONFIG_OFFSET: usize = 0x31;
+pub const CPU_CONFIG_LENGTH: usize = 3;
+
+/// Set in the CPU flags if the MPU is a clone of the SM5A, and should be reset and strobe its inputs like one
+pub const CPU_FLAG_SM5A_CORE: u8 = 0x1;
+
+/// Set in the CPU flags if the MPU has no melody generator, and sounds by driving the piezo from its R port
+pub const CPU_FLAG_R_PORT_SOUND: u8 = 0x2;
+
+/// File offset of the ROM data, following the config, images, and mask map. Only fixed for uncompressed images at the
+/// standard resolution. See `decode::rom_offset`
+pub const ROM_OFFSET: usize = 0x325240;
+
+/// Offset of the little endian u32 file offset of the first extension chunk. 0 if there are no chunks
+pub const EXTENSION_OFFSET: usize = 0x34;
+
+/// Offset of the total number of S ports, if the device has more than the standard 8. 0 otherwise
+pub const S_PORT_COUNT_OFFSET: usize = 0xA;
+
+/// Offset of the input mappings of S ports 8 and beyond, 4 bytes each
+pub const EXTENDED_S_PORTS_OFFSET: usize = 0x38;
+
+/// Offset of the little endian u32 length of the ROM data
+pub const ROM_LENGTH_OFFSET: usize = 0x58;
+
+/// Length of the header preceding the melody ROM, which directly follows the ROM data
+pub const MELODY_HEADER_LENGTH: usize = 5;
+
+/// Offset of the little endian CRC32 of just the ROM data, so the core can verify the ROM independently of the images
+pub const ROM_CRC_OFFSET: usize = 0x5C;
+
+/// Offset of the territory the game was released in. 0 if unknown
+pub const REGION_OFFSET: usize = 0x60;
+
+/// Offset of the number of additional resolutions encoded in the `MRES` chunk
+pub const RESOLUTION_COUNT_OFFSET: usize = 0x61;
+
+/// Offset of the table of additional resolutions, one 3 byte width/height entry each
+pub const RESOLUTION_TABLE_OFFSET: usize = 0x62;
+
+pub const MAX_ADDITIONAL_RESOLUTIONS: usize = 4;
+
+/// Offset of the little endian CRC32 of everything following the config, so truncated or corrupted files can be
+/// detected
+pub const PAYLOAD_CRC_OFFSET: usize = 0x6E;
+
+/// Offset of the little endian u32 number of mask entries, including any in the `MOVF` chunk
+pub const MASK_ENTRY_COUNT_OFFSET: usize = 0x72;
+
+/// Offset of the game's name, as null padded UTF-8
+pub const NAME_OFFSET: usize = 0x76;
+pub const NAME_LENGTH: usize = 48;
+
+/// Offset of the game's company, as null padded UTF-8
+pub const COMPANY_OFFSET: usize = 0xA6;
+pub const COMPANY_LENGTH: usize = 24;
+
+/// Offset of the little endian u16 release year. 0 if unknown
+pub const YEAR_OFFSET: usize = 0xBE;
+
+/// Offset of the little endian u32 length of the stored image block, which is smaller if it's compressed
+pub const IMAGE_LENGTH_OFFSET: usize = 0xC0;
+
+/// Offset of how the image block is compressed. 0 if it isn't
+pub const COMPRESSION_OFFSET: usize = 0xC4;
+
+/// Compression type of an image block stored as a zlib stream
+pub const COMPRESSION_ZLIB: u8 = 1;
+
+/// Offset of the width and height of the primary images, packed as 10 bits each
+pub const IMAGE_RESOLUTION_OFFSET: usize = 0xC5;
+
+pub const STANDARD_S_PORTS: usize = 8;
+pub const MAX_S_PORTS: usize = 16;
+
+/// Offset of the full ASCII commit SHA of the generator tool that built the file, with `--embed-full-sha`. Reserved
+/// otherwise
+pub const FULL_GIT_SHA_OFFSET: usize = 0xC8;
+pub const FULL_GIT_SHA_LENGTH: usize = 40;
+
+/// Offset of the width and height of the bottom or right screen of dual screen devices, packed like the screen size.
+/// Reserved for single screen devices
+pub const SECOND_SCREEN_SIZE_OFFSET: usize = 0xF0;
+
+/// Offset of the position of the primary images on the canvas, packed as 10 bits each. Zero unless cropped
+pub const IMAGE_OFFSET_OFFSET: usize = 0xF3;
+
+/// Offset of the format of the mask entries. See `MaskEntryFormat`
+pub const MASK_ENTRY_FORMAT_OFFSET: usize = 0xF6;
+
+/// Offset of the number of colors in the background palette, as a u16. Zero for full color backgrounds
+pub const PALETTE_SIZE_OFFSET: usize = 0xF7;
+
+/// The most colors a background palette can have, so each pixel's index fits in a byte
+pub const MAX_PALETTE_SIZE: usize = 256;
+
+/// Offset of the ASCII short commit SHA of the generator tool that built the file
+pub const GIT_SHA_OFFSET: usize = 0xF9;
+pub const GIT_SHA_LENGTH: usize = 7;
+
+/// Extension chunk containing the canvas position of each physical input
+pub const INPUT_GEOMETRY_TAG: &[u8; 4] = b"INPT";
+
+/// Extension chunk containing the image and mask map of each additional resolution, in resolution table order
+pub const MULTI_RESOLUTION_TAG: &[u8; 4] = b"MRES";
+
+/// Extension chunk containing the mask entries that don't fit in the mask config
+pub const MASK_OVERFLOW_TAG: &[u8; 4] = b"MOVF";
+
+/// Tag of the extension chunk containing JSON build metadata
+pub const METADATA_TAG: &[u8; 4] = b"META";
+
+/// Tag of the extension chunk holding the fingerprint of the options the file was built with
+pub const BUILD_OPTIONS_TAG: &[u8; 4] = b"OPTS";
+
+/// Options controlling how a game is encoded
+#[derive(Clone, Debug)]
+pub struct EncodeOptions {
+    /// The byte written to every reserved region of the config
+    pub reserved_fill: u8,
+    /// Strip trailing padding from the ROM
+    pub trim_rom: bool,
+    /// Accept ROMs found by file name that don't match the manifest hash
+    pub skip_hash_check: bool,
+    /// The output file extension, without a leading dot
+    pub output_ext: String,
+    /// Fail instead of warning when the manifest maps an action the core can't represent
+    pub strict: bool,
+    /// Embed JSON build metadata as an extension chunk
+    pub embed_metadata: bool,
+    /// Write the generator's full commit SHA into the config, alongside the short SHA
+    pub embed_full_sha: bool,
+    /// Build everything, but don't write the file
+    pub dry_run: bool,
+    /// Compress the image block, for cores that can inflate it
+    pub compress: bool,
+    /// Write the file to stdout instead of the output directory
+    pub to_stdout: bool,
+    /// Reduce the primary background to a palette of at most this many colors
+    pub palette_colors: Option<usize>,
+    /// Use the first screen's size for dual screen devices whose screens differ in size, instead of failing
+    pub allow_screen_mismatch: bool,
+    /// Store mask entries that don't fit in the mask config in the `MOVF` chunk, for cores that read it, instead of
+    /// failing
+    pub allow_mask_overflow: bool,
+    /// Write the ROM into a subdirectory of the output directory named after the company
+    pub by_company: bool,
+    /// Overrides the file name of the ROM, without extension
+    pub name_template: Option<NameTemplate>,
+    /// A fingerprint of the options the game is built with, stored so a later run with different options rebuilds it
+    pub build_fingerprint: Option<u32>,
+}
+
+/// How many bytes of an encoded file each part takes, for `--stats`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EncodeStats {
+    pub header: usize,
+    /// The stored image block, which is smaller if it's compressed
+    pub images: usize,
+    /// The mask config, including its padding and any entries moved to the `MOVF` chunk
+    pub mask_map: usize,
+    pub mask_entries: usize,
+    pub rom: usize,
+    /// The melody ROM and its header
+    pub melody: usize,
+    /// Every extension chunk other than `MOVF`, including the additional resolutions
+    pub extensions: usize,
+    pub total: usize,
+}
+
+impl AddAssign for EncodeStats {
+    fn add_assign(&mut self, other: Self) {
+        self.header += other.header;
+        self.images += other.images;
+        self.mask_map += other.mask_map;
+        self.mask_entries += other.mask_entries;
+        self.rom += other.rom;
+        self.melody += other.melody;
+        self.extensions += other.extensions;
+        self.total += other.total;
+    }
+}
+
+impl fmt::Display for EncodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let part = |bytes: usize| {
+            let percent = if self.total == 0 {
+                0.0
+            } else {
+                bytes as f64 * 100.0 / self.total as f64
+            };
+
+            format!("{bytes:>10} bytes ({percent:.1}%)")
+        };
+
+        writeln!(f, "Header:     {}", part(self.header))?;
+        writeln!(f, "Images:     {}", part(self.images))?;
+        writeln!(
+            f,
+            "Mask map:   {}, {} entries",
+            part(self.mask_map),
+            self.mask_entries
+        )?;
+        writeln!(f, "ROM:        {}", part(self.rom))?;
+        writeln!(f, "Melody:     {}", part(self.melody))?;
+        writeln!(f, "Extensions: {}", part(self.extensions))?;
+        write!(f, "Total:      {:>10} bytes", self.total)
+    }
+}
+
+/// Provenance embedded in the file with `--embed-metadata`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BuildMetadata<'a> {
+    name: &'a str,
+    company: &'a str,
+    year: &'a str,
+    cpu: &'a CPUType,
+    screen: &'a Screen,
+    tool_version: &'a str,
+    git_sha: &'a str,
+    /// Seconds since the Unix epoch, or `SOURCE_DATE_EPOCH` if set for reproducible builds
+    build_time: u64,
+}
+
+///
+/// Encodes `rendered` as the game's primary images, and each of `additional` as an alternate resolution the core can
+/// choose instead
+///
 pub fn encode(
-    background_bytes: &[u8],
-    mask_bytes: &[u8],
-    pixels_to_mask_id: &[Option<u16>],
+    platform_name: &str,
+    rendered: &RenderedData,
+    additional: &[RenderedData],
     platform: &PlatformSpecification,
     asset_dir: &Path,
     output_dir: &Path,
-) -> Result<PathBuf, String> {
+    options: &EncodeOptions,
+) -> Result<(PathBuf, EncodeStats), BuildError> {
+    let RenderedData {
+        background_bytes,
+        mask_bytes,
+        pixels_to_mask_id,
+        input_geometry,
+        resolution,
+        offset,
+        ..
+    } = rendered;
+
+    for warning in validate(platform, options).map_err(BuildError::InvalidManifest)? {
+        logln!("{}", format!("WARNING: {warning}").yellow());
+    }
+
     // Build config
-    let mut config = build_config(platform)?;
+    let mut config = build_config(platform, options)?;
+
+    // Covers devices with extended S ports, whose mapping is split across two regions of the config
+    debug_assert!(
+        check_input_round_trip(platform, &config).is_ok(),
+        "Input mapping does not round trip"
+    );
+
+    let Resolution { width, height } = *resolution;
+
+    if width >= MAX_MASK_COORDINATE || height >= MAX_MASK_COORDINATE {
+        return Err(BuildError::InvalidLayout(format!(
+            "Resolution {width}x{height} overflows its 10 bit fields"
+        )));
+    }
+
+    config[IMAGE_RESOLUTION_OFFSET..IMAGE_RESOLUTION_OFFSET + 3]
+        .copy_from_slice(&pack_resolution(*resolution));
+    config[IMAGE_OFFSET_OFFSET..IMAGE_OFFSET_OFFSET + 3].copy_from_slice(&pack_offset(*offset));
+
+    write_resolution_table(&mut config, additional).map_err(BuildError::InvalidLayout)?;
 
     // Build image
-    let background_iter = background_bytes.into_iter();
-    let mask_iter = mask_bytes.into_iter();
-
-    let mut count = 0;
-
-    let mut image_block = background_iter
-        .zip(mask_iter)
-        .filter(|_| {
-            let prev_count = count;
-            // Drop every 4th (alpha) byte
-            if count < 3 {
-                count += 1;
-            } else {
-                count = 0;
-            }
-            prev_count != 3
-        })
-        // Background is low byte
-        .flat_map(|(background_byte, mask_byte)| [*background_byte, *mask_byte])
-        .collect::<Vec<u8>>();
+    let image_block = match options.palette_colors {
+        Some(colors) => {
+            let (block, palette_size) =
+                build_paletted_image_block(background_bytes, mask_bytes, colors);
+
+            logln!("Reduced background to {palette_size} colors");
+
+            config[PALETTE_SIZE_OFFSET..PALETTE_SIZE_OFFSET + 2]
+                .copy_from_slice(&(palette_size as u16).to_le_bytes());
+
+            block
+        }
+        None => build_image_block(background_bytes, mask_bytes),
+    };
+
+    let mut stored_block = if options.compress {
+        let compressed = compress(&image_block)?;
+
+        logln!(
+            "Compressed images from {:#x} to {:#x} bytes",
+            image_block.len(),
+            compressed.len()
+        );
+
+        config[COMPRESSION_OFFSET] = COMPRESSION_ZLIB;
+
+        compressed
+    } else {
+        image_block.clone()
+    };
 
-    config.append(&mut image_block);
+    let mut stats = EncodeStats {
+        header: CONFIG_LENGTH,
+        images: stored_block.len(),
+        ..Default::default()
+    };
+
+    let image_length = stored_block.len() as u32;
+    config[IMAGE_LENGTH_OFFSET..IMAGE_LENGTH_OFFSET + 4]
+        .copy_from_slice(&image_length.to_le_bytes());
+
+    config.append(&mut stored_block);
+
+    debug_assert!(
+        decode::image_block(&config).is_ok_and(|decoded| decoded == image_block),
+        "Image block does not round trip"
+    );
+
+    // Build mask config. Every resolution shares the format, so it's chosen by the largest id of any of them
+    let mask_format = [rendered]
+        .into_iter()
+        .chain(additional)
+        .map(|rendered| MaskEntryFormat::for_ids(&rendered.pixels_to_mask_id))
+        .max()
+        .unwrap_or(MaskEntryFormat::Compact);
+
+    if mask_format == MaskEntryFormat::Wide {
+        logln!(
+            "{}",
+            "WARNING: Mask ids don't fit in 10 bits, so mask entries use the wide format with 16 bit ids. The cores in \
+             this repo can't read it yet"
+                .yellow()
+        );
+    }
+
+    config[MASK_ENTRY_FORMAT_OFFSET] = mask_format as u8;
+
+    let entry_length = mask_format.entry_length();
+    let mut mask_block = build_mask_map(platform_name, pixels_to_mask_id, *resolution, mask_format)
+        .map_err(BuildError::MaskOverflow)?;
+
+    stats.mask_entries = mask_block.len() / entry_length;
+    stats.mask_map = mask_block.len().max(mask_map_length(*resolution));
+
+    let mask_entry_count = stats.mask_entries as u32;
+    config[MASK_ENTRY_COUNT_OFFSET..MASK_ENTRY_COUNT_OFFSET + 4]
+        .copy_from_slice(&mask_entry_count.to_le_bytes());
+
+    // The mask config has a fixed size, so the ROM stays in place. Anything more is moved to a chunk, split between
+    // entries
+    let mask_config_length = mask_map_length(*resolution);
+    let mask_config_entries_length = mask_config_length / entry_length * entry_length;
+
+    let mask_overflow = if mask_block.len() > mask_config_entries_length {
+        let overflow = mask_block.split_off(mask_config_entries_length);
+        let overflow_entries = overflow.len() / entry_length;
+
+        // The cores in this repo never read the chunk, so these segments wouldn't be displayed
+        if !options.allow_mask_overflow {
+            return Err(BuildError::MaskOverflow(format!(
+                "{overflow_entries} mask entries don't fit in the mask config. Pass --allow-mask-overflow to store \
+                 them in an extension chunk for cores that read it"
+            )));
+        }
+
+        logln!(
+            "{}",
+            format!(
+                "WARNING: {overflow_entries} mask entries don't fit in the mask config, and are stored in an \
+                 extension chunk that the cores in this repo don't read"
+            )
+            .yellow()
+        );
+
+        Some(overflow)
+    } else {
+        None
+    };
 
-    // Build mask config
-    let mut mask_block = build_mask_map(pixels_to_mask_id)?;
+    mask_block.resize(mask_config_length, 0);
 
     config.append(&mut mask_block);
 
     // Add ROM
-    // TODO: Add melody ROM
+    let melody_data = load_melody(platform, asset_dir)?;
+    validate_melody_banks(platform, &melody_data).map_err(BuildError::InvalidManifest)?;
+
     let rom_path = asset_dir.join(&platform.rom.rom);
 
     let mut rom_data = match fs::read(&rom_path) {
-        Ok(data) => Ok(data),
+        Ok(data) => {
+            let hash = hex::encode(Sha1::digest(&data));
+
+            if !options.skip_hash_check && hash != platform.rom.rom_hash.to_lowercase() {
+                return Err(BuildError::HashMismatch {
+                    name: platform.rom.rom.clone(),
+                    actual: hash,
+                    expected: platform.rom.rom_hash.clone(),
+                });
+            }
+
+            Ok(data)
+        }
         Err(_) => match find_rom_by_hash(&platform.rom.rom_hash, asset_dir) {
             Ok(data) => Ok(data),
-            Err(err) => Err(format!("{err}\nCould not open ROM {rom_path:?}")),
+            Err(BuildError::AssetMissing(err)) => Err(BuildError::AssetMissing(format!(
+                "{err}\nCould not open ROM {rom_path:?}"
+            ))),
+            Err(err) => Err(err),
         },
     }?;
 
+    check_rom_length(platform, &rom_data).map_err(BuildError::RomSizeMismatch)?;
+
+    if options.trim_rom {
+        trim_rom(&mut rom_data, platform);
+    }
+
+    let rom_length = rom_data.len() as u32;
+    config[ROM_LENGTH_OFFSET..ROM_LENGTH_OFFSET + 4].copy_from_slice(&rom_length.to_le_bytes());
+
+    let rom_crc = crc32fast::hash(&rom_data);
+    config[ROM_CRC_OFFSET..ROM_CRC_OFFSET + 4].copy_from_slice(&rom_crc.to_le_bytes());
+
+    debug_assert_eq!(Ok(config.len()), decode::rom_offset(&config));
+    // Full color, uncompressed files at the standard resolution keep the ROM where the cores expect it
+    debug_assert!(
+        options.compress
+            || options.palette_colors.is_some()
+            || *resolution != Resolution::STANDARD
+            || config.len() == ROM_OFFSET
+    );
+
+    stats.rom = rom_data.len();
     config.append(&mut rom_data);
 
-    let mut game_name = platform.metadata.name.clone();
+    // Melody ROM, directly after the ROM. Devices without one get an empty melody
+    config.extend_from_slice(&(melody_data.len() as u32).to_le_bytes());
+    config.push(platform.rom.melody_banks.map_or(0, |banks| banks.count));
+    config.extend_from_slice(&melody_data);
+    stats.melody = MELODY_HEADER_LENGTH + melody_data.len();
+
+    debug_assert!(
+        decode::melody(&config).is_ok_and(|melody| melody == melody_data),
+        "Melody ROM does not round trip"
+    );
+
+    // Add extension chunks
+    let mut chunks: Vec<(&[u8; 4], Vec<u8>)> = vec![];
+
+    if let Some(input_geometry) = build_input_geometry(platform, input_geometry, *resolution)
+        .map_err(BuildError::InvalidLayout)?
+    {
+        chunks.push((INPUT_GEOMETRY_TAG, input_geometry));
+    }
+
+    if let Some(mask_overflow) = mask_overflow {
+        chunks.push((MASK_OVERFLOW_TAG, mask_overflow));
+    }
+
+    if !additional.is_empty() {
+        let mut data = vec![];
+
+        for rendered in additional {
+            data.append(&mut build_image_block(
+                &rendered.background_bytes,
+                &rendered.mask_bytes,
+            ));
+
+            let mut mask_block = build_mask_map(
+                platform_name,
+                &rendered.pixels_to_mask_id,
+                rendered.resolution,
+                mask_format,
+            )
+            .map_err(BuildError::MaskOverflow)?;
+
+            data.extend_from_slice(&((mask_block.len() / entry_length) as u32).to_le_bytes());
+            data.append(&mut mask_block);
+        }
+
+        chunks.push((MULTI_RESOLUTION_TAG, data));
+    }
+
+    if options.embed_metadata {
+        chunks.push((
+            METADATA_TAG,
+            build_metadata(platform).map_err(BuildError::InvalidManifest)?,
+        ));
+    }
+
+    if let Some(fingerprint) = options.build_fingerprint {
+        chunks.push((BUILD_OPTIONS_TAG, fingerprint.to_le_bytes().to_vec()));
+    }
+
+    if !chunks.is_empty() {
+        let offset = config.len() as u32;
+        config[EXTENSION_OFFSET..EXTENSION_OFFSET + 4].copy_from_slice(&offset.to_le_bytes());
+
+        for (tag, mut data) in chunks {
+            config.extend_from_slice(tag);
+            config.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            config.append(&mut data);
+        }
+    }
+
+    stats.total = config.len();
+    stats.extensions =
+        stats.total - stats.header - stats.images - stats.mask_map - stats.rom - stats.melody;
+
+    // Everything is in place, so the payload can be checksummed
+    let payload_crc = crc32fast::hash(&config[CONFIG_LENGTH..]);
+    config[PAYLOAD_CRC_OFFSET..PAYLOAD_CRC_OFFSET + 4].copy_from_slice(&payload_crc.to_le_bytes());
+
+    debug_assert!(
+        decode::verify_payload_crc(&config).is_ok(),
+        "Payload CRC does not round trip"
+    );
+
+    let output_path = output_path(platform, output_dir, options);
 
-    if game_name.to_lowercase().starts_with("game & watch:") {
-        game_name = game_name.chars().skip("Game & Watch:".len()).collect();
+    if options.dry_run {
+        logln!(
+            "Dry run: Not writing {} bytes to {}",
+            config.len(),
+            output_path.display()
+        );
+
+        return Ok((output_path, stats));
     }
 
-    game_name = game_name.replace(":", " -");
-    let game_name = game_name.trim();
+    if options.to_stdout {
+        let mut stdout = io::stdout().lock();
+
+        stdout
+            .write_all(&config)
+            .and_then(|_| stdout.flush())
+            .map_err(|err| BuildError::io("Could not write to stdout", err))?;
 
-    let output_path: PathBuf = output_dir.join(format!("{game_name}.gnw"));
-    fs::write(&output_path, config).unwrap();
+        return Ok((output_dir.to_path_buf(), stats));
+    }
 
-    Ok(output_path)
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent).map_err(|err| {
+            BuildError::io(format!("Could not create output directory {parent:?}"), err)
+        })?;
+    }
+
+    write_atomically(&output_path, &config)?;
+
+    Ok((output_path, stats))
 }
 
-fn find_rom_by_hash(target_hash: &String, asset_dir: &Path) -> Result<Vec<u8>, String> {
-    for entry in fs::read_dir(asset_dir).expect("Could not open temp directory") {
-        if let Ok(entry) = entry {
-            let mut file = match File::open(entry.path()) {
-                Ok(file) => file,
-                Err(_) => continue,
-            };
-            let mut hasher = Sha1::new();
-            let _ = match io::copy(&mut file, &mut hasher) {
-                Ok(_) => {}
-                Err(_) => continue,
-            };
-            let hash = hasher.finalize();
+///
+/// Writes to a temporary file beside `path` and renames it into place, so an interrupted or failed write never
+/// leaves a partial file for the core to load
+///
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), BuildError> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = fs::write(&temp_path, data)
+        .map_err(|err| BuildError::io(format!("Could not write {temp_path:?}"), err))
+        .and_then(|_| {
+            fs::rename(&temp_path, path).map_err(|err| {
+                BuildError::io(format!("Could not move {temp_path:?} to {path:?}"), err)
+            })
+        });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
 
-            let hash = hex::encode(hash);
+    result
+}
 
-            if &hash == target_hash {
-                let mut buffer = Vec::new();
-                if let Err(_) = file.seek(io::SeekFrom::Start(0)) {
-                    return Err("Could not reread from file after hash check".into());
-                }
-                if let Err(_) = file.read_to_end(&mut buffer) {
-                    return Err(format!("Could not open SHA matched ROM {:?}", entry.path()));
-                }
+///
+/// Checks the ROM is the size of the CPU's internal ROM, as a wrong or mismatched dump would hang the core. Dumps with
+/// trailing 0x00/0xFF padding past that size are allowed, as some sets pad their ROMs
+///
+#[allow(clippy::diverging_sub_expression)]
+fn check_rom_length(platform: &PlatformSpecification, rom_data: &[u8]) -> Result<(), String> {
+    let cpu = &platform.device.cpu;
+
+    guard!(let Some(expected) = expected_rom_len(cpu) else {
+        return Ok(());
+    });
+
+    let padded = rom_data.get(expected..).is_some_and(|padding| {
+        padding.iter().all(|byte| *byte == 0x00) || padding.iter().all(|byte| *byte == 0xFF)
+    });
+
+    if rom_data.len() != expected && !padded {
+        return Err(format!(
+            "ROM is {:#x} bytes, but the {cpu:?} has a {expected:#x} byte ROM",
+            rom_data.len()
+        ));
+    }
+
+    Ok(())
+}
+
+///
+/// Interleaves the background and mask images, dropping their alpha. Both are RGBA with 4 bytes per pixel, and each
+/// pixel is stored as `[background red, mask red, background green, mask green, background blue, mask blue]`
+///
+fn build_image_block(background: &Pixmap, mask: &Pixmap) -> Vec<u8> {
+    let background = background.data();
+    let mask = mask.data();
+
+    debug_assert!(background.len().is_multiple_of(4) && mask.len().is_multiple_of(4));
+    debug_assert_eq!(background.len(), mask.len());
+
+    let mut block = Vec::with_capacity(background.len() / 4 * 6);
+
+    for (background, mask) in background.chunks_exact(4).zip(mask.chunks_exact(4)) {
+        // Background is low byte
+        for channel in 0..3 {
+            block.push(background[channel]);
+            block.push(mask[channel]);
+        }
+    }
+
+    block
+}
+
+///
+/// Builds the image block with the background reduced to a palette of at most `colors` colors, returning it and the
+/// palette's size. Backgrounds that already have few enough colors keep them exactly, and others are quantized. The
+/// block starts with the palette as RGB triples, then each pixel is stored as
+/// `[background palette index, mask red, mask green, mask blue]`
+///
+fn build_paletted_image_block(
+    background: &Pixmap,
+    mask: &Pixmap,
+    colors: usize,
+) -> (Vec<u8>, usize) {
+    let colors = colors.min(MAX_PALETTE_SIZE);
+    let background = background.data();
+    let mask = mask.data();
+
+    debug_assert_eq!(background.len(), mask.len());
+
+    // Quantize the opaque colors, so alpha doesn't pull the palette around
+    let opaque = background
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 0xFF])
+        .collect::<Vec<_>>();
+
+    let mut exact: HashMap<[u8; 3], u8> = HashMap::new();
+
+    for pixel in opaque.chunks_exact(4) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+
+        if !exact.contains_key(&color) {
+            if exact.len() == colors {
+                exact.clear();
+                break;
+            }
+
+            exact.insert(color, exact.len() as u8);
+        }
+    }
+
+    let (palette, indices) = if exact.is_empty() {
+        let quantizer = NeuQuant::new(QUANTIZER_SAMPLE_FACTOR, colors, &opaque);
+        let indices = opaque
+            .chunks_exact(4)
+            .map(|pixel| quantizer.index_of(pixel) as u8)
+            .collect::<Vec<_>>();
+
+        (quantizer.color_map_rgb(), indices)
+    } else {
+        let mut palette = vec![0; exact.len() * 3];
+
+        for (color, index) in &exact {
+            let index = *index as usize * 3;
+            palette[index..index + 3].copy_from_slice(color);
+        }
+
+        let indices = opaque
+            .chunks_exact(4)
+            .map(|pixel| exact[&[pixel[0], pixel[1], pixel[2]]])
+            .collect::<Vec<_>>();
+
+        (palette, indices)
+    };
+
+    let palette_size = palette.len() / 3;
+    let mut block = palette;
+    block.reserve(indices.len() * 4);
+
+    for (index, mask) in indices.into_iter().zip(mask.chunks_exact(4)) {
+        block.push(index);
+        block.extend_from_slice(&mask[..3]);
+    }
+
+    (block, palette_size)
+}
+
+///
+/// Deflates the image block as a zlib stream
+///
+fn compress(image_block: &[u8]) -> Result<Vec<u8>, BuildError> {
+    let mut encoder = ZlibEncoder::new(vec![], Compression::best());
+
+    encoder
+        .write_all(image_block)
+        .and_then(|_| encoder.finish())
+        .map_err(|err| BuildError::io("Could not compress images", err))
+}
+
+///
+/// Fills in the count and sizes of the additional resolutions, whose data is stored in the `MRES` chunk
+///
+fn write_resolution_table(config: &mut [u8], additional: &[RenderedData]) -> Result<(), String> {
+    if additional.len() > MAX_ADDITIONAL_RESOLUTIONS {
+        return Err(format!(
+            "{} additional resolutions were requested, but at most {MAX_ADDITIONAL_RESOLUTIONS} are supported",
+            additional.len()
+        ));
+    }
+
+    config[RESOLUTION_COUNT_OFFSET] = additional.len() as u8;
+
+    for (i, rendered) in additional.iter().enumerate() {
+        let Resolution { width, height } = rendered.resolution;
+
+        if width >= MAX_MASK_COORDINATE || height >= MAX_MASK_COORDINATE {
+            return Err(format!(
+                "Resolution {width}x{height} overflows its 10 bit fields"
+            ));
+        }
+
+        let offset = RESOLUTION_TABLE_OFFSET + i * 3;
+        config[offset..offset + 3].copy_from_slice(&pack_resolution(rendered.resolution));
+    }
+
+    Ok(())
+}
+
+/// Packs a screen's width and height, rounded to whole pixels, as 10 bits each
+fn pack_screen_size(size: &Size) -> [u8; 3] {
+    pack_resolution(Resolution {
+        width: size.width.round() as usize,
+        height: size.height.round() as usize,
+    })
+}
+
+/// Packs the position of the images on the canvas as 10 bits each, like a resolution
+fn pack_offset((x, y): (usize, usize)) -> [u8; 3] {
+    pack_resolution(Resolution {
+        width: x,
+        height: y,
+    })
+}
+
+/// Packs a resolution's width and height as 10 bits each
+fn pack_resolution(resolution: Resolution) -> [u8; 3] {
+    let mut data: bitvec::vec::BitVec<u8> = bitvec![u8, Lsb0; 0; 3*8];
+    data[0..10].store(resolution.width as u16);
+    data[10..20].store(resolution.height as u16);
+
+    data.as_raw_slice().try_into().unwrap()
+}
+
+fn build_metadata(platform: &PlatformSpecification) -> Result<Vec<u8>, String> {
+    let build_time = match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| format!("SOURCE_DATE_EPOCH \"{epoch}\" is not a Unix timestamp"))?,
+        Err(_) => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs()),
+    };
+
+    let metadata = BuildMetadata {
+        name: &platform.metadata.name,
+        company: &platform.metadata.company,
+        year: &platform.metadata.year,
+        cpu: &platform.device.cpu,
+        screen: &platform.device.screen,
+        tool_version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("VERGEN_GIT_SHA"),
+        build_time,
+    };
+
+    serde_json::to_vec(&metadata)
+        .map_err(|err| format!("Could not serialize build metadata: {err}"))
+}
+
+///
+/// Reads the melody ROM named in the manifest, falling back to finding it by hash. Devices without a melody ROM get
+/// an empty one
+///
+#[allow(clippy::diverging_sub_expression)]
+fn load_melody(platform: &PlatformSpecification, asset_dir: &Path) -> Result<Vec<u8>, BuildError> {
+    guard!(let Some(melody) = &platform.rom.melody else {
+        return Ok(vec![]);
+    });
+
+    let melody_path = asset_dir.join(melody);
+
+    match fs::read(&melody_path) {
+        Ok(data) => Ok(data),
+        Err(err) => match &platform.rom.melody_hash {
+            Some(melody_hash) => match find_rom_by_hash(melody_hash, asset_dir) {
+                Err(BuildError::AssetMissing(err)) => Err(BuildError::AssetMissing(format!(
+                    "{err}\nCould not open melody ROM {melody_path:?}"
+                ))),
+                result => result,
+            },
+            None => Err(BuildError::AssetMissing(format!(
+                "Could not open melody ROM {melody_path:?}: {err}"
+            ))),
+        },
+    }
+}
+
+///
+/// Checks that the melody ROM is exactly the bank layout declared in the manifest, so the core doesn't map audio
+/// across the wrong boundaries
+///
+#[allow(clippy::diverging_sub_expression)]
+fn validate_melody_banks(
+    platform: &PlatformSpecification,
+    melody_data: &[u8],
+) -> Result<(), String> {
+    guard!(let Some(banks) = platform.rom.melody_banks else {
+        return Ok(());
+    });
+
+    guard!(let Some(melody) = &platform.rom.melody else {
+        return Err("Manifest declares melody banks, but no melody ROM".to_string());
+    });
+
+    if banks.count == 0 || banks.size == 0 {
+        return Err(format!(
+            "Melody banks must have a non-zero count and size, but {} banks of {} bytes were declared",
+            banks.count, banks.size
+        ));
+    }
+
+    let length = melody_data.len();
+
+    if !length.is_multiple_of(banks.count as usize) {
+        return Err(format!(
+            "Melody ROM {melody} is {length} bytes, which can't be evenly divided into {} banks",
+            banks.count
+        ));
+    }
+
+    if length / banks.count as usize != banks.size {
+        return Err(format!(
+            "Melody ROM {melody} is {length} bytes, but the manifest declares {} banks of {} bytes",
+            banks.count, banks.size
+        ));
+    }
+
+    Ok(())
+}
+
+///
+/// The path the ROM for `platform` is written to in `output_dir`
+///
+pub fn output_path(
+    platform: &PlatformSpecification,
+    output_dir: &Path,
+    options: &EncodeOptions,
+) -> PathBuf {
+    let game_name = match &options.name_template {
+        Some(template) => template.expand(platform),
+        None => platform.metadata.file_name(),
+    };
+
+    let output_dir = if options.by_company {
+        output_dir.join(platform.metadata.company_dir_name())
+    } else {
+        output_dir.to_path_buf()
+    };
+
+    output_dir.join(format!("{game_name}.{}", options.output_ext))
+}
+
+///
+/// The git SHA embedded in ROMs built by this version of the tool, or `None` if it is unknown
+///
+pub fn tool_sha() -> Option<[u8; GIT_SHA_LENGTH]> {
+    env!("VERGEN_GIT_SHA")
+        .as_bytes()
+        .get(..GIT_SHA_LENGTH)
+        .map(|sha| sha.try_into().unwrap())
+}
 
-                return Ok(buffer);
+///
+/// The full git SHA embedded with `--embed-full-sha`, or `None` if it is unknown
+///
+pub fn full_tool_sha() -> Option<[u8; FULL_GIT_SHA_LENGTH]> {
+    env!("VERGEN_GIT_SHA")
+        .as_bytes()
+        .get(..FULL_GIT_SHA_LENGTH)
+        .map(|sha| sha.try_into().unwrap())
+}
+
+///
+/// Whether the ROM at `path` was built by this version of the tool, with options matching `fingerprint`. Files that
+/// can't be read or aren't a recognized version of the format are never up to date
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn is_up_to_date(path: &Path, fingerprint: u32) -> bool {
+    guard!(let Some(sha) = tool_sha() else {
+        return false;
+    });
+
+    guard!(let Ok(data) = fs::read(path) else {
+        return false;
+    });
+
+    decode::rom_offset(&data).is_ok_and(|rom_offset| data.len() > rom_offset)
+        && decode::version(&data) == Ok(FORMAT_VERSION)
+        && data[GIT_SHA_OFFSET..GIT_SHA_OFFSET + GIT_SHA_LENGTH] == sha
+        && decode::verify_payload_crc(&data).is_ok()
+        && decode::build_fingerprint(&data) == Ok(Some(fingerprint))
+}
+
+///
+/// Reads back the ROM written to `path`, checking its signature and version, payload CRC, ROM CRC, and that its ROM
+/// data matches the manifest hash. ROMs built with `--trim-rom` or `--skip-hash-check` are only checked against their
+/// CRC, as they may intentionally differ from the manifest
+///
+pub fn verify(
+    path: &Path,
+    platform: &PlatformSpecification,
+    options: &EncodeOptions,
+) -> Result<(), String> {
+    let data = fs::read(path).map_err(|err| format!("Could not read back {path:?}: {err}"))?;
+
+    let version = decode::version(&data)?;
+
+    if version != FORMAT_VERSION {
+        return Err(format!(
+            "Wrote format version {version}, but expected {FORMAT_VERSION}"
+        ));
+    }
+
+    decode::verify_payload_crc(&data)?;
+
+    let rom = decode::rom(&data)?;
+    let stored_crc =
+        u32::from_le_bytes(data[ROM_CRC_OFFSET..ROM_CRC_OFFSET + 4].try_into().unwrap());
+    let rom_crc = crc32fast::hash(rom);
+
+    if rom_crc != stored_crc {
+        return Err(format!(
+            "ROM CRC {rom_crc:#010x} does not match the stored CRC {stored_crc:#010x}"
+        ));
+    }
+
+    let hash = hex::encode(Sha1::digest(rom));
+
+    // Trimmed ROMs don't record how much padding was removed, so they can't be hashed as they were dumped
+    if hash == platform.rom.rom_hash.to_lowercase() || options.skip_hash_check || options.trim_rom {
+        return Ok(());
+    }
+
+    Err(format!(
+        "ROM has SHA1 {hash}, but the manifest expects {}",
+        platform.rom.rom_hash
+    ))
+}
+
+///
+/// Reads the file in `asset_dir` whose SHA1 is `target_hash`, for ROMs that aren't where the manifest names them. Only
+/// regular files are hashed, skipping subdirectories and the files written by the generator itself. Files are hashed
+/// in parallel, stopping once a match is found, and the first match by file name is returned. Hashes are remembered in
+/// the directory's `HASH_CACHE`, so files that haven't changed since an earlier search aren't hashed again
+///
+pub fn find_rom_by_hash(target_hash: &str, asset_dir: &Path) -> Result<Vec<u8>, BuildError> {
+    // Manifests may give hashes in either case, but they're always computed as lowercase
+    let target_hash = target_hash.to_lowercase();
+
+    let entries = fs::read_dir(asset_dir).map_err(|err| {
+        BuildError::io(format!("Could not open asset directory {asset_dir:?}"), err)
+    })?;
+
+    let mut candidates = entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+        .filter(|entry| entry.file_name() != CACHE_MARKER && entry.file_name() != HASH_CACHE)
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+
+            Some(HashedFile {
+                name: entry.file_name().to_str()?.to_string(),
+                size: metadata.len(),
+                modified: modified_nanos(&metadata)?,
+                sha1: String::new(),
+            })
+        })
+        .collect::<Vec<HashedFile>>();
+
+    candidates.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let cache_path = asset_dir.join(HASH_CACHE);
+    let cached = read_hash_cache(&cache_path);
+    let hashed = Mutex::new(vec![]);
+
+    let found = candidates.par_iter().find_map_first(|candidate| {
+        let path = asset_dir.join(&candidate.name);
+
+        let sha1 = match cached.iter().find(|file| file.matches(candidate)) {
+            Some(file) => file.sha1.clone(),
+            None => {
+                let mut file = File::open(&path).ok()?;
+                let mut hasher = Sha1::new();
+                io::copy(&mut file, &mut hasher).ok()?;
+
+                let sha1 = hex::encode(hasher.finalize());
+
+                hashed.lock().unwrap().push(HashedFile {
+                    sha1: sha1.clone(),
+                    ..candidate.clone()
+                });
+
+                sha1
             }
+        };
+
+        if sha1 != target_hash {
+            return None;
+        }
+
+        // Reopened rather than rewound, so nothing depends on how much hashing consumed. A remembered hash is checked
+        // again, in case the file was replaced without its size or time changing
+        let data = fs::read(&path)
+            .map_err(|err| BuildError::io(format!("Could not open SHA matched ROM {path:?}"), err));
+
+        match data {
+            Ok(data) if hex::encode(Sha1::digest(&data)) != target_hash => None,
+            result => Some(result),
         }
+    });
+
+    let hashed = hashed.into_inner().unwrap();
+
+    if !hashed.is_empty() {
+        // Files that no longer exist are dropped. Failing to write the cache only means hashing again next time
+        let mut files = cached
+            .into_iter()
+            .filter(|file| candidates.iter().any(|candidate| file.matches(candidate)))
+            .chain(hashed)
+            .collect::<Vec<HashedFile>>();
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let _ = write_hash_cache(&cache_path, &files);
+    }
+
+    found.unwrap_or_else(|| {
+        Err(BuildError::AssetMissing(
+            "No SHA matched ROM found".to_string(),
+        ))
+    })
+}
+
+/// A file's SHA1, along with what identifies the version of the file it was computed from
+#[derive(Clone, Debug)]
+struct HashedFile {
+    name: String,
+    size: u64,
+    /// Modification time in nanoseconds since the Unix epoch
+    modified: u128,
+    sha1: String,
+}
+
+impl HashedFile {
+    /// Whether this is the same file as `other`, unchanged
+    fn matches(&self, other: &HashedFile) -> bool {
+        self.name == other.name && self.size == other.size && self.modified == other.modified
+    }
+}
+
+fn modified_nanos(metadata: &fs::Metadata) -> Option<u128> {
+    let modified = metadata.modified().ok()?;
+
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos())
+}
+
+///
+/// Reads the `sha1 size modified name` lines of a hash cache, skipping any that are malformed. Empty if there's no
+/// cache
+///
+#[allow(clippy::diverging_sub_expression)]
+fn read_hash_cache(path: &Path) -> Vec<HashedFile> {
+    guard!(let Ok(contents) = fs::read_to_string(path) else {
+        return vec![];
+    });
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+
+            Some(HashedFile {
+                sha1: fields.next()?.to_string(),
+                size: fields.next()?.parse().ok()?,
+                modified: fields.next()?.parse().ok()?,
+                name: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+fn write_hash_cache(path: &Path, files: &[HashedFile]) -> Result<(), String> {
+    let contents = files
+        .iter()
+        .map(|file| {
+            format!(
+                "{}\t{}\t{}\t{}\n",
+                file.sha1, file.size, file.modified, file.name
+            )
+        })
+        .collect::<String>();
+
+    write_atomically(path, contents.as_bytes()).map_err(|err| err.to_string())
+}
+
+///
+/// Strips trailing `0x00`/`0xFF` padding from a ROM, down to its declared size if there is one. Only ROMs matching the
+/// manifest hash are trimmed, so the hash always identifies the untrimmed dump
+///
+#[allow(clippy::diverging_sub_expression)]
+fn trim_rom(rom_data: &mut Vec<u8>, platform: &PlatformSpecification) {
+    if hex::encode(Sha1::digest(&rom_data)) != platform.rom.rom_hash.to_lowercase() {
+        logln!("Not trimming ROM, as it does not match the manifest hash");
+        return;
+    }
+
+    guard!(let Some(&pad) = rom_data.last() else {
+        return;
+    });
+
+    if pad != 0x00 && pad != 0xFF {
+        // No padding
+        return;
     }
 
-    Err(format!("No SHA matched ROM found"))
+    let content_length = rom_data
+        .iter()
+        .rposition(|byte| *byte != pad)
+        .map_or(0, |index| index + 1);
+
+    let trimmed_length = if let Some(rom_size) = platform.rom.rom_size {
+        if content_length > rom_size {
+            logln!(
+                "Not trimming ROM, as it has data at {content_length:#x}, past its declared size {rom_size:#x}"
+            );
+            return;
+        }
+
+        rom_size
+    } else {
+        content_length
+    };
+
+    if trimmed_length < rom_data.len() {
+        logln!(
+            "Trimmed ROM from {:#x} to {trimmed_length:#x} bytes",
+            rom_data.len()
+        );
+        rom_data.truncate(trimmed_length);
+    }
 }
 
-fn build_config(platform: &PlatformSpecification) -> Result<Vec<u8>, String> {
-    let mut config = Vec::<u8>::with_capacity(0x100);
+fn build_config(
+    platform: &PlatformSpecification,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, BuildError> {
+    let mut config = Vec::<u8>::with_capacity(CONFIG_LENGTH);
+    config.extend_from_slice(MAGIC);
+
     // Version
-    config.push(1);
+    debug_assert_eq!(config.len(), VERSION_OFFSET);
+    config.push(FORMAT_VERSION);
 
     // MPU version
-    let version = match platform.device.cpu {
-        CPUType::SM510 => 0,
-        CPUType::SM511 => 1,
-        CPUType::SM512 => 2,
-        CPUType::SM530 => 3,
-        CPUType::SM5a => 4,
-        CPUType::SM510Tiger => 5,
-        CPUType::SM511Tiger1Bit => 6,
-        CPUType::SM511Tiger2Bit => 7,
-        CPUType::KB1013VK12 => 8,
+    debug_assert_eq!(config.len(), MPU_OFFSET);
+    config.push(mpu_version(&platform.device.cpu));
+
+    // Screen configuration
+    let (screen, first, second) = match &platform.device.screen {
+        Screen::Single { width, height } => (
+            0,
+            Size {
+                width: *width,
+                height: *height,
+            },
+            None,
+        ),
+        Screen::DualVertical { top, bottom } => {
+            check_screen_match("Top", top, "bottom", bottom, options)
+                .map_err(BuildError::InvalidManifest)?;
+
+            (1, top.clone(), Some(bottom))
+        }
+        Screen::DualHorizontal { left, right } => {
+            check_screen_match("Left", left, "right", right, options)
+                .map_err(BuildError::InvalidManifest)?;
+
+            (2, left.clone(), Some(right))
+        }
+    };
+    debug_assert_eq!(config.len(), SCREEN_OFFSET);
+    config.push(screen);
+
+    debug_assert_eq!(config.len(), SCREEN_SIZE_OFFSET);
+    config.extend_from_slice(&pack_screen_size(&first));
+
+    // Input mapping
+    let s_port_count = platform.port_map.s_port_count(&platform.device.cpu);
+    let port_errors = platform.port_map.validate(&platform.device.cpu);
+
+    if !port_errors.is_empty() {
+        return Err(BuildError::InvalidManifest(port_errors.join("\n")));
+    }
+
+    for (port, action) in platform.port_map.mapped_actions() {
+        if let Action::Custom(code) = action.action {
+            if code >= 0x7F {
+                return Err(BuildError::InvalidManifest(format!(
+                    "Custom input code {code:#04x} on port {port} must be below 0x7F, which marks unused inputs"
+                )));
+            }
+        }
+    }
+
+    // Always hold at least the standard 8 ports, which are written even if unused
+    let mut s_ports: Vec<Option<[Option<NamedAction>; 4]>> =
+        vec![None; s_port_count.max(STANDARD_S_PORTS)];
+    let mut b_port: Option<NamedAction> = None;
+    let mut ba_port: Option<NamedAction> = None;
+    let mut acl_port: Option<NamedAction> = None;
+    let mut acl_mode = AclMode::default();
+
+    for port in &platform.port_map.ports {
+        match port {
+            Port::S { index, bitmap } => s_ports[*index] = Some(bitmap.clone()),
+            Port::Acl { bit, mode } => {
+                acl_port = bit.clone();
+                acl_mode = *mode;
+            }
+            Port::B { bit } => b_port = bit.clone(),
+            Port::BA { bit } => ba_port = bit.clone(),
+        }
+    }
+
+    let extended_s_ports = s_ports.split_off(STANDARD_S_PORTS);
+
+    // S port count. 0 for the standard 8 ports, so those devices are unchanged
+    debug_assert_eq!(config.len(), S_PORT_COUNT_OFFSET);
+    if extended_s_ports.is_empty() {
+        config.push(0);
+    } else {
+        config.push(s_port_count as u8);
+    }
+
+    // Reserved
+    config.push(options.reserved_fill);
+
+    debug_assert_eq!(config.len(), S_PORTS_OFFSET);
+    for port in s_ports {
+        config.extend_from_slice(&s_port_bytes(port));
+    }
+
+    let unused_action = NamedAction {
+        action: Action::Unused,
+        active_low: true,
+        name: None,
+    };
+
+    let b_port = if let Some(b_port) = b_port {
+        input_value_for_port(b_port)
+    } else {
+        // B normally has a pull-up resistor. If not set, default it to high unless the manifest says otherwise
+        let mut action = unused_action.clone();
+        action.active_low = platform.port_map.default_active_low();
+        input_value_for_port(action)
+    };
+    debug_assert_eq!(config.len(), B_PORT_OFFSET);
+    config.push(b_port);
+
+    let ba_port = if let Some(ba_port) = ba_port {
+        input_value_for_port(ba_port)
+    } else {
+        // BA normally has a pull-up resistor. If not set, default it to high unless the manifest says otherwise
+        let mut action = unused_action.clone();
+        action.active_low = platform.port_map.default_active_low();
+        input_value_for_port(action)
+    };
+    config.push(ba_port);
+
+    let acl_port = if let Some(acl_port) = acl_port {
+        input_value_for_port(acl_port)
+    } else {
+        input_value_for_port(unused_action)
+    };
+    config.push(acl_port);
+
+    let ground_index = if let Some(ground_last_index) = platform.port_map.ground_last_index {
+        // Indexes start at 1. Validated to fit with the ports above
+        ground_last_index as u8 + 1
+    } else {
+        // Unset
+        0
+    };
+
+    config.push(ground_index);
+
+    debug_assert_eq!(config.len(), INPUT_FLAGS_OFFSET);
+    config.push(match acl_mode {
+        AclMode::Momentary => 0,
+        AclMode::Latched => INPUT_FLAG_ACL_LATCHED,
+    });
+
+    debug_assert_eq!(config.len(), CPU_CONFIG_OFFSET);
+    config.extend_from_slice(&cpu_config(&platform.device.cpu));
+
+    // Extension chunk offset. Filled in once the chunks are placed
+    config.extend_from_slice(&[0; 4]);
+
+    // Extended S ports
+    debug_assert_eq!(config.len(), EXTENDED_S_PORTS_OFFSET);
+    for i in 0..MAX_S_PORTS - STANDARD_S_PORTS {
+        if let Some(port) = extended_s_ports.get(i) {
+            config.extend_from_slice(&s_port_bytes(port.clone()));
+        } else {
+            config.extend_from_slice(&[options.reserved_fill; 4]);
+        }
+    }
+
+    // ROM length. Filled in once the ROM is loaded
+    debug_assert_eq!(config.len(), ROM_LENGTH_OFFSET);
+    config.extend_from_slice(&[0; 4]);
+
+    // ROM CRC32. Filled in once the ROM is loaded
+    debug_assert_eq!(config.len(), ROM_CRC_OFFSET);
+    config.extend_from_slice(&[0; 4]);
+
+    // Region. Unrecognized regions are warned about in `validate`
+    debug_assert_eq!(config.len(), REGION_OFFSET);
+    let region = match platform.metadata.region().unwrap_or_default() {
+        Region::Unknown => 0,
+        Region::Japan => 1,
+        Region::Usa => 2,
+        Region::Europe => 3,
+        Region::Asia => 4,
+        Region::Soviet => 5,
+    };
+
+    config.push(region);
+
+    // Additional resolutions. Filled in once they're rendered
+    debug_assert_eq!(config.len(), RESOLUTION_COUNT_OFFSET);
+    config.push(0);
+
+    debug_assert_eq!(config.len(), RESOLUTION_TABLE_OFFSET);
+    config.extend_from_slice(&[options.reserved_fill; MAX_ADDITIONAL_RESOLUTIONS * 3]);
+
+    // Payload CRC32. Filled in once the file is complete
+    debug_assert_eq!(config.len(), PAYLOAD_CRC_OFFSET);
+    config.extend_from_slice(&[0; 4]);
+
+    // Mask entry count. Filled in once the mask map is built
+    debug_assert_eq!(config.len(), MASK_ENTRY_COUNT_OFFSET);
+    config.extend_from_slice(&[0; 4]);
+
+    // Game metadata, for the core's menu
+    debug_assert_eq!(config.len(), NAME_OFFSET);
+    config.extend_from_slice(&text_field(
+        "Name",
+        platform.metadata.display_name(),
+        NAME_LENGTH,
+    ));
+
+    debug_assert_eq!(config.len(), COMPANY_OFFSET);
+    config.extend_from_slice(&text_field(
+        "Company",
+        platform.metadata.company.trim(),
+        COMPANY_LENGTH,
+    ));
+
+    debug_assert_eq!(config.len(), YEAR_OFFSET);
+    config.extend_from_slice(&platform.metadata.year_number().unwrap_or(0).to_le_bytes());
+
+    // Image block length and compression. Filled in once the images are encoded
+    debug_assert_eq!(config.len(), IMAGE_LENGTH_OFFSET);
+    config.extend_from_slice(&[0; 4]);
+
+    debug_assert_eq!(config.len(), COMPRESSION_OFFSET);
+    config.push(0);
+
+    // Image resolution. Filled in once the images are rendered
+    debug_assert_eq!(config.len(), IMAGE_RESOLUTION_OFFSET);
+    config.extend_from_slice(&[0; 3]);
+
+    // Full generator commit. Zeroed if it's unknown, like the short commit
+    debug_assert_eq!(config.len(), FULL_GIT_SHA_OFFSET);
+    if options.embed_full_sha {
+        config.extend_from_slice(&full_tool_sha().unwrap_or([0; FULL_GIT_SHA_LENGTH]));
+    } else {
+        config.extend_from_slice(&[options.reserved_fill; FULL_GIT_SHA_LENGTH]);
+    }
+
+    // Second screen size. Single screen devices keep the one size
+    debug_assert_eq!(config.len(), SECOND_SCREEN_SIZE_OFFSET);
+    if let Some(second) = second {
+        config.extend_from_slice(&pack_screen_size(second));
+    } else {
+        config.extend_from_slice(&[options.reserved_fill; 3]);
+    }
+
+    // Image offset. Filled in once the images are rendered
+    debug_assert_eq!(config.len(), IMAGE_OFFSET_OFFSET);
+    config.extend_from_slice(&[0; 3]);
+
+    // Mask entry format. Filled in once the images are rendered
+    debug_assert_eq!(config.len(), MASK_ENTRY_FORMAT_OFFSET);
+    config.push(MaskEntryFormat::Compact as u8);
+
+    // Palette size. Filled in once the images are rendered
+    debug_assert_eq!(config.len(), PALETTE_SIZE_OFFSET);
+    config.extend_from_slice(&[0; 2]);
+
+    if let Some(sha) = tool_sha() {
+        config.extend_from_slice(&sha);
+    } else {
+        logln!("Unknown git SHA");
+
+        config.extend_from_slice(&[0; GIT_SHA_LENGTH]);
     };
 
-    config.push(version);
+    Ok(config)
+}
+
+///
+/// Fails if the two screens of a dual screen device differ in size, as this almost always indicates a bad manifest, and
+/// cores that only read the first screen's size would draw the second at the wrong size. With `allow_screen_mismatch`,
+/// this only logs
+///
+fn check_screen_match(
+    first_name: &str,
+    first: &Size,
+    second_name: &str,
+    second: &Size,
+    options: &EncodeOptions,
+) -> Result<(), String> {
+    if first == second {
+        return Ok(());
+    }
+
+    let message = format!(
+        "{first_name} screen size {}x{} doesn't match the {second_name} screen size {}x{}",
+        first.width, first.height, second.width, second.height
+    );
+
+    if !options.allow_screen_mismatch {
+        return Err(format!(
+            "{message}. Pass --allow-screen-mismatch to build it anyway"
+        ));
+    }
+
+    logln!("{message}");
+
+    Ok(())
+}
+
+///
+/// Encodes `text` as a null padded field of `length` bytes, truncating it at a character boundary if it's too long.
+/// At least one null is always kept, so the field is also null terminated
+///
+fn text_field(field_name: &str, text: &str, length: usize) -> Vec<u8> {
+    let mut end = text.len().min(length - 1);
+
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let truncated = text[..end].trim_end();
+
+    if truncated.len() < text.len() {
+        logln!("{field_name} \"{text}\" is too long, and was truncated to \"{truncated}\"");
+    }
+
+    let mut field = truncated.as_bytes().to_vec();
+    field.resize(length, 0);
+
+    field
+}
+
+fn s_port_bytes(port: Option<[Option<NamedAction>; 4]>) -> [u8; 4] {
+    if let Some(port) = port {
+        port.map(|action| {
+            if let Some(action) = action {
+                input_value_for_port(action)
+            } else {
+                0x7F
+            }
+        })
+    } else {
+        // Write 4 unused entries
+        [0x7F; 4]
+    }
+}
+
+///
+/// Checks for problems that don't prevent encoding, but that the core can't handle: mapped actions it can't
+/// represent for the platform's CPU, actions on a joystick the device doesn't have, S ports the core never reads for
+/// the CPU, and screen sizes that overflow their 10 bit fields. Returns a warning for each, or under `strict` fails
+/// with all of them
+///
+pub fn validate(
+    platform: &PlatformSpecification,
+    options: &EncodeOptions,
+) -> Result<Vec<String>, String> {
+    let cpu = &platform.device.cpu;
+    let joysticks = platform.device.joysticks();
+
+    let mut warnings = vec![];
+
+    for (port, action) in platform.port_map.mapped_actions() {
+        if !cpu.supports_action(&action.action) {
+            warnings.push(format!(
+                "Action {:?} on port {port} is not supported by the core for CPU {cpu:?}",
+                action.action
+            ));
+        } else if let Some(joystick) = action
+            .action
+            .joystick()
+            .filter(|&joystick| joystick > joysticks)
+        {
+            warnings.push(format!(
+                "Action {:?} on port {port} is on joystick {joystick}, but the device has {joysticks}",
+                action.action
+            ));
+        }
+    }
+
+    let s_ports = cpu.input_capabilities().s_ports;
+
+    for port in &platform.port_map.ports {
+        if let Port::S { index, bitmap } = port {
+            if *index >= s_ports
+                && bitmap
+                    .iter()
+                    .flatten()
+                    .any(|action| action.action != Action::Unused)
+            {
+                warnings.push(format!(
+                    "Port S{index} is never read by the core for CPU {cpu:?}, which reads {s_ports} S ports"
+                ));
+            }
+        }
+    }
+
+    for Size { width, height } in screen_sizes(&platform.device.screen) {
+        if width.round() as usize >= MAX_MASK_COORDINATE
+            || height.round() as usize >= MAX_MASK_COORDINATE
+        {
+            warnings.push(format!(
+                "Screen size {width}x{height} overflows its 10 bit fields, and will be truncated"
+            ));
+        }
+    }
+
+    if let Err(err) = platform.metadata.region() {
+        warnings.push(format!("{err}, which will be encoded as unknown"));
+    }
+
+    if options.strict && !warnings.is_empty() {
+        return Err(warnings.join("\n"));
+    }
+
+    Ok(warnings)
+}
+
+///
+/// Runs every check performed while encoding that doesn't need the platform's assets, returning any warnings
+///
+pub fn audit(
+    platform: &PlatformSpecification,
+    options: &EncodeOptions,
+) -> Result<Vec<String>, String> {
+    let warnings = validate(platform, options)?;
+
+    let config = build_config(platform, options).map_err(|err| err.to_string())?;
 
-    // Screen configuration
-    let (screen, width, height) = match &platform.device.screen {
-        Screen::Single { width, height } => (0, *width, *height),
-        Screen::DualVertical { top, bottom } => {
-            if top != bottom {
-                println!("Top and bottom screen sizes don't match");
-            }
+    let cpu = &platform.device.cpu;
+    let decoded = decode::mpu(&config)?;
 
-            (1, top.width, top.height)
-        }
-        Screen::DualHorizontal { left, right } => {
-            if left != right {
-                println!("Left and right screen sizes don't match");
-            }
+    if decoded != *cpu {
+        return Err(format!(
+            "CPU {cpu:?} was encoded as MPU version {}, which decodes as {decoded:?}",
+            config[MPU_OFFSET]
+        ));
+    }
 
-            (2, left.width, left.height)
-        }
-    };
-    config.push(screen);
+    check_input_round_trip(platform, &config)?;
 
-    let mut data: bitvec::vec::BitVec<u8> = bitvec![u8, Lsb0; 0; 3*8];
-    let width = width.round() as u16;
-    let height = height.round() as u16;
-    data[0..10].store(width);
-    data[10..20].store(height);
+    Ok(warnings)
+}
+
+///
+/// The game's input mapping as it's encoded into the config, decoded into a table for proofreading the manifest. See
+/// `decode::input_map_table`
+///
+pub fn input_map_report(
+    platform: &PlatformSpecification,
+    options: &EncodeOptions,
+) -> Result<Vec<String>, BuildError> {
+    let config = build_config(platform, options)?;
 
-    config.append(&mut data.into());
+    // The config was just built, so it can only fail to decode from a bug in the encoder
+    decode::input_map_table(&config).map_err(BuildError::Other)
+}
 
-    // Reserved
-    config.push(0);
-    config.push(0);
+///
+/// Checks that the input mapping decodes from `config` exactly as the manifest maps it, failing with each input that
+/// doesn't
+///
+fn check_input_round_trip(platform: &PlatformSpecification, config: &[u8]) -> Result<(), String> {
+    let decoded = decode::input_ports(config)?;
 
-    // Input mapping
-    let mut s_ports: [Option<[Option<NamedAction>; 4]>; 8] = Default::default();
-    let mut b_port: Option<NamedAction> = None;
-    let mut ba_port: Option<NamedAction> = None;
-    let mut acl_port: Option<NamedAction> = None;
+    let mut pairs: Vec<(String, Option<&NamedAction>, Option<&NamedAction>)> = vec![];
+    let mut acl_error = None;
 
     for port in &platform.port_map.ports {
         match port {
             Port::S { index, bitmap } => {
-                if *index > 7 {
-                    return Err(format!("Port index {index} is out of bounds"));
-                }
+                for (bit, action) in bitmap.iter().enumerate() {
+                    let actual = decoded
+                        .s
+                        .get(*index)
+                        .and_then(|bitmap| bitmap[bit].as_ref());
 
-                s_ports[*index] = Some(bitmap.clone());
+                    pairs.push((format!("S{index} bit {bit}"), action.as_ref(), actual));
+                }
             }
-            Port::ACL { bit } => acl_port = bit.clone(),
-            Port::B { bit } => b_port = bit.clone(),
-            Port::BA { bit } => ba_port = bit.clone(),
-        }
-    }
-
-    for port in s_ports {
-        if let Some(port) = port {
-            for action in port {
-                if let Some(action) = action {
-                    config.push(input_value_for_port(action));
-                } else {
-                    config.push(0x7F);
+            Port::Acl { bit, mode } => {
+                pairs.push(("ACL".to_string(), bit.as_ref(), decoded.acl.as_ref()));
+
+                if *mode != decoded.acl_mode {
+                    acl_error = Some(format!(
+                        "ACL mode {mode:?} decodes as {:?}",
+                        decoded.acl_mode
+                    ));
                 }
             }
-        } else {
-            // Write 4 zeros
-            config.push(0x7F);
-            config.push(0x7F);
-            config.push(0x7F);
-            config.push(0x7F);
+            Port::B { bit } => pairs.push(("B".to_string(), bit.as_ref(), decoded.b.as_ref())),
+            Port::BA { bit } => pairs.push(("BA".to_string(), bit.as_ref(), decoded.ba.as_ref())),
         }
     }
 
-    let unused_action = NamedAction {
-        action: Action::Unused,
-        active_low: true,
-        name: None,
+    // Unused inputs are stored the same as unmapped ones. Compared by code, as a custom code can name the same input
+    // line as another action
+    let mapping = |action: Option<&NamedAction>| {
+        action
+            .filter(|action| action.action != Action::Unused)
+            .map(|action| input_value_for_port(action.clone()))
     };
 
-    let b_port = if let Some(b_port) = b_port {
-        input_value_for_port(b_port)
-    } else {
-        // B has a pull-up resistor. If not set, default it to high
-        let mut action = unused_action.clone();
-        action.active_low = true;
-        input_value_for_port(action)
-    };
-    config.push(b_port);
+    let mut errors = pairs
+        .into_iter()
+        .filter(|(_, expected, actual)| mapping(*expected) != mapping(*actual))
+        .map(|(port, expected, actual)| {
+            format!(
+                "Input {port} is mapped to {:?}, but decodes as {:?}",
+                expected.map(|action| (&action.action, action.active_low)),
+                actual.map(|action| (&action.action, action.active_low))
+            )
+        })
+        .collect::<Vec<String>>();
 
-    let ba_port = if let Some(ba_port) = ba_port {
-        input_value_for_port(ba_port)
-    } else {
-        // BA has a pull-up resistor. If not set, default it to high
-        let mut action = unused_action.clone();
-        action.active_low = true;
-        input_value_for_port(action)
-    };
-    config.push(ba_port);
+    if decoded.ground_last_index.map(usize::from) != platform.port_map.ground_last_index {
+        errors.push(format!(
+            "Ground index {:?} decodes as {:?}",
+            platform.port_map.ground_last_index, decoded.ground_last_index
+        ));
+    }
 
-    let acl_port = if let Some(acl_port) = acl_port {
-        input_value_for_port(acl_port)
-    } else {
-        input_value_for_port(unused_action)
-    };
-    config.push(acl_port);
+    errors.extend(acl_error);
 
-    let ground_index = if let Some(ground_last_index) = platform.port_map.ground_last_index {
-        // Indexes start at 1
-        ground_last_index + 1
+    if errors.is_empty() {
+        Ok(())
     } else {
-        // Unset
-        0
-    };
-
-    config.push(ground_index);
-
-    // Spacer pixels for input mapping
-    for _ in 0..4 {
-        config.push(0);
+        Err(errors.join("\n"))
     }
+}
 
-    // Reserved space
-    for _ in 0..0xC9 {
-        config.push(0);
+///
+/// The MPU version identifying `cpu` in the config
+///
+pub(crate) fn mpu_version(cpu: &CPUType) -> u8 {
+    match cpu {
+        CPUType::SM510 => 0,
+        CPUType::SM511 => 1,
+        CPUType::SM512 => 2,
+        CPUType::SM530 => 3,
+        CPUType::SM5a => 4,
+        CPUType::SM510Tiger => 5,
+        CPUType::SM511Tiger1Bit => 6,
+        CPUType::SM511Tiger2Bit => 7,
+        CPUType::KB1013VK12 => 8,
     }
+}
 
-    let sha = env!("VERGEN_GIT_SHA");
-
-    if sha.len() < 1 {
-        println!("Unknown git SHA");
-
-        vec![0 as u8; 7].iter().for_each(|c| config.push(*c));
-    } else {
-        sha.chars()
-            .take(7)
-            .map(|c| c as u8)
-            .for_each(|c| config.push(c));
-    };
+///
+/// The CPU config of `cpu`, for quirks the core can't tell from the MPU version alone. Empty (all zero) for every CPU
+/// the core already emulates from its version
+///
+fn cpu_config(cpu: &CPUType) -> [u8; CPU_CONFIG_LENGTH] {
+    match cpu {
+        // A Soviet clone of the SM5A, with the same ROM size, reset, and R port input strobes and sound, which the
+        // core would otherwise emulate as an SM510
+        CPUType::KB1013VK12 => [CPU_FLAG_SM5A_CORE | CPU_FLAG_R_PORT_SOUND, 0, 0],
+        CPUType::SM510
+        | CPUType::SM511
+        | CPUType::SM512
+        | CPUType::SM530
+        | CPUType::SM5a
+        | CPUType::SM510Tiger
+        | CPUType::SM511Tiger1Bit
+        | CPUType::SM511Tiger2Bit => [0; CPU_CONFIG_LENGTH],
+    }
+}
 
-    Ok(config)
+/// The size of each screen, as stored in the config
+fn screen_sizes(screen: &Screen) -> Vec<Size> {
+    match screen {
+        Screen::Single { width, height } => vec![Size {
+            width: *width,
+            height: *height,
+        }],
+        Screen::DualVertical { top, bottom } => vec![top.clone(), bottom.clone()],
+        Screen::DualHorizontal { left, right } => vec![left.clone(), right.clone()],
+    }
 }
 
-fn input_value_for_port(action: NamedAction) -> u8 {
+/// The config byte for a single input, as stored in the input mapping
+pub fn input_value_for_port(action: NamedAction) -> u8 {
     let mut input: u8 = match action.action {
         Action::JoyUp => 0,
         Action::JoyDown => 1,
@@ -309,7 +1859,11 @@ fn input_value_for_port(action: NamedAction) -> u8 {
         Action::PowerOn => 26,
         Action::PowerOff => 27,
         Action::Keypad => 28,
-        Action::Custom => 29,
+        Action::Custom(code) => code & 0x7F,
+        Action::Mode => 30,
+        Action::Pause => 31,
+        Action::Time => 32,
+        Action::Alarm => 33,
         Action::Unused => 0x7F,
     };
 
@@ -320,45 +1874,202 @@ fn input_value_for_port(action: NamedAction) -> u8 {
     input
 }
 
+///
+/// Builds the input geometry chunk, mapping each layout input to the action bound to it in the manifest.
+/// Returns `None` if the layout has no usable input geometry
+///
+#[allow(clippy::diverging_sub_expression)]
+fn build_input_geometry(
+    platform: &PlatformSpecification,
+    input_geometry: &[InputGeometry],
+    resolution: Resolution,
+) -> Result<Option<Vec<u8>>, String> {
+    let Resolution { width, height } = resolution;
+
+    let mut entries: Vec<u8> = vec![];
+    let mut count: u8 = 0;
+
+    for geometry in input_geometry {
+        guard!(let Some(action) = platform.port_map.action_for_input(&geometry.tag, geometry.mask) else {
+            // Layout input isn't mapped to anything
+            continue;
+        });
+
+        let dimensions = &geometry.dimensions;
+
+        // Clip to the canvas, as rounding during scaling can push a button slightly past the edge
+        let x = dimensions.x.clamp(0, width as i32) as usize;
+        let y = dimensions.y.clamp(0, height as i32) as usize;
+        let right = (dimensions.x + dimensions.width as i32).clamp(0, width as i32) as usize;
+        let bottom = (dimensions.y + dimensions.height as i32).clamp(0, height as i32) as usize;
+
+        if right <= x || bottom <= y {
+            return Err(format!(
+                "Input {} (mask {:#x}) at {dimensions:?} does not fit on the {width}x{height} canvas",
+                geometry.tag, geometry.mask
+            ));
+        }
+
+        if count == u8::MAX {
+            return Err(format!("More than {} inputs in layout", u8::MAX));
+        }
+
+        // Active low is irrelevant here
+        entries.push(input_value_for_port(action.clone()) & 0x7F);
+
+        let mut data: bitvec::vec::BitVec<u8> = bitvec![u8, Lsb0; 0; 5*8];
+        data[0..10].store::<u16>(x as u16);
+        data[10..20].store::<u16>(y as u16);
+        data[20..30].store::<u16>((right - x) as u16);
+        data[30..40].store::<u16>((bottom - y) as u16);
+
+        entries.append(&mut data.into());
+        count += 1;
+    }
+
+    if count == 0 {
+        return Ok(None);
+    }
+
+    let mut output = vec![count];
+    output.append(&mut entries);
+
+    Ok(Some(output))
+}
+
 const BYTES_PER_ENTRY: usize = 5;
+/// Mask entry positions and lengths are 10 bit fields
+const MAX_MASK_COORDINATE: usize = 1 << 10;
+/// The largest id a compact mask entry can hold
+const MAX_COMPACT_MASK_ID: u16 = 0x3FF;
+/// The longest run a single mask entry can hold. Longer runs are split into consecutive entries
+const MAX_MASK_LENGTH: usize = MAX_MASK_COORDINATE - 1;
 const AVERAGE_ENTRIES_PER_ROW: usize = 52;
-const TOTAL_BYTE_LENGTH: usize = BYTES_PER_ENTRY * AVERAGE_ENTRIES_PER_ROW * HEIGHT;
+/// NeuQuant learns from every this many pixels. 1 is the slowest and most accurate
+pub const QUANTIZER_SAMPLE_FACTOR: i32 = 10;
+
+/// How each mask entry is packed, stored in the config at `MASK_ENTRY_FORMAT_OFFSET`
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum MaskEntryFormat {
+    /// 5 bytes: a 10 bit id, then the 10 bit x, y, and length
+    Compact = 0,
+    /// 6 bytes: a 16 bit id, then the 10 bit x, y, and length, and 2 reserved bits. Only used when an id doesn't fit
+    /// in 10 bits, so simple games stay compact
+    Wide = 1,
+}
+
+impl MaskEntryFormat {
+    ///
+    /// The smallest format that holds every id in `pixels_to_mask_id`
+    ///
+    pub fn for_ids(pixels_to_mask_id: &[Option<u16>]) -> Self {
+        if pixels_to_mask_id
+            .iter()
+            .flatten()
+            .any(|id| *id > MAX_COMPACT_MASK_ID)
+        {
+            MaskEntryFormat::Wide
+        } else {
+            MaskEntryFormat::Compact
+        }
+    }
+
+    /// The number of bytes in each entry
+    pub fn entry_length(self) -> usize {
+        match self {
+            MaskEntryFormat::Compact => BYTES_PER_ENTRY,
+            MaskEntryFormat::Wide => BYTES_PER_ENTRY + 1,
+        }
+    }
+
+    /// The width of the id field, which is followed by the 10 bit x, y, and length
+    pub fn id_bits(self) -> usize {
+        match self {
+            MaskEntryFormat::Compact => 10,
+            MaskEntryFormat::Wide => 16,
+        }
+    }
+}
+
+///
+/// The size of the fixed mask config for a canvas of `resolution`. 0x2DB40 bytes for the primary images
+///
+pub fn mask_map_length(resolution: Resolution) -> usize {
+    BYTES_PER_ENTRY * AVERAGE_ENTRIES_PER_ROW * resolution.height
+}
 
+///
+/// Appends the entries of a run of `length` pixels of `id`. A run longer than the length field can hold is split into
+/// consecutive entries, but a run starting at a coordinate that doesn't fit in its field is an error
+///
+#[allow(clippy::too_many_arguments)]
 fn insert_mask_entry_bytes(
+    platform_name: &str,
     output: &mut Vec<u8>,
-    byte_index: &mut usize,
+    max_entries: usize,
+    format: MaskEntryFormat,
     id: u16,
     length: usize,
     start_x: usize,
     y: usize,
 ) -> Result<(), String> {
-    if *byte_index + BYTES_PER_ENTRY > TOTAL_BYTE_LENGTH {
+    let mut start_x = start_x;
+    let mut remaining = length;
+
+    if format == MaskEntryFormat::Compact && id > MAX_COMPACT_MASK_ID {
         return Err(format!(
-            "More entries ({}) than allowed ({TOTAL_BYTE_LENGTH})",
-            *byte_index + BYTES_PER_ENTRY
+            "Mask id {id:#x} does not fit in the 10 bits of a compact mask entry"
         ));
     }
 
-    output[*byte_index..*byte_index + BYTES_PER_ENTRY]
-        .clone_from_slice(&entry_to_bytes(id, length, start_x, y));
+    while remaining > 0 {
+        let entry_count = output.len() / format.entry_length() + 1;
+
+        if entry_count > max_entries {
+            return Err(format!(
+                "{platform_name}: More entries ({entry_count}) than allowed ({max_entries}) at row {y}"
+            ));
+        }
+
+        if start_x >= MAX_MASK_COORDINATE || y >= MAX_MASK_COORDINATE {
+            return Err(format!(
+                "Mask entry for id {id} at ({start_x}, {y}) does not fit in 10 bit fields"
+            ));
+        }
+
+        let entry_length = remaining.min(MAX_MASK_LENGTH);
+
+        output.append(&mut entry_to_bytes(format, id, entry_length, start_x, y));
 
-    *byte_index += BYTES_PER_ENTRY;
+        start_x += entry_length;
+        remaining -= entry_length;
+    }
 
     Ok(())
 }
 
-fn build_mask_map(pixels_to_mask_id: &[Option<u16>]) -> Result<Vec<u8>, String> {
-    // 5 bytes per entry
-    let mut output: Vec<u8> = vec![0; TOTAL_BYTE_LENGTH];
-    let mut byte_index = 0;
+///
+/// Run length encodes each row of `pixels_to_mask_id` into 5 byte mask entries, top to bottom and left to right
+///
+pub fn build_mask_map(
+    platform_name: &str,
+    pixels_to_mask_id: &[Option<u16>],
+    resolution: Resolution,
+    format: MaskEntryFormat,
+) -> Result<Vec<u8>, String> {
+    let Resolution { width, height } = resolution;
+
+    // 5 bytes per entry. Every entry covers at least one pixel, so more than one per pixel is a bug
+    let mut output: Vec<u8> = Vec::with_capacity(mask_map_length(resolution));
+    let max_entries = width * height;
 
-    for y in 0..HEIGHT {
+    for y in 0..height {
         let mut current_id: Option<u16> = None;
         let mut start_x: usize = 0;
         let mut length: usize = 0;
 
-        for x in 0..WIDTH {
-            if let Some(id) = pixels_to_mask_id[y * WIDTH + x] {
+        for x in 0..width {
+            if let Some(id) = pixels_to_mask_id[y * width + x] {
                 // Has id
                 match current_id {
                     Some(stored_id) => {
@@ -368,8 +2079,10 @@ fn build_mask_map(pixels_to_mask_id: &[Option<u16>]) -> Result<Vec<u8>, String>
                         } else {
                             // This is a new segment, finish the old segment and start a new one
                             insert_mask_entry_bytes(
+                                platform_name,
                                 &mut output,
-                                &mut byte_index,
+                                max_entries,
+                                format,
                                 stored_id,
                                 length,
                                 start_x,
@@ -394,27 +2107,1096 @@ fn build_mask_map(pixels_to_mask_id: &[Option<u16>]) -> Result<Vec<u8>, String>
                     // End entry
                     current_id = None;
 
-                    insert_mask_entry_bytes(&mut output, &mut byte_index, id, length, start_x, y)?;
+                    insert_mask_entry_bytes(
+                        platform_name,
+                        &mut output,
+                        max_entries,
+                        format,
+                        id,
+                        length,
+                        start_x,
+                        y,
+                    )?;
                 }
             }
         }
 
         if let Some(id) = current_id {
             // Clean up straggler at the end of a row
-            insert_mask_entry_bytes(&mut output, &mut byte_index, id, length, start_x, y)?;
+            insert_mask_entry_bytes(
+                platform_name,
+                &mut output,
+                max_entries,
+                format,
+                id,
+                length,
+                start_x,
+                y,
+            )?;
         }
     }
 
     Ok(output)
 }
 
-fn entry_to_bytes(id: u16, length: usize, start_x: usize, y: usize) -> Vec<u8> {
-    let mut data: bitvec::vec::BitVec<u8> = bitvec![u8, Lsb0; 0; 5*8];
+fn entry_to_bytes(
+    format: MaskEntryFormat,
+    id: u16,
+    length: usize,
+    start_x: usize,
+    y: usize,
+) -> Vec<u8> {
+    let id_bits = format.id_bits();
+
+    let mut data: bitvec::vec::BitVec<u8> = bitvec![u8, Lsb0; 0; format.entry_length() * 8];
 
-    data[0..10].store::<u16>(id);
-    data[10..20].store::<u16>(start_x as u16);
-    data[20..30].store::<u16>(y as u16);
-    data[30..40].store::<u16>(length as u16);
+    data[0..id_bits].store::<u16>(id);
+    data[id_bits..id_bits + 10].store::<u16>(start_x as u16);
+    data[id_bits + 10..id_bits + 20].store::<u16>(y as u16);
+    data[id_bits + 20..id_bits + 30].store::<u16>(length as u16);
 
     data.into()
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+
+    use super::*;
+    use crate::{
+        manifest::{MelodyBanks, LEGACY_CUSTOM_CODE},
+        HEIGHT, WIDTH,
+    };
+
+    fn options() -> EncodeOptions {
+        EncodeOptions {
+            reserved_fill: 0,
+            trim_rom: false,
+            skip_hash_check: false,
+            output_ext: "gnw".to_string(),
+            strict: false,
+            embed_metadata: false,
+            embed_full_sha: false,
+            dry_run: true,
+            compress: false,
+            to_stdout: false,
+            palette_colors: None,
+            allow_screen_mismatch: false,
+            allow_mask_overflow: false,
+            by_company: false,
+            name_template: None,
+            build_fingerprint: None,
+        }
+    }
+
+    fn platform(cpu: &str, ports: &str) -> PlatformSpecification {
+        platform_with_screen(
+            cpu,
+            ports,
+            r#"{ "type": "single", "width": 1000, "height": 600 }"#,
+        )
+    }
+
+    fn platform_with_screen(cpu: &str, ports: &str, screen: &str) -> PlatformSpecification {
+        serde_json::from_str(&format!(
+            r#"{{
+                "device": {{ "cpu": "{cpu}", "screen": {screen} }},
+                "portMap": {{ "ports": {ports}, "groundLastIndex": 1 }},
+                "metadata": {{ "year": "1981", "name": "Test", "company": "Nintendo" }},
+                "rom": {{ "rom": "test", "romHash": "{}" }}
+            }}"#,
+            "0".repeat(40)
+        ))
+        .unwrap()
+    }
+
+    fn blank_rendered() -> RenderedData {
+        RenderedData {
+            background_bytes: Pixmap::new(WIDTH as u32, HEIGHT as u32).unwrap(),
+            mask_bytes: Pixmap::new(WIDTH as u32, HEIGHT as u32).unwrap(),
+            pixels_to_mask_id: vec![None; WIDTH * HEIGHT],
+            input_geometry: vec![],
+            resolution: Resolution::STANDARD,
+            offset: (0, 0),
+            overlap_warnings: vec![],
+        }
+    }
+
+    ///
+    /// Encodes the rendered images with the given asset files, returning the written file
+    ///
+    fn encode_with_assets(
+        name: &str,
+        rendered: &RenderedData,
+        platform: &PlatformSpecification,
+        options: &EncodeOptions,
+        assets: &[(&str, &[u8])],
+    ) -> Result<Vec<u8>, BuildError> {
+        let dir = env::temp_dir().join(format!("gnw_encode_{name}_{}", std::process::id()));
+        let asset_dir = dir.join("assets");
+        fs::create_dir_all(&asset_dir).unwrap();
+
+        for (file, data) in assets {
+            fs::write(asset_dir.join(file), data).unwrap();
+        }
+
+        let options = EncodeOptions {
+            dry_run: false,
+            ..options.clone()
+        };
+        let result = encode(name, rendered, &[], platform, &asset_dir, &dir, &options)
+            .map(|(path, _)| fs::read(path).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        result
+    }
+
+    ///
+    /// A 4 KiB ROM, with the platform's hash updated to match it
+    ///
+    fn test_rom(platform: &mut PlatformSpecification) -> Vec<u8> {
+        let rom = (0..0x1000).map(|i| i as u8).collect::<Vec<_>>();
+        platform.rom.rom_hash = hex::encode(Sha1::digest(&rom));
+
+        rom
+    }
+
+    #[test]
+    fn melody_round_trip() {
+        let melody = (0..0x200).map(|i| (i * 3) as u8).collect::<Vec<_>>();
+
+        let mut platform = platform("sm511", "[]");
+        let rom = test_rom(&mut platform);
+        platform.rom.melody = Some("melody".to_string());
+        platform.rom.melody_banks = Some(MelodyBanks {
+            count: 2,
+            size: 0x100,
+        });
+
+        let data = encode_with_assets(
+            "melody",
+            &blank_rendered(),
+            &platform,
+            &options(),
+            &[("test", &rom), ("melody", &melody)],
+        )
+        .unwrap();
+
+        assert_eq!(decode::rom(&data).unwrap(), rom);
+        assert_eq!(decode::melody(&data).unwrap(), melody);
+        // The bank count follows the melody length
+        assert_eq!(data[ROM_OFFSET + rom.len() + 4], 2);
+    }
+
+    #[test]
+    fn melody_bank_header_round_trip() {
+        let melody = vec![0x5A; 0x300];
+
+        let mut platform = platform("sm512", "[]");
+        let rom = test_rom(&mut platform);
+        platform.rom.melody = Some("melody".to_string());
+
+        let encode = |platform: &PlatformSpecification| {
+            encode_with_assets(
+                "melody_banks",
+                &blank_rendered(),
+                platform,
+                &options(),
+                &[("test", &rom), ("melody", &melody)],
+            )
+        };
+
+        // Without a declared layout, the bank count is 0
+        let data = encode(&platform).unwrap();
+        assert!(decode::melody_banks(&data).unwrap().is_none());
+
+        platform.rom.melody_banks = Some(MelodyBanks {
+            count: 3,
+            size: 0x100,
+        });
+        let data = encode(&platform).unwrap();
+        let banks = decode::melody_banks(&data).unwrap().unwrap();
+        assert_eq!((banks.count, banks.size), (3, 0x100));
+
+        // Banks that don't evenly divide the melody ROM are rejected
+        platform.rom.melody_banks = Some(MelodyBanks {
+            count: 5,
+            size: 0x100,
+        });
+        assert!(encode(&platform).is_err());
+    }
+
+    #[test]
+    fn mask_overflow_chunk() {
+        let mut platform = platform("sm510", "[]");
+        let rom = test_rom(&mut platform);
+
+        // Alternating ids give every pixel its own entry, far more than the mask config holds
+        let mut rendered = blank_rendered();
+        for (i, id) in rendered.pixels_to_mask_id.iter_mut().enumerate() {
+            *id = Some((i % 2) as u16);
+        }
+
+        // The cores can't display the overflow, so it fails unless allowed
+        assert!(matches!(
+            encode_with_assets(
+                "overflow_default",
+                &rendered,
+                &platform,
+                &options(),
+                &[("test", &rom)]
+            ),
+            Err(BuildError::MaskOverflow(_))
+        ));
+
+        let options = EncodeOptions {
+            allow_mask_overflow: true,
+            ..options()
+        };
+        let data = encode_with_assets(
+            "overflow",
+            &rendered,
+            &platform,
+            &options,
+            &[("test", &rom)],
+        )
+        .unwrap();
+
+        let entry_count = u32::from_le_bytes(
+            data[MASK_ENTRY_COUNT_OFFSET..MASK_ENTRY_COUNT_OFFSET + 4]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        assert_eq!(entry_count, WIDTH * HEIGHT);
+
+        let chunks = decode::extension_chunks(&data).unwrap();
+        let overflow = chunks
+            .iter()
+            .find(|chunk| &chunk.tag == MASK_OVERFLOW_TAG)
+            .unwrap();
+        assert_eq!(
+            overflow.data.len(),
+            (entry_count - mask_map_length(Resolution::STANDARD) / 5) * 5
+        );
+        // The ROM stays in place
+        assert_eq!(decode::rom(&data).unwrap(), rom);
+    }
+
+    #[test]
+    fn metadata_round_trip() {
+        let mut platform = platform("sm510", "[]");
+        let rom = test_rom(&mut platform);
+
+        let data = encode_with_assets(
+            "no_metadata",
+            &blank_rendered(),
+            &platform,
+            &options(),
+            &[("test", &rom)],
+        )
+        .unwrap();
+        assert_eq!(decode::embedded_metadata(&data), Ok(None));
+
+        let options = EncodeOptions {
+            embed_metadata: true,
+            ..options()
+        };
+        let data = encode_with_assets(
+            "metadata",
+            &blank_rendered(),
+            &platform,
+            &options,
+            &[("test", &rom)],
+        )
+        .unwrap();
+
+        let metadata = decode::embedded_metadata(&data).unwrap().unwrap();
+        assert_eq!(metadata["name"], "Test");
+        assert_eq!(metadata["company"], "Nintendo");
+        assert_eq!(metadata["year"], "1981");
+        assert_eq!(metadata["cpu"], "sm510");
+        assert_eq!(metadata["screen"]["width"], 1000.0);
+        assert_eq!(metadata["toolVersion"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(metadata["gitSha"], env!("VERGEN_GIT_SHA"));
+        assert!(metadata["buildTime"].is_u64());
+        // The chunk comes after the ROM, which is unchanged
+        assert_eq!(decode::rom(&data).unwrap(), rom);
+    }
+
+    #[test]
+    fn build_fingerprint_freshness() {
+        let mut platform = platform("sm510", "[]");
+        let rom = test_rom(&mut platform);
+
+        let options = EncodeOptions {
+            build_fingerprint: Some(0x1234_5678),
+            ..options()
+        };
+        let data = encode_with_assets(
+            "fingerprint",
+            &blank_rendered(),
+            &platform,
+            &options,
+            &[("test", &rom)],
+        )
+        .unwrap();
+        assert_eq!(decode::build_fingerprint(&data), Ok(Some(0x1234_5678)));
+
+        let path = env::temp_dir().join(format!("gnw_fingerprint_{}.gnw", std::process::id()));
+        fs::write(&path, &data).unwrap();
+
+        // Only known versions of the tool can be up to date at all
+        assert_eq!(is_up_to_date(&path, 0x1234_5678), tool_sha().is_some());
+        assert!(!is_up_to_date(&path, 0x1234_5679));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rom_crc() {
+        let mut platform = platform("sm510", "[]");
+        let rom = test_rom(&mut platform);
+
+        let data = encode_with_assets(
+            "rom_crc",
+            &blank_rendered(),
+            &platform,
+            &options(),
+            &[("test", &rom)],
+        )
+        .unwrap();
+
+        assert_eq!(
+            data[ROM_CRC_OFFSET..ROM_CRC_OFFSET + 4],
+            crc32fast::hash(&rom).to_le_bytes()
+        );
+        assert_eq!(data[ROM_OFFSET..ROM_OFFSET + rom.len()], rom);
+    }
+
+    #[test]
+    fn trimmed_rom_length() {
+        let mut platform = platform("sm510", "[]");
+        let mut rom = (0..0x800).map(|i| (i % 0xFE + 1) as u8).collect::<Vec<_>>();
+        rom.resize(0x1000, 0xFF);
+        platform.rom.rom_hash = hex::encode(Sha1::digest(&rom));
+
+        let options = EncodeOptions {
+            trim_rom: true,
+            build_fingerprint: Some(1),
+            ..options()
+        };
+        let trimmed = |platform: &PlatformSpecification| {
+            encode_with_assets(
+                "trim_rom",
+                &blank_rendered(),
+                platform,
+                &options,
+                &[("test", &rom)],
+            )
+            .unwrap()
+        };
+
+        // The cores stop loading CPU ROM at the stored length, so the melody header and chunks that follow start right
+        // after it
+        let data = trimmed(&platform);
+        assert_eq!(
+            data[ROM_LENGTH_OFFSET..ROM_LENGTH_OFFSET + 4],
+            0x800u32.to_le_bytes()
+        );
+        assert_eq!(decode::rom(&data).unwrap(), &rom[..0x800]);
+        assert_eq!(
+            data[EXTENSION_OFFSET..EXTENSION_OFFSET + 4],
+            ((ROM_OFFSET + 0x800 + MELODY_HEADER_LENGTH) as u32).to_le_bytes()
+        );
+
+        // Never past the declared size
+        platform.rom.rom_size = Some(0xC00);
+        let data = trimmed(&platform);
+        assert_eq!(decode::rom(&data).unwrap(), &rom[..0xC00]);
+
+        platform.rom.rom_size = Some(0x400);
+        let data = trimmed(&platform);
+        assert_eq!(decode::rom(&data).unwrap(), rom);
+    }
+
+    #[test]
+    fn payload_crc_detects_corruption() {
+        let mut platform = platform("sm510", "[]");
+        let rom = test_rom(&mut platform);
+
+        let mut data = encode_with_assets(
+            "payload_crc",
+            &blank_rendered(),
+            &platform,
+            &options(),
+            &[("test", &rom)],
+        )
+        .unwrap();
+        assert!(decode::verify_payload_crc(&data).is_ok());
+
+        // A single flipped byte in the ROM, well past the config
+        data[ROM_OFFSET + 0x10] ^= 0xFF;
+        assert!(decode::verify_payload_crc(&data).is_err());
+    }
+
+    #[test]
+    fn compressed_image_block_round_trip() {
+        let mut platform = platform("sm510", "[]");
+        let rom = test_rom(&mut platform);
+
+        // A simple gradient, so the images aren't trivially empty
+        let mut rendered = blank_rendered();
+        for (i, pixel) in rendered
+            .background_bytes
+            .data_mut()
+            .chunks_exact_mut(4)
+            .enumerate()
+        {
+            pixel.copy_from_slice(&[(i % WIDTH) as u8, (i / WIDTH) as u8, 0x40, 0xFF]);
+        }
+
+        let encode = |name, options: &EncodeOptions| {
+            encode_with_assets(name, &rendered, &platform, options, &[("test", &rom)]).unwrap()
+        };
+
+        let uncompressed = encode("uncompressed", &options());
+        let compressed = encode(
+            "compressed",
+            &EncodeOptions {
+                compress: true,
+                ..options()
+            },
+        );
+
+        let stored_length = |data: &[u8]| {
+            u32::from_le_bytes(
+                data[IMAGE_LENGTH_OFFSET..IMAGE_LENGTH_OFFSET + 4]
+                    .try_into()
+                    .unwrap(),
+            ) as usize
+        };
+
+        assert_eq!(compressed[COMPRESSION_OFFSET], COMPRESSION_ZLIB);
+        assert!(stored_length(&compressed) < stored_length(&uncompressed));
+        assert_eq!(
+            decode::image_block(&compressed).unwrap(),
+            decode::image_block(&uncompressed).unwrap()
+        );
+        // Everything after the images is unchanged
+        assert_eq!(decode::rom(&compressed).unwrap(), rom);
+    }
+
+    #[test]
+    fn header_version_and_screen() {
+        let config = build_config(&platform("sm510", "[]"), &options()).unwrap();
+
+        assert_eq!(config.len(), CONFIG_LENGTH);
+        assert_eq!(config[..4], *b"GNW1");
+        assert_eq!(config[VERSION_OFFSET], FORMAT_VERSION);
+        assert_eq!(config[GIT_SHA_OFFSET - 1], 0);
+        assert_eq!(config[SCREEN_OFFSET], 0);
+        // 1000 in bits 0..10 and 600 in bits 10..20
+        assert_eq!(
+            config[SCREEN_SIZE_OFFSET..SCREEN_SIZE_OFFSET + 3],
+            [0xE8, 0x63, 0x09]
+        );
+    }
+
+    #[test]
+    fn header_dual_screen() {
+        let screen = r#"{
+            "type": "dualVertical",
+            "top": { "width": 1000, "height": 600 },
+            "bottom": { "width": 1000, "height": 601 }
+        }"#;
+        let platform = platform_with_screen("sm510", "[]", screen);
+
+        assert!(build_config(&platform, &options()).is_err());
+
+        let options = EncodeOptions {
+            allow_screen_mismatch: true,
+            ..options()
+        };
+        let config = build_config(&platform, &options).unwrap();
+
+        assert_eq!(config[SCREEN_OFFSET], 1);
+        assert_eq!(
+            config[SCREEN_SIZE_OFFSET..SCREEN_SIZE_OFFSET + 3],
+            [0xE8, 0x63, 0x09]
+        );
+        assert_eq!(
+            config[SECOND_SCREEN_SIZE_OFFSET..SECOND_SCREEN_SIZE_OFFSET + 3],
+            [0xE8, 0x67, 0x09]
+        );
+    }
+
+    #[test]
+    fn header_mpu_version() {
+        let expected = [
+            ("sm510", 0),
+            ("sm511", 1),
+            ("sm512", 2),
+            ("sm530", 3),
+            ("sm5a", 4),
+            ("sm510_tiger", 5),
+            ("sm511_tiger1bit", 6),
+            ("sm511_tiger2bit", 7),
+            ("kb1013vk12", 8),
+        ];
+
+        assert_eq!(expected.len(), CPUType::value_variants().len());
+
+        for (cpu, version) in expected {
+            let config = build_config(&platform(cpu, "[]"), &options()).unwrap();
+
+            assert_eq!(config[MPU_OFFSET], version, "{cpu}");
+
+            // Only the KB1013VK1-2 has quirks beyond its MPU version
+            let cpu_config = &config[CPU_CONFIG_OFFSET..CPU_CONFIG_OFFSET + CPU_CONFIG_LENGTH];
+            if cpu == "kb1013vk12" {
+                assert_eq!(
+                    cpu_config,
+                    [CPU_FLAG_SM5A_CORE | CPU_FLAG_R_PORT_SOUND, 0, 0]
+                );
+            } else {
+                assert_eq!(cpu_config, [0; CPU_CONFIG_LENGTH], "{cpu}");
+            }
+        }
+    }
+
+    #[test]
+    fn cpu_round_trip() {
+        for cpu in CPUType::value_variants() {
+            let mut platform = platform("sm510", "[]");
+            platform.device.cpu = cpu.clone();
+            let config = build_config(&platform, &options()).unwrap();
+
+            assert_eq!(
+                decode::mpu(&config).as_ref(),
+                Ok(cpu),
+                "CPU {cpu:?} does not round trip"
+            );
+        }
+    }
+
+    #[test]
+    fn header_input_mapping() {
+        let ports = r#"[
+            {
+                "type": "s",
+                "index": 1,
+                "bitmap": [
+                    { "action": "button1", "activeLow": false },
+                    null,
+                    { "action": "joyLeft", "activeLow": true },
+                    null
+                ]
+            },
+            { "type": "acl", "bit": { "action": "service1", "activeLow": false } },
+            { "type": "b", "bit": { "action": "start1", "activeLow": true } }
+        ]"#;
+        let config = build_config(&platform("sm510", ports), &options()).unwrap();
+
+        // Standard ports are always written, with unmapped ports as unused
+        assert_eq!(config[S_PORT_COUNT_OFFSET], 0);
+        assert_eq!(config[S_PORTS_OFFSET..S_PORTS_OFFSET + 4], [0x7F; 4]);
+        assert_eq!(
+            config[S_PORTS_OFFSET + 4..S_PORTS_OFFSET + 8],
+            [0x04, 0x7F, 0x82, 0x7F]
+        );
+        assert_eq!(config[S_PORTS_OFFSET + 8..B_PORT_OFFSET], [0x7F; 24]);
+
+        // B, BA (pulled high when unmapped), ACL, then the ground index, starting at 1
+        assert_eq!(
+            config[B_PORT_OFFSET..B_PORT_OFFSET + 4],
+            [0x8D, 0xFF, 0x0F, 0x02]
+        );
+    }
+
+    #[test]
+    fn header_extended_input_round_trip() {
+        // Every bit of all 10 S ports mapped to a distinct custom code
+        let ports = (0..10)
+            .map(|index| {
+                let bitmap = (0..4)
+                    .map(|bit| {
+                        format!(
+                            r#"{{ "action": {{ "custom": {} }}, "activeLow": {} }}"#,
+                            index * 4 + bit + 1,
+                            bit % 2 == 0
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                format!(r#"{{ "type": "s", "index": {index}, "bitmap": [{bitmap}] }}"#)
+            })
+            .chain([
+                r#"{ "type": "b", "bit": { "action": "start1", "activeLow": true } }"#.to_string(),
+                r#"{ "type": "acl", "bit": { "action": "service1", "activeLow": false } }"#
+                    .to_string(),
+            ])
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut platform = platform("sm510", &format!("[{ports}]"));
+        platform.port_map.s_port_count = Some(10);
+        platform.port_map.ground_last_index = Some(9);
+
+        let config = build_config(&platform, &options()).unwrap();
+        assert_eq!(config[S_PORT_COUNT_OFFSET], 10);
+
+        let decoded = decode::input_ports(&config).unwrap();
+        assert_eq!(decoded.s.len(), 10);
+
+        // Custom codes decode as the named action sharing their code, if any, so compare the encoded values
+        for (index, port) in decoded.s.into_iter().enumerate() {
+            for (bit, action) in port.into_iter().enumerate() {
+                let active_low = if bit % 2 == 0 { 0x80 } else { 0 };
+
+                assert_eq!(
+                    input_value_for_port(action.unwrap()),
+                    (index * 4 + bit + 1) as u8 | active_low,
+                    "S{index} bit {bit}"
+                );
+            }
+        }
+
+        assert_eq!(decoded.b.unwrap().action, Action::Start1);
+        assert_eq!(decoded.acl.unwrap().action, Action::Service1);
+        assert_eq!(decoded.ground_last_index, Some(9));
+        assert!(check_input_round_trip(&platform, &config).is_ok());
+    }
+
+    #[test]
+    fn input_map_report_rows() {
+        let ports = r#"[
+            {
+                "type": "s",
+                "index": 1,
+                "bitmap": [{ "action": "button1", "activeLow": false }, null, null, null]
+            },
+            { "type": "b", "bit": { "action": "start1", "activeLow": true } }
+        ]"#;
+        let report = input_map_report(&platform("sm510", ports), &options()).unwrap();
+
+        // Header, the 4 bits of the only mapped S port, B, BA, ACL, and the ground index
+        assert_eq!(report.len(), 9);
+        assert_eq!(report[1], "S2    0    Button1  no          0x04");
+        assert_eq!(report[5], "B     -    Start1   yes         0x8d");
+        assert_eq!(report[8], "Grounded last: S2");
+    }
+
+    #[test]
+    fn header_ground_index_bounds() {
+        let mut platform = platform("sm510", "[]");
+        let cpu = platform.device.cpu.clone();
+
+        platform.port_map.ground_last_index = Some(7);
+        let config = build_config(&platform, &options()).unwrap();
+        assert_eq!(config[B_PORT_OFFSET + 3], 8);
+
+        for index in [8, 300] {
+            platform.port_map.ground_last_index = Some(index);
+            assert!(build_config(&platform, &options()).is_err());
+        }
+
+        // Extended S ports widen the bounds, up to the most the config stores
+        platform.port_map.s_port_count = Some(MAX_S_PORTS as u8);
+        platform.port_map.ground_last_index = Some(MAX_S_PORTS - 1);
+        assert!(platform.port_map.validate(&cpu).is_empty());
+        assert_eq!(
+            build_config(&platform, &options()).unwrap()[B_PORT_OFFSET + 3],
+            MAX_S_PORTS as u8
+        );
+        platform.port_map.ground_last_index = Some(MAX_S_PORTS);
+        assert_eq!(platform.port_map.validate(&cpu).len(), 1);
+    }
+
+    #[test]
+    fn s_port_count_bounds() {
+        let mut platform = platform("sm510", "[]");
+        let cpu = platform.device.cpu.clone();
+
+        platform.port_map.s_port_count = Some(MAX_S_PORTS as u8);
+        assert!(platform.port_map.validate(&cpu).is_empty());
+        assert!(build_config(&platform, &options()).is_ok());
+
+        // More than the config stores is caught by validation, not only once encoding
+        for count in [MAX_S_PORTS as u8 + 1, u8::MAX] {
+            platform.port_map.s_port_count = Some(count);
+            assert_eq!(platform.port_map.validate(&cpu).len(), 1);
+            assert!(build_config(&platform, &options()).is_err());
+        }
+    }
+
+    #[test]
+    fn header_acl_mode() {
+        let ports = |mode: &str| {
+            format!(
+                r#"[{{ "type": "acl", "bit": {{ "action": "service1", "activeLow": false }}{mode} }}]"#
+            )
+        };
+
+        // Momentary unless set, and stored apart from the reserved fill
+        let options = EncodeOptions {
+            reserved_fill: 0xFF,
+            ..options()
+        };
+        let config = build_config(&platform("sm510", &ports("")), &options).unwrap();
+        assert_eq!(config[INPUT_FLAGS_OFFSET], 0);
+        // As is the CPU config that follows it
+        assert_eq!(config[CPU_CONFIG_OFFSET..EXTENSION_OFFSET], [0; 3]);
+
+        let platform = platform("sm510", &ports(r#", "mode": "latched""#));
+        let config = build_config(&platform, &options).unwrap();
+        assert_eq!(config[INPUT_FLAGS_OFFSET], INPUT_FLAG_ACL_LATCHED);
+        assert_eq!(
+            decode::input_ports(&config).unwrap().acl_mode,
+            AclMode::Latched
+        );
+        assert!(check_input_round_trip(&platform, &config).is_ok());
+    }
+
+    #[test]
+    fn input_value_active_low() {
+        let action = |action, active_low| NamedAction {
+            action,
+            active_low,
+            name: None,
+        };
+
+        assert_eq!(input_value_for_port(action(Action::JoyUp, false)), 0x00);
+        assert_eq!(input_value_for_port(action(Action::JoyUp, true)), 0x80);
+        assert_eq!(input_value_for_port(action(Action::Custom(29), true)), 0x9D);
+        assert_eq!(input_value_for_port(action(Action::Custom(4), false)), 0x04);
+        assert_eq!(input_value_for_port(action(Action::Alarm, true)), 0xA1);
+        assert_eq!(input_value_for_port(action(Action::Unused, false)), 0x7F);
+    }
+
+    #[test]
+    fn legacy_custom_action() {
+        let parse = |action: &str| {
+            serde_json::from_str::<NamedAction>(&format!(
+                r#"{{ "action": {action}, "activeLow": false }}"#
+            ))
+            .map(|named| named.action)
+        };
+
+        // Manifests from before custom codes still load, keeping the code they were encoded with
+        assert_eq!(
+            parse(r#""custom""#).unwrap(),
+            Action::Custom(LEGACY_CUSTOM_CODE)
+        );
+        assert_eq!(parse(r#"{ "custom": 30 }"#).unwrap(), Action::Custom(30));
+        assert_eq!(parse(r#""joyUp""#).unwrap(), Action::JoyUp);
+        assert!(parse(r#""jump""#).is_err());
+    }
+
+    #[test]
+    fn header_custom_code() {
+        let ports = |code| {
+            format!(
+                r#"[{{ "type": "b", "bit": {{ "action": {{ "custom": {code} }}, "activeLow": true }} }}]"#
+            )
+        };
+
+        let config = build_config(&platform("sm510", &ports(30)), &options()).unwrap();
+        assert_eq!(config[B_PORT_OFFSET], 0x9E);
+
+        assert!(build_config(&platform("sm510", &ports(0x7F)), &options()).is_err());
+    }
+
+    #[test]
+    fn header_default_active_low() {
+        let ports = r#"[{ "type": "b", "bit": { "action": "button1", "activeLow": true } }]"#;
+
+        let config = build_config(&platform("sm510", "[]"), &options()).unwrap();
+        assert_eq!(config[B_PORT_OFFSET..B_PORT_OFFSET + 2], [0xFF, 0xFF]);
+
+        let mut platform = platform("sm510", ports);
+        platform.port_map.default_active_low = Some(false);
+        let config = build_config(&platform, &options()).unwrap();
+        // Only the unset BA takes the default
+        assert_eq!(config[B_PORT_OFFSET..B_PORT_OFFSET + 2], [0x84, 0x7F]);
+    }
+
+    #[test]
+    fn duplicate_and_out_of_range_ports() {
+        let ports = r#"[
+            { "type": "s", "index": 2, "bitmap": [null, null, null, null] },
+            { "type": "s", "index": 2, "bitmap": [null, null, null, null] },
+            { "type": "s", "index": 8, "bitmap": [null, null, null, null] },
+            { "type": "b", "bit": null },
+            { "type": "ba", "bit": null },
+            { "type": "b", "bit": null }
+        ]"#;
+        let platform = platform("sm510", ports);
+
+        assert_eq!(
+            platform.port_map.validate(&platform.device.cpu),
+            [
+                "S port 2 is mapped more than once",
+                "Port index 8 is out of bounds for a device with 8 S ports",
+                "Port B is declared more than once",
+            ]
+        );
+        assert!(build_config(&platform, &options()).is_err());
+    }
+
+    #[test]
+    fn mask_entry_packing() {
+        // Each field is 10 bits, packed LSB first: id, start x, y, then length
+        assert_eq!(
+            entry_to_bytes(MaskEntryFormat::Compact, 1, 4, 2, 3),
+            [0x01, 0x08, 0x30, 0x00, 0x01]
+        );
+        assert_eq!(
+            entry_to_bytes(MaskEntryFormat::Compact, 0x3FF, 0x3FF, 0x3FF, 0x3FF),
+            [0xFF; 5]
+        );
+        assert_eq!(
+            entry_to_bytes(MaskEntryFormat::Compact, 0, 0x3FF, 0, 0),
+            [0x00, 0x00, 0x00, 0xC0, 0xFF]
+        );
+    }
+
+    #[test]
+    fn mask_wide_ids() {
+        // The id takes 16 bits, then x, y, and length follow as in the compact format
+        assert_eq!(
+            entry_to_bytes(MaskEntryFormat::Wide, 0x1234, 3, 2, 4),
+            [0x34, 0x12, 0x02, 0x10, 0x30, 0x00]
+        );
+
+        let pixels = [None, Some(0x400), Some(0x400), Some(0x3FF)];
+        assert_eq!(MaskEntryFormat::for_ids(&pixels), MaskEntryFormat::Wide);
+        assert_eq!(
+            MaskEntryFormat::for_ids(&pixels[..1]),
+            MaskEntryFormat::Compact
+        );
+
+        let resolution = Resolution {
+            width: 4,
+            height: 1,
+        };
+        assert!(build_mask_map("test", &pixels, resolution, MaskEntryFormat::Compact).is_err());
+
+        let mask = build_mask_map("test", &pixels, resolution, MaskEntryFormat::Wide).unwrap();
+        assert_eq!(
+            decode::mask_entries(&mask, MaskEntryFormat::Wide),
+            [
+                decode::MaskEntry {
+                    id: 0x400,
+                    x: 1,
+                    y: 0,
+                    length: 2
+                },
+                decode::MaskEntry {
+                    id: 0x3FF,
+                    x: 3,
+                    y: 0,
+                    length: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn mask_long_runs_are_split() {
+        let resolution = Resolution {
+            width: 2000,
+            height: 1,
+        };
+        let mask = build_mask_map(
+            "test",
+            &[Some(5); 2000],
+            resolution,
+            MaskEntryFormat::Compact,
+        )
+        .unwrap();
+
+        assert_eq!(
+            mask,
+            [
+                entry_to_bytes(MaskEntryFormat::Compact, 5, 1023, 0, 0),
+                entry_to_bytes(MaskEntryFormat::Compact, 5, 977, 1023, 0),
+            ]
+            .concat()
+        );
+
+        // The third entry of a longer run would start past the last x coordinate
+        let resolution = Resolution {
+            width: 2500,
+            height: 1,
+        };
+        assert!(build_mask_map(
+            "test",
+            &[Some(5); 2500],
+            resolution,
+            MaskEntryFormat::Compact
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn paletted_image_block() {
+        let mut background = Pixmap::new(64, 4).unwrap();
+        let mut mask = Pixmap::new(64, 4).unwrap();
+
+        for (i, (pixel, mask)) in background
+            .data_mut()
+            .chunks_exact_mut(4)
+            .zip(mask.data_mut().chunks_exact_mut(4))
+            .enumerate()
+        {
+            pixel.copy_from_slice(&[(i % 64 * 4) as u8, 0x80, (i / 64 * 60) as u8, 0xFF]);
+            mask.copy_from_slice(&[i as u8, 1, 2, 0xFF]);
+        }
+
+        // Every pixel is a different color, so they're quantized
+        let (block, palette_size) = build_paletted_image_block(&background, &mask, 16);
+        assert!((2..=16).contains(&palette_size));
+        assert_eq!(block.len(), palette_size * 3 + 64 * 4 * 4);
+
+        let pixels = &block[palette_size * 3..];
+        assert!(pixels
+            .chunks_exact(4)
+            .all(|pixel| (pixel[0] as usize) < palette_size));
+        // The mask keeps its full color
+        assert_eq!(&pixels[4 * 5 + 1..4 * 6], &[5, 1, 2]);
+
+        // A background with few enough colors keeps them exactly
+        let mut flat = Pixmap::new(64, 4).unwrap();
+        for (i, pixel) in flat.data_mut().chunks_exact_mut(4).enumerate() {
+            pixel.copy_from_slice(&[if i % 2 == 0 { 0x10 } else { 0xE0 }, 0x20, 0x30, 0xFF]);
+        }
+
+        let (block, palette_size) = build_paletted_image_block(&flat, &mask, 16);
+        assert_eq!(palette_size, 2);
+        assert_eq!(&block[..6], &[0x10, 0x20, 0x30, 0xE0, 0x20, 0x30]);
+        assert_eq!(&block[6..14], &[0, 0, 1, 2, 1, 1, 1, 2]);
+    }
+
+    #[test]
+    fn verify_rejects_bad_files() {
+        let platform = platform("sm510", "[]");
+        let path = env::temp_dir().join(format!("gnw_verify_{}.gnw", std::process::id()));
+
+        assert!(verify(&path, &platform, &options())
+            .unwrap_err()
+            .starts_with("Could not read back"));
+
+        fs::write(&path, b"not a rom").unwrap();
+        assert!(verify(&path, &platform, &options())
+            .unwrap_err()
+            .starts_with("Not a Game & Watch ROM"));
+
+        // A config that claims more images than the file holds
+        let mut data = build_config(&platform, &options()).unwrap();
+        data[IMAGE_LENGTH_OFFSET..IMAGE_LENGTH_OFFSET + 4].copy_from_slice(&0x100u32.to_le_bytes());
+        fs::write(&path, &data).unwrap();
+        assert!(verify(&path, &platform, &options()).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn find_rom_by_hash_skips_directories() {
+        let asset_dir = env::temp_dir().join(format!("gnw_find_rom_{}", std::process::id()));
+        fs::create_dir_all(asset_dir.join("debug")).unwrap();
+        fs::write(asset_dir.join("debug").join("rom.bin"), b"nested").unwrap();
+        fs::write(asset_dir.join(CACHE_MARKER), b"marker").unwrap();
+        fs::write(asset_dir.join("rom.bin"), b"program").unwrap();
+
+        let hash = |data: &[u8]| hex::encode(Sha1::digest(data));
+
+        assert_eq!(
+            find_rom_by_hash(&hash(b"program"), &asset_dir).unwrap(),
+            b"program"
+        );
+        // Manifest hashes match whatever their case
+        assert_eq!(
+            find_rom_by_hash(&hash(b"program").to_uppercase(), &asset_dir).unwrap(),
+            b"program"
+        );
+        // Neither the files in subdirectories nor the cache marker are candidates
+        assert!(find_rom_by_hash(&hash(b"nested"), &asset_dir).is_err());
+        assert!(find_rom_by_hash(&hash(b"marker"), &asset_dir).is_err());
+
+        fs::remove_dir_all(&asset_dir).unwrap();
+    }
+
+    #[test]
+    fn find_rom_by_hash_remembers_hashes() {
+        let asset_dir = env::temp_dir().join(format!("gnw_hash_cache_{}", std::process::id()));
+        fs::create_dir_all(&asset_dir).unwrap();
+        fs::write(asset_dir.join("a.bin"), b"first").unwrap();
+        fs::write(asset_dir.join("b.bin"), b"second").unwrap();
+
+        let hash = |data: &[u8]| hex::encode(Sha1::digest(data));
+
+        assert!(matches!(
+            find_rom_by_hash(&hash(b"missing"), &asset_dir),
+            Err(BuildError::AssetMissing(_))
+        ));
+
+        let cached = read_hash_cache(&asset_dir.join(HASH_CACHE));
+        let mut names = cached
+            .iter()
+            .map(|file| file.name.as_str())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a.bin", "b.bin"]);
+
+        // A remembered hash that no longer describes the file doesn't match it
+        let stale = cached
+            .into_iter()
+            .map(|file| HashedFile {
+                sha1: hash(b"stale"),
+                ..file
+            })
+            .collect::<Vec<_>>();
+        write_hash_cache(&asset_dir.join(HASH_CACHE), &stale).unwrap();
+
+        assert!(find_rom_by_hash(&hash(b"stale"), &asset_dir).is_err());
+
+        // A changed file is hashed again
+        fs::write(asset_dir.join("b.bin"), b"replaced").unwrap();
+        assert_eq!(
+            find_rom_by_hash(&hash(b"replaced"), &asset_dir).unwrap(),
+            b"replaced"
+        );
+
+        fs::remove_dir_all(&asset_dir).unwrap();
+    }
+
+    #[test]
+    fn validate_input_capabilities() {
+        let port = |index: u8, action: &str| {
+            format!(
+                r#"[{{ "type": "s", "index": {index}, "bitmap": [{{ "action": "{action}", "activeLow": false }}, null, null, null] }}]"#
+            )
+        };
+        let strict = EncodeOptions {
+            strict: true,
+            ..options()
+        };
+
+        // A second joystick is only read on devices that declare one
+        let mut joystick = platform("sm510", &port(1, "rightJoyUp"));
+        assert_eq!(validate(&joystick, &options()).unwrap().len(), 1);
+        assert!(validate(&joystick, &strict).is_err());
+        joystick.device.joysticks = Some(2);
+        assert!(validate(&joystick, &strict).unwrap().is_empty());
+
+        // SM5A units only have 3 S ports the core reads
+        let sm5a = platform("sm5a", &port(3, "button1"));
+        assert_eq!(validate(&sm5a, &options()).unwrap().len(), 1);
+        assert!(validate(&sm5a, &strict).is_err());
+        assert!(validate(&platform("sm510", &port(3, "button1")), &strict)
+            .unwrap()
+            .is_empty());
+    }
+}
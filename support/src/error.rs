@@ -0,0 +1,59 @@
+use std::io;
+
+use thiserror::Error;
+
+///
+/// Why building a device failed. Each variant displays the same message the command line tool prints, so callers can
+/// match on the cause without losing the details
+///
+#[derive(Debug, Error)]
+pub enum BuildError {
+    /// An archive or file expected in the MAME directory doesn't exist
+    #[error("{0}")]
+    AssetMissing(String),
+    /// An archive exists, but couldn't be read or extracted
+    #[error("{0}")]
+    CorruptArchive(String),
+    /// A missing asset couldn't be downloaded with `--fetch-base` or `--asset-url-template`
+    #[error("{0}")]
+    FetchFailed(String),
+    /// The layout, or the requested view within it, doesn't exist
+    #[error("{0}")]
+    LayoutNotFound(String),
+    /// The layout exists, but can't be drawn or doesn't fit the format, such as a resolution too large for its fields
+    #[error("{0}")]
+    InvalidLayout(String),
+    /// The game's manifest entry describes something the format can't encode
+    #[error("{0}")]
+    InvalidManifest(String),
+    #[error("ROM {name} has SHA1 {actual}, but the manifest expects {expected}. Pass --skip-hash-check to use it anyway")]
+    HashMismatch {
+        name: String,
+        actual: String,
+        expected: String,
+    },
+    /// The mask ids or coordinates don't fit in the format's fields
+    #[error("{0}")]
+    MaskOverflow(String),
+    /// The ROM isn't the size of the CPU's internal ROM
+    #[error("{0}")]
+    RomSizeMismatch(String),
+    #[error("{message}: {source}")]
+    Io {
+        message: String,
+        #[source]
+        source: io::Error,
+    },
+    /// Anything without a more specific variant, such as a contained panic or a failed `--verify`
+    #[error("{0}")]
+    Other(String),
+}
+
+impl BuildError {
+    pub fn io(message: impl Into<String>, source: io::Error) -> Self {
+        BuildError::Io {
+            message: message.into(),
+            source,
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    assets::{artwork_archive, extract_path, rom_archive},
+    encode_format::find_rom_by_hash,
+    manifest::{PlatformSpecification, Screen},
+    render::screen_filename,
+    task_log::logln,
+};
+
+const ATTEMPTS: u32 = 3;
+
+/// The extensions a fetched archive may be cached under
+const ARCHIVE_EXTENSIONS: [&str; 2] = ["zip", "7z"];
+
+const SEVEN_ZIP_MAGIC: &[u8] = b"7z\xBC\xAF\x27\x1C";
+
+///
+/// Downloads assets that are missing from a user's MAME set from a user provided base URL or archive URL template.
+/// This is strictly opt-in, and ROMs are only ever fetched for homebrew titles
+///
+pub struct Fetcher {
+    base_url: Option<String>,
+    archive_template: Option<String>,
+    cache_dir: PathBuf,
+}
+
+impl Fetcher {
+    pub fn new(
+        base_url: Option<&String>,
+        archive_template: Option<&String>,
+        cache_dir: PathBuf,
+    ) -> Self {
+        Fetcher {
+            base_url: base_url.map(|base_url| base_url.trim_end_matches('/').to_string()),
+            archive_template: archive_template.cloned(),
+            cache_dir,
+        }
+    }
+
+    pub fn fetches_archives(&self) -> bool {
+        self.archive_template.is_some()
+    }
+
+    ///
+    /// Extracts the device's artwork and ROM archives into `asset_dir`, downloading whichever aren't in the MAME
+    /// directory from the archive URL template. A downloaded ROM archive must contain a ROM matching the manifest hash
+    ///
+    #[allow(clippy::diverging_sub_expression)]
+    pub fn fetch_archives(
+        &self,
+        platform_name: &str,
+        platform: &PlatformSpecification,
+        mame_path: &Path,
+        asset_dir: &Path,
+    ) -> Result<(), String> {
+        guard!(let Some(template) = &self.archive_template else {
+            return Ok(());
+        });
+
+        let artwork_path = artwork_archive(platform_name, mame_path);
+
+        if artwork_path.exists() {
+            extract_path(&artwork_path, asset_dir, "artwork").map_err(|err| err.to_string())?;
+        } else {
+            self.fetch_archive(template, "artwork", platform_name, asset_dir)?;
+        }
+
+        let rom_name = platform.rom.rom_owner.as_deref().unwrap_or(platform_name);
+        let rom_path = rom_archive(rom_name, mame_path);
+
+        if rom_path.exists() {
+            return extract_path(&rom_path, asset_dir, "ROM").map_err(|err| err.to_string());
+        }
+
+        if platform.metadata.company.to_lowercase() != "homebrew" {
+            // Never fetch commercial ROMs
+            return Err(format!(
+                "Could not find ROM archive at {rom_path:?}, and not fetching it as {platform_name} is not homebrew"
+            ));
+        }
+
+        let cache_path = self.fetch_archive(template, "roms", rom_name, asset_dir)?;
+
+        if find_rom_by_hash(&platform.rom.rom_hash, asset_dir).is_err() {
+            // Don't keep serving the bad archive from the cache
+            let _ = fs::remove_file(&cache_path);
+
+            return Err(format!(
+                "Fetched ROM archive for {rom_name} does not contain a ROM matching hash {}",
+                platform.rom.rom_hash
+            ));
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Downloads (or reuses the cached copy of) the archive named `name` in MAME's `dir` directory, and extracts it into
+    /// `asset_dir`. Returns the path of the cached archive
+    ///
+    fn fetch_archive(
+        &self,
+        template: &str,
+        dir: &str,
+        name: &str,
+        asset_dir: &Path,
+    ) -> Result<PathBuf, String> {
+        let cache_dir = self.cache_dir.join("archives").join(dir);
+
+        let cached = ARCHIVE_EXTENSIONS
+            .iter()
+            .map(|extension| cache_dir.join(format!("{name}.{extension}")))
+            .find(|path| path.exists());
+
+        let cache_path = match cached {
+            Some(cache_path) => cache_path,
+            None => {
+                let url = template.replace("{dir}", dir).replace("{name}", name);
+                let data = download(&url)?;
+
+                logln!("Fetched {url}");
+
+                fs::create_dir_all(&cache_dir)
+                    .map_err(|err| format!("Could not create fetch cache {cache_dir:?}: {err}"))?;
+
+                // Extraction picks the format by extension
+                let cache_path = cache_dir.join(format!("{name}.{}", archive_extension(&data)));
+
+                write_file(&cache_path, &data)?;
+
+                cache_path
+            }
+        };
+
+        if let Err(err) = extract_path(&cache_path, asset_dir, dir) {
+            let _ = fs::remove_file(&cache_path);
+
+            return Err(err.to_string());
+        }
+
+        Ok(cache_path)
+    }
+
+    ///
+    /// Fetch any screen SVGs and ROM missing from the extracted assets in `asset_dir`
+    ///
+    #[allow(clippy::diverging_sub_expression)]
+    pub fn fetch_missing(
+        &self,
+        platform_name: &str,
+        platform: &PlatformSpecification,
+        asset_dir: &Path,
+    ) -> Result<(), String> {
+        guard!(let Some(base_url) = &self.base_url else {
+            return Ok(());
+        });
+
+        let screen_count = match platform.device.screen {
+            Screen::Single { .. } => 1,
+            Screen::DualVertical { .. } | Screen::DualHorizontal { .. } => 2,
+        };
+
+        for index in 0..screen_count {
+            let file_name = screen_filename(index, platform_name, &platform.device);
+
+            let parent_exists = platform.rom.rom_owner.as_ref().is_some_and(|parent| {
+                asset_dir
+                    .join(screen_filename(index, parent, &platform.device))
+                    .exists()
+            });
+
+            if asset_dir.join(&file_name).exists() || parent_exists {
+                continue;
+            }
+
+            // There's no declared hash for artwork
+            self.fetch(
+                base_url,
+                platform_name,
+                &[&file_name],
+                None,
+                &asset_dir.join(&file_name),
+            )?;
+        }
+
+        let rom_path = asset_dir.join(&platform.rom.rom);
+
+        if !rom_path.exists() {
+            if platform.metadata.company.to_lowercase() != "homebrew" {
+                // Never fetch commercial ROMs
+                logln!(
+                    "Not fetching ROM {} as {platform_name} is not homebrew",
+                    platform.rom.rom
+                );
+                return Ok(());
+            }
+
+            self.fetch(
+                base_url,
+                platform_name,
+                &[&platform.rom.rom, &platform.rom.rom_hash],
+                Some(&platform.rom.rom_hash),
+                &rom_path,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Downloads the first available of `file_names` to `destination`, rejecting it if it does not match
+    /// `expected_hash`. Successful downloads are cached by game so they aren't fetched again
+    ///
+    fn fetch(
+        &self,
+        base_url: &str,
+        platform_name: &str,
+        file_names: &[&String],
+        expected_hash: Option<&String>,
+        destination: &Path,
+    ) -> Result<(), String> {
+        let cache_path = self.cache_dir.join(platform_name).join(file_names[0]);
+
+        if let Ok(data) = fs::read(&cache_path) {
+            if matches_hash(&data, expected_hash) {
+                return write_file(destination, &data);
+            }
+
+            logln!("Cached {cache_path:?} does not match its hash. Refetching");
+        }
+
+        let mut errors = vec![];
+
+        for file_name in file_names {
+            let url = format!("{base_url}/{file_name}");
+
+            let data = match download(&url) {
+                Ok(data) => data,
+                Err(err) => {
+                    errors.push(err);
+                    continue;
+                }
+            };
+
+            if !matches_hash(&data, expected_hash) {
+                return Err(format!(
+                    "Downloaded {url} does not match expected hash {}",
+                    expected_hash.map_or("", |hash| hash)
+                ));
+            }
+
+            logln!("Fetched {url}");
+
+            if let Some(parent) = cache_path.parent() {
+                if fs::create_dir_all(parent).is_ok() && fs::write(&cache_path, &data).is_err() {
+                    logln!("Could not cache {url} at {cache_path:?}");
+                }
+            }
+
+            return write_file(destination, &data);
+        }
+
+        Err(format!(
+            "Could not fetch {}\n{}",
+            file_names[0],
+            errors.join("\n")
+        ))
+    }
+}
+
+fn download(url: &str) -> Result<Vec<u8>, String> {
+    let mut last_error = String::new();
+
+    for attempt in 1..=ATTEMPTS {
+        match ureq::get(url).call() {
+            Ok(response) => {
+                let mut data = vec![];
+
+                match response.into_reader().read_to_end(&mut data) {
+                    Ok(_) => return Ok(data),
+                    Err(err) => last_error = format!("Could not read {url}: {err}"),
+                }
+            }
+            Err(ureq::Error::Status(status, _)) if status < 500 => {
+                // Retrying won't change anything
+                return Err(format!("Could not fetch {url}: HTTP {status}"));
+            }
+            Err(err) => last_error = format!("Could not fetch {url}: {err}"),
+        }
+
+        if attempt < ATTEMPTS {
+            thread::sleep(Duration::from_secs(1 << (attempt - 1)));
+        }
+    }
+
+    Err(last_error)
+}
+
+#[allow(clippy::diverging_sub_expression)]
+fn matches_hash(data: &[u8], expected_hash: Option<&String>) -> bool {
+    guard!(let Some(expected_hash) = expected_hash else {
+        return true;
+    });
+
+    hex::encode(Sha1::digest(data)) == expected_hash.to_lowercase()
+}
+
+fn write_file(path: &Path, data: &[u8]) -> Result<(), String> {
+    fs::write(path, data).map_err(|err| format!("Could not write fetched asset {path:?}: {err}"))
+}
+
+///
+/// The extension a downloaded archive is cached under, chosen from its contents as the URL doesn't have to end in one.
+/// Anything that isn't a 7z is treated as a zip
+///
+fn archive_extension(data: &[u8]) -> &'static str {
+    if data.starts_with(SEVEN_ZIP_MAGIC) {
+        "7z"
+    } else {
+        "zip"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_extension_from_magic() {
+        assert_eq!(archive_extension(b"7z\xBC\xAF\x27\x1C\x00\x04"), "7z");
+        assert_eq!(archive_extension(b"PK\x03\x04"), "zip");
+        assert_eq!(archive_extension(b""), "zip");
+    }
+}
@@ -0,0 +1,267 @@
+use std::{collections::HashMap, fs, path::Path, process};
+
+use colored::Colorize;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::glob_match;
+
+#[derive(Debug, Deserialize)]
+struct MameList {
+    #[serde(rename = "machine", default)]
+    machines: Vec<Machine>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Machine {
+    name: String,
+    cloneof: Option<String>,
+    description: String,
+    year: Option<String>,
+    manufacturer: Option<String>,
+    #[serde(rename = "rom", default)]
+    roms: Vec<Rom>,
+    #[serde(rename = "chip", default)]
+    chips: Vec<Chip>,
+    #[serde(rename = "display", default)]
+    displays: Vec<Display>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Rom {
+    name: String,
+    region: Option<String>,
+    sha1: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Chip {
+    #[serde(rename = "type")]
+    chip_type: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Display {
+    tag: String,
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+///
+/// Generates manifest entries for every machine matching `pattern` in MAME's `-listxml` output, read from `xml_path`
+/// or by running `mame_binary`, and writes them to `output_path` as JSON. Port maps can't be derived from the XML, so
+/// each entry's is left empty to be filled in by hand
+///
+pub fn generate(
+    xml_path: Option<&Path>,
+    mame_binary: &Path,
+    pattern: &str,
+    output_path: &Path,
+) -> Result<(), String> {
+    let xml = match xml_path {
+        Some(path) => fs::read(path).map_err(|err| format!("Could not read {path:?}: {err}"))?,
+        None => {
+            let output = process::Command::new(mame_binary)
+                .args(["-listxml", pattern])
+                .output()
+                .map_err(|err| format!("Could not run {mame_binary:?}: {err}"))?;
+
+            if !output.status.success() {
+                return Err(format!(
+                    "{mame_binary:?} -listxml failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+
+            output.stdout
+        }
+    };
+
+    let list: MameList = serde_xml_rs::from_reader(xml.as_slice())
+        .map_err(|err| format!("Could not parse MAME machine list: {err}"))?;
+
+    let mut entries = Map::new();
+    // The first game with each ROM hash owns the ROM, like the manifest extractor
+    let mut rom_owners: HashMap<String, String> = HashMap::new();
+
+    for machine in list
+        .machines
+        .iter()
+        .filter(|machine| glob_match(pattern, &machine.name))
+    {
+        match platform_entry(machine, &mut rom_owners) {
+            Ok(entry) => {
+                entries.insert(machine.name.clone(), entry);
+            }
+            Err(err) => println!(
+                "{}",
+                format!("WARNING: Skipping {}: {err}", machine.name).yellow()
+            ),
+        }
+    }
+
+    if entries.is_empty() {
+        return Err(format!("No supported machines match {pattern:?}"));
+    }
+
+    let count = entries.len();
+
+    let json = serde_json::to_string_pretty(&Value::Object(entries))
+        .map_err(|err| format!("Could not serialize manifest: {err}"))?;
+
+    fs::write(output_path, json + "\n")
+        .map_err(|err| format!("Could not write {output_path:?}: {err}"))?;
+
+    println!("Wrote {count} games to {output_path:?}");
+
+    Ok(())
+}
+
+///
+/// Builds the manifest entry of a single machine, recording it in `rom_owners`. Errors if its CPU or screens aren't
+/// supported, or it has no program ROM
+///
+#[allow(clippy::diverging_sub_expression)]
+fn platform_entry(
+    machine: &Machine,
+    rom_owners: &mut HashMap<String, String>,
+) -> Result<Value, String> {
+    let cpu = cpu_type(machine)?;
+    let screen = screen(machine)?;
+
+    let region_rom = |region: &str| {
+        machine
+            .roms
+            .iter()
+            .find(|rom| rom.region.as_deref() == Some(region) && rom.sha1.is_some())
+    };
+
+    guard!(let Some(program) = region_rom("maincpu") else {
+        return Err("No program ROM in the maincpu region".to_string());
+    });
+
+    let rom_hash = program.sha1.clone().unwrap_or_default();
+
+    let mut rom = json!({
+        "rom": program.name,
+        "romHash": rom_hash,
+    });
+
+    if let Some(melody) = region_rom("maincpu:melody") {
+        rom["melody"] = json!(melody.name);
+        rom["melodyHash"] = json!(melody.sha1);
+    }
+
+    match rom_owners.get(&rom_hash) {
+        Some(owner) => rom["romOwner"] = json!(owner),
+        None => {
+            rom_owners.insert(rom_hash, machine.name.clone());
+        }
+    }
+
+    if let Some(parent) = &machine.cloneof {
+        rom["parent"] = json!(parent);
+    }
+
+    Ok(json!({
+        "device": {
+            "cpu": cpu,
+            "screen": screen,
+        },
+        "portMap": {
+            "ports": [],
+        },
+        "metadata": {
+            "year": machine.year.clone().unwrap_or_else(|| "????".to_string()),
+            "name": machine.description,
+            "company": machine.manufacturer.clone().unwrap_or_default(),
+        },
+        "rom": rom,
+    }))
+}
+
+///
+/// The manifest's name for the machine's main CPU. MAME uses the same device for Tiger's variants of the SM510 and
+/// SM511, so those are chosen by manufacturer, with Tiger's SM511 games assumed to use 1-bit sound
+///
+#[allow(clippy::diverging_sub_expression)]
+fn cpu_type(machine: &Machine) -> Result<&'static str, String> {
+    guard!(let Some(chip) = machine.chips.iter().find(|chip| chip.chip_type == "cpu") else {
+        return Err("No CPU".to_string());
+    });
+
+    let name = chip
+        .name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+
+    let tiger = machine
+        .manufacturer
+        .as_deref()
+        .is_some_and(|manufacturer| manufacturer.to_lowercase().contains("tiger"));
+
+    let cpu = if name.ends_with("kb1013vk12") {
+        "kb1013vk12"
+    } else if name.ends_with("sm5a") {
+        "sm5a"
+    } else if name.ends_with("sm510") {
+        if tiger {
+            "sm510_tiger"
+        } else {
+            "sm510"
+        }
+    } else if name.ends_with("sm511") {
+        if tiger {
+            "sm511_tiger1bit"
+        } else {
+            "sm511"
+        }
+    } else if name.ends_with("sm512") {
+        "sm512"
+    } else if name.ends_with("sm530") {
+        "sm530"
+    } else {
+        return Err(format!("Unsupported CPU {}", chip.name));
+    };
+
+    Ok(cpu)
+}
+
+///
+/// The manifest's screen for the machine's SVG displays, which are laid out by their tags
+///
+fn screen(machine: &Machine) -> Result<Value, String> {
+    let size = |tag: &str| {
+        machine
+            .displays
+            .iter()
+            .find(|display| display.tag.trim_start_matches(':') == tag)
+            .and_then(|display| Some(json!({ "width": display.width?, "height": display.height? })))
+    };
+
+    if let Some(mut single) = size("screen") {
+        single["type"] = json!("single");
+
+        return Ok(single);
+    }
+
+    if let (Some(top), Some(bottom)) = (size("screen_top"), size("screen_bottom")) {
+        return Ok(json!({ "type": "dualVertical", "top": top, "bottom": bottom }));
+    }
+
+    if let (Some(left), Some(right)) = (size("screen_left"), size("screen_right")) {
+        return Ok(json!({ "type": "dualHorizontal", "left": left, "right": right }));
+    }
+
+    Err(format!(
+        "Unrecognized screens {:?}",
+        machine
+            .displays
+            .iter()
+            .map(|display| display.tag.as_str())
+            .collect::<Vec<_>>()
+    ))
+}
@@ -0,0 +1,92 @@
+use std::{fmt::Write, fs, path::Path};
+
+use crate::{
+    encode_format::input_value_for_port,
+    error::BuildError,
+    manifest::{NamedAction, PlatformSpecification, Port},
+};
+
+///
+/// Writes the platform's port map to `export_dir` in the form of MAME's `INPUT_PORTS` definitions, so it can be
+/// compared against the device's driver. Each bit is annotated with the config byte it's encoded as
+///
+pub fn export_inputs(
+    platform_name: &str,
+    platform: &PlatformSpecification,
+    export_dir: &Path,
+) -> Result<(), BuildError> {
+    let mut output = String::new();
+
+    // Writing to a String can't fail
+    writeln!(
+        output,
+        "// {} ({platform_name}), {:?}\n",
+        platform.metadata.display_name(),
+        platform.device.cpu
+    )
+    .unwrap();
+
+    for port in &platform.port_map.ports {
+        match port {
+            Port::S { index, bitmap } => {
+                writeln!(output, "PORT_START(\"IN.{index}\") // S{}", index + 1).unwrap();
+
+                for (bit, action) in bitmap.iter().enumerate() {
+                    write_bit(&mut output, 1 << bit, action.as_ref());
+                }
+            }
+            Port::Acl { bit, .. } => {
+                writeln!(output, "PORT_START(\"ACL\")").unwrap();
+                write_bit(&mut output, 0x1, bit.as_ref());
+            }
+            Port::B { bit } => {
+                writeln!(output, "PORT_START(\"B\")").unwrap();
+                write_bit(&mut output, 0x1, bit.as_ref());
+            }
+            Port::BA { bit } => {
+                writeln!(output, "PORT_START(\"BA\")").unwrap();
+                write_bit(&mut output, 0x1, bit.as_ref());
+            }
+        }
+
+        output.push('\n');
+    }
+
+    fs::create_dir_all(export_dir).map_err(|err| {
+        BuildError::io(
+            format!("Could not create input export directory {export_dir:?}"),
+            err,
+        )
+    })?;
+
+    let path = export_dir.join(format!("{platform_name}.txt"));
+
+    fs::write(&path, output)
+        .map_err(|err| BuildError::io(format!("Could not write inputs to {path:?}"), err))
+}
+
+fn write_bit(output: &mut String, mask: u8, action: Option<&NamedAction>) {
+    guard!(let Some(action) = action else {
+        writeln!(output, "\tPORT_BIT( 0x{mask:02x}, IP_ACTIVE_HIGH, IPT_UNUSED )").unwrap();
+        return;
+    });
+
+    let active = if action.active_low {
+        "IP_ACTIVE_LOW"
+    } else {
+        "IP_ACTIVE_HIGH"
+    };
+
+    let name = action
+        .name
+        .as_ref()
+        .map_or(String::new(), |name| format!(" PORT_NAME(\"{name}\")"));
+
+    writeln!(
+        output,
+        "\tPORT_BIT( 0x{mask:02x}, {active}, {} ){name} // config 0x{:02x}",
+        action.action.mame_input_type(),
+        input_value_for_port(action.clone())
+    )
+    .unwrap();
+}
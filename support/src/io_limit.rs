@@ -0,0 +1,117 @@
+use std::{
+    fs,
+    path::Path,
+    sync::{Condvar, Mutex},
+    thread,
+};
+
+/// Concurrent I/O operations allowed against network storage, which is easily saturated
+const NETWORK_LIMIT: usize = 2;
+
+/// Filesystem types (as reported by `/proc/mounts`) treated as network storage
+const NETWORK_FILESYSTEMS: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "9p",
+    "afs",
+    "ceph",
+    "glusterfs",
+    "davfs",
+    "fuse.sshfs",
+    "fuse.rclone",
+];
+
+///
+/// Limits the number of concurrent I/O operations against the user's MAME directory, independently of how many
+/// devices are being processed at once
+///
+pub struct IoLimiter {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Returns its slot to the `IoLimiter` when dropped
+pub struct IoPermit<'a> {
+    limiter: &'a IoLimiter,
+}
+
+impl IoLimiter {
+    pub fn new(limit: usize) -> Self {
+        IoLimiter {
+            available: Mutex::new(limit.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until an I/O slot is available
+    pub fn acquire(&self) -> IoPermit<'_> {
+        let mut available = self.available.lock().unwrap();
+
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+
+        *available -= 1;
+
+        IoPermit { limiter: self }
+    }
+}
+
+impl Drop for IoPermit<'_> {
+    fn drop(&mut self) {
+        *self.limiter.available.lock().unwrap() += 1;
+        self.limiter.released.notify_one();
+    }
+}
+
+///
+/// The default I/O limit for `path`. Network storage gets a conservative limit, and local disks one per logical CPU
+///
+pub fn default_limit(path: &Path) -> usize {
+    if is_network_path(path) {
+        NETWORK_LIMIT
+    } else {
+        thread::available_parallelism().map_or(1, |count| count.get())
+    }
+}
+
+///
+/// Heuristically detects whether `path` is on network storage, by UNC prefix on Windows and by the filesystem type
+/// of its mount point on Linux. Anything undetectable is considered local
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn is_network_path(path: &Path) -> bool {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let path_string = path.to_string_lossy();
+
+    // UNC shares, which canonicalize to `\\?\UNC\`
+    if path_string.starts_with(r"\\?\UNC\")
+        || (path_string.starts_with(r"\\") && !path_string.starts_with(r"\\?\"))
+    {
+        return true;
+    }
+
+    guard!(let Ok(mounts) = fs::read_to_string("/proc/mounts") else {
+        return false;
+    });
+
+    // The longest mount point containing the path is the one it's on
+    let mount_type = mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            let mount_point = fields.next()?;
+            let mount_type = fields.next()?;
+
+            Some((mount_point.replace("\\040", " "), mount_type))
+        })
+        .filter(|(mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.len())
+        .map(|(_, mount_type)| mount_type);
+
+    mount_type.is_some_and(|mount_type| NETWORK_FILESYSTEMS.contains(&mount_type))
+}
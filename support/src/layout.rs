@@ -1,11 +1,13 @@
-use std::{collections::HashMap, fs, path::Path};
+use std::{fs, path::Path};
 
 use serde::Deserialize;
 
-use serde_xml_rs;
+use crate::{error::BuildError, task_log::logln};
 
 #[derive(Debug, Deserialize)]
 pub struct MameLayout {
+    /// Layouts for a PNG background may have no artwork elements at all
+    #[serde(default)]
     pub element: Vec<NameElement>,
     pub view: Vec<View>,
 }
@@ -49,14 +51,6 @@ pub enum ViewElement {
     Screen(Screen),
 }
 
-#[derive(Clone, Debug, Deserialize)]
-pub struct XYBounds {
-    pub x: i32,
-    pub y: i32,
-    pub width: i32,
-    pub height: i32,
-}
-
 #[derive(Clone, Debug, Deserialize)]
 pub struct CompleteBounds {
     // Standard XY
@@ -105,8 +99,8 @@ impl CompleteBounds {
             Bounds {
                 x: xc - width,
                 y: yc - height,
-                width: width,
-                height: height,
+                width,
+                height,
             }
         } else if let (Some(left), Some(right), Some(top), Some(bottom)) =
             (self.left, self.right, self.top, self.bottom)
@@ -130,6 +124,28 @@ pub struct Element {
     pub ref_name: String,
     pub bounds: CompleteBounds,
     pub blend: Option<BlendType>,
+    /// The MAME input port (`IN.0`, `ACL`, etc.) this element triggers when clicked
+    #[serde(rename = "inputtag")]
+    pub input_tag: Option<String>,
+    /// The bits of `input_tag` this element triggers
+    #[serde(rename = "inputmask")]
+    pub input_mask: Option<String>,
+}
+
+impl Element {
+    /// The input port tag and bit mask of this element, if it represents a physical input
+    pub fn input(&self) -> Option<(&str, u32)> {
+        let tag = self.input_tag.as_ref()?.trim().trim_start_matches(':');
+        let mask = self.input_mask.as_ref()?.trim();
+
+        let mask = if let Some(hex) = mask.strip_prefix("0x").or_else(|| mask.strip_prefix("0X")) {
+            u32::from_str_radix(hex, 16).ok()?
+        } else {
+            mask.parse::<u32>().ok()?
+        };
+
+        Some((tag, mask))
+    }
 }
 
 #[derive(Clone, PartialEq, Debug, Deserialize)]
@@ -144,20 +160,82 @@ pub enum BlendType {
 pub struct Screen {
     pub index: i32,
     pub bounds: CompleteBounds,
+    #[allow(dead_code)]
     pub blend: Option<BlendType>,
 }
 
+///
+/// Parses the device's layout, and picks the view to render: the view named `specified_layout`, the view at
+/// `specified_index` in the order they appear in the file, or the most suitable view if neither is given
+///
+#[allow(clippy::diverging_sub_expression)]
 pub fn parse_layout(
     temp_dir: &Path,
     specified_layout: Option<&String>,
-) -> Result<(MameLayout, View), String> {
+    specified_index: Option<usize>,
+) -> Result<(MameLayout, View), BuildError> {
+    let output = read_layout(temp_dir)?;
+
+    let view_names = || {
+        output
+            .view
+            .iter()
+            .enumerate()
+            .map(|(index, view)| format!("{index}: \"{}\"", view.name))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+
+    if let Some(specified_layout) = specified_layout {
+        let specified_name = specified_layout.trim().to_lowercase();
+
+        if let Some(view) = output
+            .view
+            .iter()
+            .find(|view| view.name.to_lowercase() == specified_name)
+        {
+            let view = view.clone();
+            return Ok((output, view));
+        } else {
+            return Err(BuildError::LayoutNotFound(format!(
+                "Could not find view named \"{specified_layout}\". Available views are {}",
+                view_names()
+            )));
+        }
+    }
+
+    if let Some(index) = specified_index {
+        if let Some(view) = output.view.get(index) {
+            let view = view.clone();
+            return Ok((output, view));
+        } else {
+            return Err(BuildError::LayoutNotFound(format!(
+                "Could not find view at index {index}. Available views are {}",
+                view_names()
+            )));
+        }
+    }
+
+    guard!(let Some(view) = select_view(&output.view) else {
+        return Err(BuildError::LayoutNotFound(format!(
+            "Could not find suitable view. Available views are {}",
+            view_names()
+        )));
+    });
+
+    let view = view.clone();
+
+    Ok((output, view))
+}
+
+fn read_layout(temp_dir: &Path) -> Result<MameLayout, BuildError> {
     let layout_path = temp_dir.join("default.lay");
     let layout_file = match fs::read(&layout_path) {
         Ok(layout_file) => layout_file,
         Err(_) => {
-            return Err(format!(
+            return Err(BuildError::LayoutNotFound(format!(
                 "Could not find default.lay file at path {layout_path:?}"
-            ))
+            )))
         }
     };
 
@@ -168,30 +246,87 @@ pub fn parse_layout(
     // };
     let output: MameLayout = serde_xml_rs::from_reader(layout_file.as_slice()).unwrap();
 
-    let mut map = HashMap::<String, View>::new();
+    Ok(output)
+}
 
-    for view in output.view.iter() {
-        map.insert(view.name.to_lowercase(), view.clone());
-    }
+///
+/// Prints every view in the layout extracted to `temp_dir`, with its number of elements and size, marking the view
+/// that's used by default
+///
+pub fn list_views(temp_dir: &Path) -> Result<(), BuildError> {
+    let layout = read_layout(temp_dir)?;
 
-    if let Some(specified_layout) = specified_layout {
-        if let Some(view) = map.remove(&specified_layout.trim().to_lowercase()) {
-            return Ok((output, view));
+    let default_name = select_view(&layout.view).map(|view| view.name.clone());
+
+    for (index, view) in layout.view.iter().enumerate() {
+        let element_count = view
+            .items
+            .iter()
+            .filter(|item| matches!(item, ViewElement::Element(_) | ViewElement::Overlay(_)))
+            .count();
+
+        let size = match view_bounds(view) {
+            Some(bounds) => format!("{}x{}", bounds.width, bounds.height),
+            None => "unknown size".to_string(),
+        };
+
+        let default = if default_name.as_ref() == Some(&view.name) {
+            " (default)"
         } else {
-            return Err(format!("Could not find view named \"{specified_layout}\""));
-        }
+            ""
+        };
+
+        println!(
+            "{index}: \"{}\", {element_count} elements, {size}{default}",
+            view.name
+        );
     }
 
-    guard!(let Some(view) = select_view(&mut map) else {
-        return Err("Could not find suitable view".to_string());
-    });
+    Ok(())
+}
 
-    Ok((output, view))
+///
+/// The view's declared bounds, or the bounds of everything in it if it has none
+///
+fn view_bounds(view: &View) -> Option<Bounds> {
+    if let Some(ViewElement::Bounds(declared)) = view
+        .items
+        .iter()
+        .find(|item| matches!(item, ViewElement::Bounds(_)))
+    {
+        return Some(declared.to_xy());
+    }
+
+    let bounds = view
+        .items
+        .iter()
+        .map(|item| match item {
+            ViewElement::Bounds(bounds) => bounds.to_xy(),
+            ViewElement::Element(element) | ViewElement::Overlay(element) => element.bounds.to_xy(),
+            ViewElement::Screen(screen) => screen.bounds.to_xy(),
+        })
+        .collect::<Vec<Bounds>>();
+
+    let left = bounds.iter().map(|bounds| bounds.x).min()?;
+    let top = bounds.iter().map(|bounds| bounds.y).min()?;
+    let right = bounds.iter().map(|bounds| bounds.x + bounds.width).max()?;
+    let bottom = bounds.iter().map(|bounds| bounds.y + bounds.height).max()?;
+
+    Some(Bounds {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+    })
 }
 
-fn select_view<'a>(views: &mut HashMap<String, View>) -> Option<View> {
+///
+/// Picks the first view with a known suitable name, in order of preference. Otherwise falls back to the first view
+/// containing a screen, logging the available views
+///
+fn select_view(views: &[View]) -> Option<&View> {
     // Constructed this way to give ordered priority to each view name we want
-    let desired_names = vec![
+    let desired_names = [
         "backgrounds only (no frame)",
         "background only (no frame)",
         "backgrounds only (no shadow)",
@@ -206,10 +341,25 @@ fn select_view<'a>(views: &mut HashMap<String, View>) -> Option<View> {
     ];
 
     for name in desired_names {
-        if let Some(view) = views.remove(name) {
+        if let Some(view) = views.iter().find(|view| view.name.to_lowercase() == name) {
             return Some(view);
         }
     }
 
-    None
+    let view = views.iter().find(|view| {
+        view.items
+            .iter()
+            .any(|item| matches!(item, ViewElement::Screen(_)))
+    })?;
+
+    let names = views
+        .iter()
+        .map(|view| format!("\"{}\"", view.name))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    logln!("No view has a known name. Available views are {names}");
+    logln!("Using the first view with a screen, \"{}\"", view.name);
+
+    Some(view)
 }
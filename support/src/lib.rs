@@ -0,0 +1,409 @@
+//! Renders MAME's Game & Watch artwork and ROMs into the single file format read by the FPGA cores. `build_game`
+//! builds one manifest entry from a MAME directory, and the modules expose each stage for finer control
+
+// `guard!` expands its `else` block in a way clippy reports as a diverging sub-expression, so the functions
+// using it allow that lint
+#[macro_use]
+extern crate guard;
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use clap::ValueEnum;
+use colored::Colorize;
+use log::debug;
+
+use crate::{
+    assets::get_assets,
+    encode_format::{encode, EncodeOptions, EncodeStats},
+    error::BuildError,
+    layout::parse_layout,
+    manifest::PlatformSpecification,
+    render::{RenderOptions, Resolution},
+};
+
+pub mod assets;
+pub mod audit;
+pub mod debug_overlay;
+pub mod decode;
+pub mod delta;
+pub mod encode_format;
+pub mod error;
+pub mod fetch;
+pub mod gen_manifest;
+pub mod input_export;
+pub mod io_limit;
+pub mod layout;
+pub mod manifest;
+pub mod mask_export;
+pub mod name_template;
+pub mod render;
+pub mod suggest;
+pub mod svg_manage;
+pub mod task_log;
+
+pub const WIDTH: usize = 720;
+pub const HEIGHT: usize = WIDTH;
+
+/// How a game is rendered and encoded by `build_game`
+#[derive(Clone, Debug)]
+pub struct BuildOptions {
+    pub render: RenderOptions,
+    pub encode: EncodeOptions,
+    /// The name of the layout view to render, instead of the default view
+    pub layout: Option<String>,
+    /// The index of the layout view to render, instead of the default view
+    pub layout_index: Option<usize>,
+    /// Resolutions encoded alongside the primary one
+    pub multi_res: Vec<Resolution>,
+    /// Writes each game's mask entries as text into this directory
+    pub dump_mask: Option<PathBuf>,
+}
+
+impl BuildOptions {
+    ///
+    /// A CRC32 of every option that changes the contents of the built ROM, which is stored in it so a later run can
+    /// tell whether it would build the game differently. Each option is written in a fixed order and encoding, so the
+    /// fingerprint only changes when the options do
+    ///
+    pub fn fingerprint(&self) -> u32 {
+        // Options that only change what's reported, or where the ROM is written, don't make an existing ROM stale
+        let render = &self.render;
+        let encode = &self.encode;
+        let mut bytes = vec![];
+
+        let color = render.clear_color;
+
+        for value in [
+            color.red(),
+            color.green(),
+            color.blue(),
+            color.alpha(),
+            render.gamma,
+            render.brightness,
+        ] {
+            bytes.extend(value.to_bits().to_le_bytes());
+        }
+
+        push_option(&mut bytes, render.dither_colors);
+        push_value_name(&mut bytes, &render.mirror);
+        push_value_name(&mut bytes, &render.rotation);
+        push_value_name(&mut bytes, &render.mask_tiebreak);
+        push_resolution(&mut bytes, &render.resolution);
+        bytes.push(render.crop as u8);
+
+        bytes.push(encode.reserved_fill);
+
+        for flag in [
+            encode.trim_rom,
+            encode.skip_hash_check,
+            encode.embed_metadata,
+            encode.embed_full_sha,
+            encode.compress,
+            encode.allow_screen_mismatch,
+            encode.allow_mask_overflow,
+        ] {
+            bytes.push(flag as u8);
+        }
+
+        push_option(&mut bytes, encode.palette_colors);
+
+        match &self.layout {
+            Some(layout) => {
+                bytes.push(1);
+                push_usize(&mut bytes, layout.len());
+                bytes.extend(layout.as_bytes());
+            }
+            None => bytes.push(0),
+        }
+
+        push_option(&mut bytes, self.layout_index);
+
+        push_usize(&mut bytes, self.multi_res.len());
+
+        for resolution in &self.multi_res {
+            push_resolution(&mut bytes, resolution);
+        }
+
+        crc32fast::hash(&bytes)
+    }
+}
+
+fn push_usize(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend((value as u64).to_le_bytes());
+}
+
+fn push_option(bytes: &mut Vec<u8>, value: Option<usize>) {
+    match value {
+        Some(value) => {
+            bytes.push(1);
+            push_usize(bytes, value);
+        }
+        None => bytes.push(0),
+    }
+}
+
+fn push_resolution(bytes: &mut Vec<u8>, resolution: &Resolution) {
+    push_usize(bytes, resolution.width);
+    push_usize(bytes, resolution.height);
+}
+
+///
+/// Writes the name the option is given on the command line, which stays the same when variants are added or reordered
+///
+fn push_value_name(bytes: &mut Vec<u8>, value: &impl ValueEnum) {
+    // Every variant is given a name, as none are skipped
+    let name = value.to_possible_value().unwrap();
+    let name = name.get_name();
+
+    push_usize(bytes, name.len());
+    bytes.extend(name.as_bytes());
+}
+
+/// The result of building a game
+#[derive(Debug)]
+pub struct BuildOutput {
+    /// Where the ROM was written, or would have been for a dry run
+    pub path: PathBuf,
+    pub stats: EncodeStats,
+    /// A warning for each pair of segments with different mask ids that overlap
+    pub overlap_warnings: Vec<String>,
+}
+
+///
+/// Extracts a game's assets from `mame_path` into the same temporary directory as the command line tool, then renders
+/// and encodes it into `output_dir`. `name` is the game's MAME name, which its manifest entry is listed under
+///
+pub fn build_game(
+    name: &str,
+    platform: &PlatformSpecification,
+    mame_path: &Path,
+    output_dir: &Path,
+    options: &BuildOptions,
+) -> Result<BuildOutput, BuildError> {
+    let asset_dir = env::temp_dir().join("gnw").join(name);
+
+    get_assets(name, &platform.rom, mame_path, &asset_dir)?;
+
+    encode_game(name, platform, &asset_dir, output_dir, options)
+}
+
+///
+/// Renders and encodes a game whose assets were already extracted into `asset_dir`, writing the ROM into
+/// `output_dir`. Overlapping segments are logged as warnings, even if encoding fails
+///
+pub fn encode_game(
+    name: &str,
+    platform: &PlatformSpecification,
+    asset_dir: &Path,
+    output_dir: &Path,
+    options: &BuildOptions,
+) -> Result<BuildOutput, BuildError> {
+    let stage_start = Instant::now();
+
+    let (layout_manifest, layout) =
+        parse_layout(asset_dir, options.layout.as_ref(), options.layout_index)?;
+
+    debug!(
+        "Parsed layout view \"{}\" in {:?}",
+        layout.name,
+        stage_start.elapsed()
+    );
+
+    let render_at = |resolution: Resolution| {
+        let render_options = RenderOptions {
+            resolution,
+            // Only the primary background is reduced to a palette
+            dither_colors: options
+                .render
+                .dither_colors
+                .filter(|_| resolution == options.render.resolution),
+            ..options.render.clone()
+        };
+
+        let stage_start = Instant::now();
+
+        let rendered = render::render(
+            name,
+            &layout,
+            &layout_manifest,
+            platform,
+            asset_dir,
+            &render_options,
+        );
+
+        debug!(
+            "Rendered at {}x{} in {:?}",
+            resolution.width,
+            resolution.height,
+            stage_start.elapsed()
+        );
+
+        rendered
+    };
+
+    let rendered = render_at(options.render.resolution)?;
+
+    if let Some(dump_dir) = &options.dump_mask {
+        mask_export::export_mask(name, platform, &rendered, dump_dir)?;
+    }
+
+    let additional = options
+        .multi_res
+        .iter()
+        .map(|resolution| render_at(*resolution))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let stage_start = Instant::now();
+
+    let encode_options = EncodeOptions {
+        build_fingerprint: Some(options.fingerprint()),
+        ..options.encode.clone()
+    };
+
+    let encoded = encode(
+        name,
+        &rendered,
+        &additional,
+        platform,
+        asset_dir,
+        output_dir,
+        &encode_options,
+    );
+
+    debug!("Encoded in {:?}", stage_start.elapsed());
+
+    // Additional resolutions overlap in the same places, so only the primary render's are reported
+    for warning in &rendered.overlap_warnings {
+        logln!("{}", format!("WARNING: {warning}").yellow());
+    }
+
+    let (path, stats) = encoded?;
+
+    Ok(BuildOutput {
+        path,
+        stats,
+        overlap_warnings: rendered.overlap_warnings,
+    })
+}
+
+///
+/// Matches `name` against `pattern`, where `*` matches any run of characters and `?` any single character. A pattern
+/// without wildcards must match exactly
+///
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let name = name.chars().collect::<Vec<char>>();
+
+    // The last `*` seen, and the position in `name` it's currently matching up to
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // Let the last `*` absorb one more character
+            star = Some((star_p, star_n + 1));
+            p = star_p + 1;
+            n = star_n + 1;
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use resvg::tiny_skia::Color;
+
+    use super::*;
+    use crate::{
+        name_template::NameTemplate,
+        render::{MaskTiebreak, Mirror, Rotation},
+    };
+
+    fn options() -> BuildOptions {
+        BuildOptions {
+            render: RenderOptions {
+                debug: false,
+                clear_color: Color::TRANSPARENT,
+                gamma: 1.0,
+                brightness: 1.0,
+                dither_colors: None,
+                mirror: Mirror::None,
+                rotation: Rotation::None,
+                mask_tiebreak: MaskTiebreak::DrawOrder,
+                resolution: Resolution::STANDARD,
+                crop: false,
+                strict_overlap: false,
+            },
+            encode: EncodeOptions {
+                reserved_fill: 0,
+                trim_rom: false,
+                skip_hash_check: false,
+                output_ext: "gnw".to_string(),
+                strict: false,
+                embed_metadata: false,
+                embed_full_sha: false,
+                dry_run: false,
+                compress: false,
+                to_stdout: false,
+                palette_colors: None,
+                allow_screen_mismatch: false,
+                allow_mask_overflow: false,
+                by_company: false,
+                name_template: None,
+                build_fingerprint: None,
+            },
+            layout: None,
+            layout_index: None,
+            multi_res: vec![],
+            dump_mask: None,
+        }
+    }
+
+    #[test]
+    fn fingerprint_stability() {
+        let fingerprint = options().fingerprint();
+
+        // Stored in every ROM already built, which would all be rebuilt if it changed
+        assert_eq!(fingerprint, 0x1714_7A59);
+
+        let mut unchanged = options();
+        unchanged.render.debug = true;
+        unchanged.render.strict_overlap = true;
+        unchanged.encode.output_ext = "bin".to_string();
+        unchanged.encode.strict = true;
+        unchanged.encode.dry_run = true;
+        unchanged.encode.by_company = true;
+        unchanged.encode.name_template = Some(NameTemplate::parse("{name:lower}").unwrap());
+        unchanged.encode.build_fingerprint = Some(0x1234_5678);
+        unchanged.dump_mask = Some(PathBuf::from("masks"));
+        assert_eq!(unchanged.fingerprint(), fingerprint);
+
+        let mut rotated = options();
+        rotated.render.rotation = Rotation::Clockwise90;
+        assert_ne!(rotated.fingerprint(), fingerprint);
+
+        let mut multi_res = options();
+        multi_res.multi_res = vec![Resolution {
+            width: 360,
+            height: 360,
+        }];
+        assert_ne!(multi_res.fingerprint(), fingerprint);
+
+        let mut overflow = options();
+        overflow.encode.allow_mask_overflow = true;
+        assert_ne!(overflow.fingerprint(), fingerprint);
+    }
+}
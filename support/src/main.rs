@@ -1,40 +1,140 @@
+// `guard!` expands its `else` block in a way clippy reports as a diverging sub-expression, so the functions
+// using it allow that lint
 #[macro_use]
 extern crate guard;
 
-use std::{collections::HashMap, env::temp_dir, fs, path::PathBuf};
+use std::{
+    any::Any,
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    env::{self, temp_dir},
+    fs,
+    io::{self, IsTerminal},
+    num::NonZeroUsize,
+    ops::Add,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process,
+    sync::{mpsc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
 
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{error::ErrorKind, ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::debug;
+use resvg::tiny_skia::Color;
 
 use colored::Colorize;
+use rayon::prelude::*;
+use serde::Serialize;
 
-use assets::get_assets;
-use layout::parse_layout;
-use manifest::PlatformSpecification;
-
-use crate::{encode_format::encode, manifest::CPUType, render::RenderedData};
-
-mod assets;
-mod encode_format;
-mod layout;
-mod manifest;
-mod render;
-mod svg_manage;
-
-const WIDTH: usize = 720;
-const HEIGHT: usize = WIDTH;
+use fpga_gnw_romgenerator::{
+    assets::{clear_cache, get_artwork, get_assets, is_cached, mark_cached, source_paths},
+    audit, decode,
+    delta::Delta,
+    encode_format::{
+        self, find_rom_by_hash, is_up_to_date, mask_map_length, EncodeOptions, EncodeStats,
+        MAX_ADDITIONAL_RESOLUTIONS,
+    },
+    encode_game,
+    error::BuildError,
+    fetch::Fetcher,
+    gen_manifest, glob_match, input_export,
+    io_limit::{self, IoLimiter},
+    layout,
+    manifest::{self, CPUType, PlatformSpecification, Region, Status},
+    name_template::NameTemplate,
+    render::{MaskTiebreak, Mirror, RenderOptions, Resolution, Rotation},
+    suggest::closest_names,
+    task_log::{self, logln},
+    BuildOptions,
+};
 
+// The filters that select which games in the manifest to build
 #[derive(Subcommand, Clone, Debug)]
 enum FilterArg {
-    /// Match a particular game
-    Specific { name: String },
+    /// Match particular games, or every game matching a pattern using `*` and `?` wildcards, such as `gnw_mario*`.
+    /// Names that don't match any game are counted as failures
+    Specific {
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
     /// Match the games that use a particular CPU
-    CPU { name: CPUType },
+    Cpu { name: CPUType },
     /// Match the specific CPU types supported by the core currently. These are the SM510 (inc. Tiger) and SM5a CPUs
     Supported,
     /// All game types specified in the manifest.json
     All,
 }
 
+// Everything the generator can do besides building, alongside the build filters
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Build the games selected by a filter
+    #[command(flatten)]
+    Build(FilterArg),
+    /// Check that every game in the manifest can be encoded, without building anything or needing any MAME assets.
+    /// Exits with an error if any can't
+    Audit,
+    /// Print the header of a built ROM, and extract its images, ROM, and melody ROM. Doesn't need the manifest
+    Decode {
+        /// The built ROM file
+        path: PathBuf,
+        /// The directory to extract into. Defaults to a directory beside the ROM, named after it
+        output_dir: Option<PathBuf>,
+    },
+    /// List the views in a game's MAME layout, for choosing a `--layout`, without building anything. Doesn't need
+    /// the manifest
+    ListLayouts { name: String },
+    /// Check every game in the manifest for errors, listing all of them rather than stopping at the first. Doesn't
+    /// need any MAME assets. Exits with an error if any game is invalid
+    ValidateManifest,
+    /// Generate manifest entries from MAME's machine list, for adding games MAME supports. Port maps are left empty
+    /// to be filled in by hand. Doesn't need the manifest
+    GenManifest {
+        /// The file to write the generated manifest to
+        output: PathBuf,
+
+        #[arg(long)]
+        /// Read the output of `mame -listxml` from this file, rather than running MAME
+        xml: Option<PathBuf>,
+
+        #[arg(long, default_value = "mame")]
+        /// The MAME executable to run for its machine list
+        mame_binary: PathBuf,
+
+        #[arg(long, default_value = "gnw_*")]
+        /// Only generate the machines whose names match this pattern, which can contain `*` and `?` wildcards
+        pattern: String,
+    },
+    /// Print a shell completion script to stdout
+    #[command(hide = true)]
+    Completions { shell: Shell },
+    /// Print how many games the given filter and filter flags select, without building anything. Without a filter,
+    /// counts the installed games, like a build does
+    Count {
+        #[command(subcommand)]
+        filter: Option<FilterArg>,
+
+        #[arg(long)]
+        /// Also print the number of games from each company
+        by_company: bool,
+
+        #[arg(long)]
+        /// Also print the number of games for each CPU
+        by_cpu: bool,
+    },
+    /// List which games the given filter and filter flags select are in your MAME directory with a ROM matching the
+    /// manifest hash, and which are missing, grouped by company. Extracts each game's assets, but doesn't build
+    /// anything
+    InstalledSummary {
+        #[command(subcommand)]
+        filter: Option<FilterArg>,
+    },
+}
+
 #[derive(ValueEnum, Clone, Debug)]
 enum CompanyArg {
     Nintendo,
@@ -50,32 +150,266 @@ enum CompanyArg {
 #[derive(Parser, Debug)]
 struct Args {
     #[command(subcommand)]
-    filter: Option<FilterArg>,
+    command: Option<Command>,
 
     #[arg(short = 'i', long)]
     /// Only the games located in your MAME directory
     installed: bool,
 
+    #[arg(long)]
+    /// Write the name, expected ROM file name, and ROM hash of every game skipped as not installed to this file, one
+    /// game per line
+    missing_report: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Write a JSON report of every selected game, with its manifest status and the outcome of its build, to this file
+    report: Option<PathBuf>,
+
     #[arg(short = 'm', long)]
-    /// The path to your MAME directory containing your games
-    mame_path: PathBuf,
+    /// The path to your MAME directory containing your games. Required unless auditing
+    mame_path: Option<PathBuf>,
 
     #[arg(short = 'a', long, default_value = "manifest.json")]
-    /// The path to the included manifest file
-    manifest_path: PathBuf,
+    /// The path to the included manifest file. Can be passed multiple times to overlay manifests, such as one of
+    /// homebrew games, on the included one. Games in later manifests replace games of the same name in earlier ones.
+    /// Manifests ending in `.toml` are read as TOML
+    manifest_path: Vec<PathBuf>,
 
     #[arg(short = 'o', long)]
-    /// The path to the final ROM output directory
-    output_path: PathBuf,
+    /// The path to the final ROM output directory, or `-` to write the ROM of the only matching game to stdout.
+    /// Required unless auditing
+    output_path: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Create the output directory if it doesn't exist, instead of failing
+    create_output_dir: bool,
 
     #[arg(short = 'l', long)]
     /// The layout name specified in the MAME .lay file to use. Will fail if this layout is not found
     layout: Option<String>,
 
+    #[arg(long, conflicts_with = "layout")]
+    /// The index of the view to use, counting from 0 in the order views appear in the MAME .lay file, for views
+    /// without a usable name. Will fail if there is no view at this index
+    layout_index: Option<usize>,
+
     #[arg(short = 'd', long)]
-    /// Enable debug PNG output
+    /// Write debug PNGs of the background, the mask, and each segment to a `debug` directory in the asset directory,
+    /// along with the device with each input labeled with its mapped action
     debug: bool,
 
+    #[arg(long)]
+    /// Opt-in base URL to download screen SVGs missing from your MAME set from. ROMs are only fetched for homebrew
+    /// titles, and must match the manifest hash. Downloads are cached between runs
+    fetch_base: Option<String>,
+
+    #[arg(long)]
+    /// Opt-in URL to download a game's artwork and ROM archives from when they aren't in your MAME directory. `{name}`
+    /// is replaced with the archive's name and `{dir}` with `artwork` or `roms`. ROMs are only fetched for homebrew
+    /// titles, and must match the manifest hash. Downloads are cached between runs
+    asset_url_template: Option<String>,
+
+    #[arg(long)]
+    /// A directory of previously built ROMs. Only new or changed ROMs are kept in the output directory, and lists of
+    /// the unchanged and removed ROMs are written alongside them. Use the same filters as the reference build
+    delta_against: Option<PathBuf>,
+
+    #[arg(long)]
+    /// A directory to keep each game's extracted assets in between runs, keyed by its name and ROM hash. They're
+    /// reused until the game's artwork or ROM archives change, instead of being extracted again on every run
+    cache_dir: Option<PathBuf>,
+
+    #[arg(long)]
+    /// A game to leave out of the build, even if the filter selects it. Accepts `*` and `?` wildcards. Can be passed
+    /// multiple times
+    exclude: Vec<String>,
+
+    #[arg(long)]
+    /// Embed JSON build metadata (name, company, CPU, screen, tool version, and build time) in each ROM, in an
+    /// extension chunk the core ignores
+    embed_metadata: bool,
+
+    #[arg(long)]
+    /// Write the generator's full 40 character commit SHA into each ROM's config, alongside the short SHA, to tell
+    /// builds from different forks apart
+    embed_full_sha: bool,
+
+    #[arg(long, conflicts_with = "delta_against")]
+    /// Extract, render, and encode every game as usual, but don't write any ROMs. Previews which games will build
+    dry_run: bool,
+
+    #[arg(long)]
+    /// Print how many bytes of each built ROM are its header, images, mask map, ROM, melody ROM, and extension chunks,
+    /// and the totals of every game at the end of the run
+    stats: bool,
+
+    #[arg(long)]
+    /// Print each built game's S, B, BA, and ACL input mapping as a table of the actions and config bytes encoded into
+    /// its ROM, for proofreading the manifest before flashing
+    input_map_report: bool,
+
+    #[arg(long)]
+    /// A directory to write each game's port map to, formatted like MAME's input port definitions, for checking the
+    /// manifest against MAME
+    export_inputs: Option<PathBuf>,
+
+    #[arg(long)]
+    /// A directory to write each game's mask entries to as text, one `id, start_x, y, length` line per run of
+    /// segment pixels, for debugging segments that light up wrong
+    dump_mask: Option<PathBuf>,
+
+    #[arg(long, conflicts_with = "delta_against")]
+    /// Only rebuild games whose existing ROM in the output directory was built by a different version of this tool,
+    /// or with different options. ROMs that can't be read are rebuilt
+    rebuild_stale: bool,
+
+    #[arg(long)]
+    /// Rebuild every game. By default, games whose existing ROM was built by this version with the same options, and
+    /// is newer than their MAME assets and the manifest, are skipped as up to date
+    force: bool,
+
+    #[arg(long, default_value = "0", value_parser = parse_byte)]
+    /// The byte (decimal or 0x prefixed hex) to fill reserved regions of the ROM config with. Only needed for
+    /// loaders that expect a specific pattern
+    reserved_fill: u8,
+
+    #[arg(long)]
+    /// Strip trailing 0x00/0xFF padding from ROMs matching their manifest hash, down to the manifest's `romSize` if
+    /// set. The ROM length is recorded in the ROM config
+    trim_rom: bool,
+
+    #[arg(long)]
+    /// Don't check that ROMs found by file name match their manifest hash, for intentionally modified ROMs
+    skip_hash_check: bool,
+
+    #[arg(long, conflicts_with = "dry_run")]
+    /// Read each ROM back after writing it, checking its format version, CRCs, and that its ROM data matches the
+    /// manifest hash. A ROM that fails is counted as a failed game
+    verify: bool,
+
+    #[arg(long)]
+    /// Deflate the background and mask images, which make up most of each ROM. Only for cores that support
+    /// compressed images
+    compress: bool,
+
+    #[arg(long, value_name = "COLORS", value_parser = clap::value_parser!(u16).range(2..=256))]
+    /// Reduce each background to a palette of at most COLORS colors, shrinking its images by a third. Only for cores
+    /// that support paletted backgrounds
+    palette_reduce: Option<u16>,
+
+    #[arg(long, requires = "palette_reduce")]
+    /// Dither the background to its reduced palette, so gradients don't band. The mask is never dithered
+    dither: bool,
+
+    #[arg(long)]
+    /// Build dual screen games whose screens differ in size, instead of failing them. Both sizes are stored, but cores
+    /// that only read the first screen's size use it for both
+    allow_screen_mismatch: bool,
+
+    #[arg(long)]
+    /// Build games whose layouts have more mask entries than fit in the mask config, storing the rest in an extension
+    /// chunk, instead of failing them. The cores in this repo don't read the chunk, so those segments aren't displayed
+    allow_mask_overflow: bool,
+
+    #[arg(long, default_value = "gnw", value_parser = parse_extension)]
+    /// The file extension of the created ROMs, for loaders that expect something other than `gnw`
+    output_ext: String,
+
+    #[arg(long, conflicts_with = "delta_against")]
+    /// Write each ROM into a subdirectory of the output path named after its company, such as `Nintendo/`
+    by_company: bool,
+
+    #[arg(long, value_parser = NameTemplate::parse)]
+    /// The file name of each created ROM, without extension, such as `{company}_{name:lower}`. Placeholders are
+    /// `{name}`, `{company}`, `{cpu}`, and `{year}`, and take an optional `:lower` or `:upper` suffix. Defaults to
+    /// `{name}`
+    name_template: Option<NameTemplate>,
+
+    #[arg(long, default_value = "00000000", value_parser = parse_color)]
+    /// The RRGGBBAA hex color to clear the canvas to before rendering. Areas the artwork doesn't cover keep this
+    /// color, such as the base tint of an LCD. Defaults to transparent, which is encoded as black
+    clear_color: Color,
+
+    #[arg(long, default_value_t = 1.0, value_parser = parse_gamma)]
+    /// The gamma to correct the background's colors by, for artwork that looks too dark on the core's display.
+    /// Values above 1 brighten the midtones. The mask image is unaffected
+    gamma: f32,
+
+    #[arg(long, default_value_t = 1.0, value_parser = parse_brightness)]
+    /// Multiplies the background's colors after gamma correction, clamped to full brightness. The mask image is
+    /// unaffected
+    brightness: f32,
+
+    #[arg(long, value_enum, default_value_t = MaskTiebreak::DrawOrder)]
+    /// Which segment a pixel belongs to when segments overlap. By default the segment drawn last wins, which depends
+    /// on the order of the SVG's elements
+    mask_tiebreak: MaskTiebreak,
+
+    #[arg(long)]
+    /// Fail games where LCD segments with different mask ids overlap, instead of warning once the game is built
+    strict_overlap: bool,
+
+    #[arg(long, conflicts_with = "multi_res")]
+    /// Crop the images to the bounding box of their visible pixels, leaving out transparent margins. The position of
+    /// the images on the canvas is recorded for the core
+    crop: bool,
+
+    #[arg(long, value_enum, default_value_t = Mirror::None)]
+    /// Mirror the rendered device, for clones that flip the original's layout. Mask ids and inputs move with it
+    mirror: Mirror,
+
+    #[arg(long, value_enum, default_value_t = Rotation::None)]
+    /// Rotate the rendered device clockwise by this many degrees, after any mirroring, for cores that expect rotated
+    /// images. Quarter turns swap the image width and height. Mask ids and inputs move with it
+    rotate: Rotation,
+
+    #[arg(long, default_value = "720x720", value_parser = parse_resolution)]
+    /// The WIDTHxHEIGHT resolution to render the primary images at, up to 1023x1023. The cores in this repo only
+    /// support the default
+    resolution: Resolution,
+
+    #[arg(long, value_delimiter = ',', value_parser = parse_resolution)]
+    /// A comma separated list of additional WIDTHxHEIGHT resolutions to render and encode alongside the primary
+    /// images, such as `480x480,1000x1000`, for cores that pick the best fit for their display. Each adds
+    /// several megabytes to every ROM
+    multi_res: Vec<Resolution>,
+
+    #[arg(short = 'j', long)]
+    /// The maximum number of devices processed at once. Defaults to the number of logical CPUs
+    jobs: Option<NonZeroUsize>,
+
+    #[arg(long)]
+    /// The maximum number of concurrent reads from the MAME directory, independent of how many devices are processed
+    /// at once. Defaults to 2 for network storage, and the number of logical CPUs otherwise
+    parallel_io_limit: Option<NonZeroUsize>,
+
+    #[arg(long)]
+    /// Fail games with encoding warnings, such as mapped actions the core can't represent for its CPU. Also exit
+    /// with an error if any game was skipped, rather than only when a game fails
+    strict: bool,
+
+    #[arg(long, value_enum, default_value_t = Status::Imperfect)]
+    /// Skip games whose manifest status is below this. Building broken games requires `--min-status broken`
+    min_status: Status,
+
+    #[arg(long)]
+    /// Treat a panic while processing a device as a failure of that device, and continue with the rest of the batch
+    resilient: bool,
+
+    #[arg(long, value_enum)]
+    /// Only build games from this region, as set in the manifest. Combines with the other filters
+    filter_region: Option<Region>,
+
+    #[arg(short = 'V', long, action = ArgAction::Count)]
+    /// Log how long each stage of building a game takes. Pass twice to also log every element and LCD segment as it's
+    /// rendered
+    verbose: u8,
+
+    #[arg(long)]
+    /// Don't color the output. Color is also disabled when the `NO_COLOR` environment variable is set, or the output
+    /// isn't a terminal
+    no_color: bool,
+
     ///////////////////
 
     // Company filtering
@@ -115,17 +449,157 @@ struct Args {
 fn main() {
     let args = Args::parse();
 
-    let temp_dir = temp_dir().join("gnw");
+    // `--output-path -` writes the ROM to stdout, so everything else is printed to stderr
+    let to_stdout = args.output_path.as_deref() == Some(Path::new("-"));
+
+    // Escape codes are only noise in log files and piped output
+    let log_is_terminal = if to_stdout {
+        io::stderr().is_terminal()
+    } else {
+        io::stdout().is_terminal()
+    };
+
+    if args.no_color
+        || env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+        || !log_is_terminal
+    {
+        colored::control::set_override(false);
+    }
+
+    task_log::init(args.verbose);
+
+    if to_stdout {
+        task_log::print_to_stderr();
+    }
+
+    if let Some(Command::Completions { shell }) = &args.command {
+        let mut command = Args::command();
+        let name = command.get_name().to_string();
+
+        clap_complete::generate(*shell, &mut command, name, &mut io::stdout());
+
+        return;
+    }
+
+    if let Some(Command::Decode { path, output_dir }) = &args.command {
+        let output_dir = output_dir
+            .clone()
+            .unwrap_or_else(|| path.with_extension(""));
+
+        if let Err(err) = decode::dump(path, &output_dir) {
+            logln!("{}", err.red());
+            process::exit(1);
+        }
+
+        return;
+    }
+
+    if let Some(Command::GenManifest {
+        output,
+        xml,
+        mame_binary,
+        pattern,
+    }) = &args.command
+    {
+        if let Err(err) = gen_manifest::generate(xml.as_deref(), mame_binary, pattern, output) {
+            logln!("{}", err.red());
+            process::exit(1);
+        }
+
+        return;
+    }
+
+    if let Some(Command::ListLayouts { name }) = &args.command {
+        let name = name.trim();
+        let mame_path = required_dir(&args.mame_path, "--mame-path");
+        let asset_dir = temp_dir().join("gnw").join(name);
+
+        let result =
+            get_artwork(name, mame_path, &asset_dir).and_then(|_| layout::list_views(&asset_dir));
+
+        if let Err(err) = result {
+            logln!("{}", err.to_string().red());
+            process::exit(1);
+        }
+
+        return;
+    }
 
-    let manifest_file = fs::read(args.manifest_path).expect("Could not find manifest file");
+    let encode_options = EncodeOptions {
+        reserved_fill: args.reserved_fill,
+        trim_rom: args.trim_rom,
+        skip_hash_check: args.skip_hash_check,
+        output_ext: args.output_ext.clone(),
+        strict: args.strict,
+        embed_metadata: args.embed_metadata,
+        embed_full_sha: args.embed_full_sha,
+        dry_run: args.dry_run,
+        compress: args.compress,
+        to_stdout,
+        palette_colors: args.palette_reduce.map(usize::from),
+        allow_screen_mismatch: args.allow_screen_mismatch,
+        allow_mask_overflow: args.allow_mask_overflow,
+        by_company: args.by_company,
+        name_template: args.name_template.clone(),
+        build_fingerprint: None,
+    };
+
+    if let Some(Command::ValidateManifest) = args.command {
+        if !audit::validate_manifest(&args.manifest_path, &encode_options) {
+            process::exit(1);
+        }
+
+        return;
+    }
 
     let manifest: HashMap<String, PlatformSpecification> =
-        serde_json::from_slice(manifest_file.as_slice()).expect("Could not parse manifest file");
+        match manifest::load_manifests(&args.manifest_path) {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                logln!(
+                    "{}",
+                    format!("{err}\nRun validate-manifest to list every problem").red()
+                );
+                process::exit(1);
+            }
+        };
+
+    if let Some(Command::Audit) = args.command {
+        if !audit::audit_manifest(&manifest, &encode_options) {
+            process::exit(1);
+        }
+
+        return;
+    }
+
+    // Port mistakes are caught before any game is rendered, rather than when each reaches encoding
+    let mut names = manifest.keys().collect::<Vec<&String>>();
+    names.sort();
+
+    let port_errors = names
+        .into_iter()
+        .flat_map(|name| {
+            let platform = &manifest[name];
 
-    let output_path = args
-        .output_path
-        .canonicalize()
-        .expect("Could not find output path");
+            platform
+                .port_map
+                .validate(&platform.device.cpu)
+                .into_iter()
+                .map(move |err| format!("{name}: {err}"))
+        })
+        .collect::<Vec<String>>();
+
+    if !port_errors.is_empty() {
+        for err in port_errors {
+            logln!("{}", err.red());
+        }
+
+        logln!(
+            "{}",
+            "Invalid port maps in the manifest. Run validate-manifest to list every problem".red()
+        );
+        process::exit(1);
+    }
 
     let company_filter = {
         let mut filter = vec![];
@@ -174,45 +648,100 @@ fn main() {
                 .map(|(n, p)| (n.clone(), p))
                 .collect::<Vec<(String, &PlatformSpecification)>>();
 
-            if result.len() > 0 {
+            if !result.is_empty() {
                 Some(result)
             } else {
                 None
             }
         };
 
-    let platforms: Option<Vec<(String, &PlatformSpecification)>> = match &args.filter {
-        Some(FilterArg::Specific { name }) => {
-            let trimmed_name = name.trim().to_string();
+    // Names passed to `specific` that don't match any game
+    let mut unknown_names = 0;
 
-            if let Some(entry) = manifest.get(&trimmed_name) {
-                Some(vec![(trimmed_name, entry)])
-            } else {
+    // `count` and `installed-summary` select games the same way as a build with their filter
+    let filter = match &args.command {
+        Some(Command::Build(filter)) => Some(filter),
+        Some(Command::Count { filter, .. }) => filter.as_ref(),
+        Some(Command::InstalledSummary { filter }) => filter.as_ref(),
+        _ => None,
+    };
+
+    let platforms: Option<Vec<(String, &PlatformSpecification)>> = match filter {
+        Some(FilterArg::Specific { names }) => {
+            let mut matches: Vec<(String, &PlatformSpecification)> = vec![];
+
+            for pattern in names.iter().map(|name| name.trim()) {
+                let mut matched = false;
+
+                for (name, platform) in manifest
+                    .iter()
+                    .filter(|(name, _)| glob_match(pattern, name))
+                {
+                    matched = true;
+
+                    if !matches.iter().any(|(existing, _)| existing == name) {
+                        matches.push((name.clone(), platform));
+                    }
+                }
+
+                if !matched {
+                    let suggestions = closest_names(pattern, manifest.keys(), 3);
+
+                    logln!(
+                        "{}",
+                        format!(
+                            "No game matches \"{pattern}\". Did you mean {}?",
+                            suggestions.join(", ")
+                        )
+                        .red()
+                    );
+
+                    unknown_names += 1;
+                }
+            }
+
+            if matches.is_empty() {
                 None
+            } else {
+                Some(matches)
             }
         }
         Some(FilterArg::Supported) => {
             filter_platforms(vec![CPUType::SM510, CPUType::SM510Tiger, CPUType::SM5a])
         }
-        Some(FilterArg::CPU { name }) => filter_platforms(vec![name.clone()]),
+        Some(FilterArg::Cpu { name }) => filter_platforms(vec![name.clone()]),
         Some(FilterArg::All) | None => Some(manifest.iter().map(|(n, p)| (n.clone(), p)).collect()),
     };
 
-    let installed = if args.filter.is_some() {
+    let installed = if filter.is_some() {
         args.installed
     } else {
         true
     };
 
     guard!(let Some(mut platforms) = platforms else {
-        println!("No manifest listings for selected devices found");
+        logln!("No manifest listings for selected devices found");
+
+        if unknown_names > 0 {
+            process::exit(1);
+        }
+
         return;
     });
 
-    platforms.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+    // Devices with the same sort name are ordered by their MAME name so the order is stable
+    platforms.sort_by_cached_key(|(name, platform)| {
+        (platform.metadata.sort_name().to_lowercase(), name.clone())
+    });
 
     let platforms = platforms.iter().filter(|(_, p)| {
-        if company_filter.len() > 0 {
+        if let Some(region) = args.filter_region {
+            if p.metadata.region().unwrap_or_default() != region {
+                return false;
+            }
+        }
+
+        if !company_filter.is_empty() {
             for filter in &company_filter {
                 if p.metadata.company.to_lowercase().starts_with(filter) {
                     return true;
@@ -225,92 +754,1024 @@ fn main() {
         false
     });
 
-    let mut success_count = 0;
-    let mut skip_count = 0;
-    let mut fail_count = 0;
-    let mut platform_count = 0;
+    let (excluded, platforms): (Vec<_>, Vec<_>) = platforms.partition(|(name, _)| {
+        args.exclude
+            .iter()
+            .any(|pattern| glob_match(pattern.trim(), name))
+    });
 
-    let mut fail = |name: &String, message: String| {
-        println!("{message}");
-        println!("{}", format!("Failing device {name}\n").red());
+    for (name, _) in &excluded {
+        logln!("Excluding device {name}");
+    }
 
-        fail_count += 1;
+    if let Some(Command::Count {
+        by_company, by_cpu, ..
+    }) = &args.command
+    {
+        let mame_path = installed.then(|| required_dir(&args.mame_path, "--mame-path"));
+
+        let counted = platforms
+            .iter()
+            .filter(|(_, platform)| platform.metadata.status >= args.min_status)
+            .filter(|(name, platform)| {
+                // Matches how a build fails to find the assets of a game that isn't installed
+                mame_path.is_none_or(|mame_path| {
+                    source_paths(name, &platform.rom, mame_path)
+                        .iter()
+                        .all(|path| path.exists())
+                })
+            })
+            .collect::<Vec<_>>();
+
+        match counted.len() {
+            1 => logln!("1 game matches"),
+            count => logln!("{count} games match"),
+        }
+
+        if *by_company {
+            print_breakdown(
+                "Company",
+                counted.iter().map(|(_, p)| p.metadata.company.clone()),
+            );
+        }
+
+        if *by_cpu {
+            print_breakdown(
+                "CPU",
+                counted.iter().map(|(_, p)| format!("{:?}", p.device.cpu)),
+            );
+        }
+
+        return;
+    }
+
+    if let Some(Command::InstalledSummary { .. }) = &args.command {
+        let mame_path = required_dir(&args.mame_path, "--mame-path");
+
+        print_installed_summary(&platforms, mame_path, &temp_dir().join("gnw"));
+
+        return;
+    }
+
+    if to_stdout && platforms.len() != 1 {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "--output-path - writes a single ROM to stdout, but {} games match",
+                    platforms.len()
+                ),
+            )
+            .exit();
+    }
+
+    let mame_path = required_dir(&args.mame_path, "--mame-path");
+
+    let fetcher = (args.fetch_base.is_some() || args.asset_url_template.is_some()).then(|| {
+        Fetcher::new(
+            args.fetch_base.as_ref(),
+            args.asset_url_template.as_ref(),
+            temp_dir().join("gnw-fetch"),
+        )
+    });
+
+    let temp_dir = temp_dir().join("gnw");
+
+    let delta = args.delta_against.is_some().then(|| {
+        let reference_dir = required_dir(&args.delta_against, "--delta-against");
+
+        Mutex::new(Delta::new(reference_dir.to_path_buf(), &args.output_ext))
+    });
+
+    if to_stdout && (delta.is_some() || args.verify) {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--delta-against and --verify need the ROMs written to an output directory, not stdout",
+            )
+            .exit();
+    }
+
+    let output_path = if to_stdout {
+        PathBuf::from("-")
+    } else {
+        prepare_output_dir(
+            required_path(&args.output_path, "--output-path"),
+            args.create_output_dir,
+            args.dry_run,
+        )
     };
 
-    for (name, platform) in platforms {
-        platform_count += 1;
-        let asset_dir = temp_dir.join(name.clone());
+    let build_options = BuildOptions {
+        render: RenderOptions {
+            debug: args.debug,
+            clear_color: args.clear_color,
+            gamma: args.gamma,
+            brightness: args.brightness,
+            dither_colors: args.palette_reduce.filter(|_| args.dither).map(usize::from),
+            mirror: args.mirror,
+            rotation: args.rotate,
+            mask_tiebreak: args.mask_tiebreak,
+            resolution: args.resolution,
+            crop: args.crop,
+            strict_overlap: args.strict_overlap,
+        },
+        encode: encode_options,
+        layout: args.layout.clone(),
+        layout_index: args.layout_index,
+        multi_res: args.multi_res.clone(),
+        dump_mask: args.dump_mask.clone(),
+    };
 
-        println!("-------------------------");
-        println!("Processing device {}\n", name.green());
+    if args.multi_res.contains(&args.resolution) {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "{}x{} is the primary resolution, and is always encoded",
+                    args.resolution.width, args.resolution.height
+                ),
+            )
+            .exit();
+    }
 
-        if let Err(err) = get_assets(&name, &platform.rom.rom_owner, &args.mame_path, &asset_dir) {
-            if !installed {
-                // Only fail if we're not looking for only owned games
-                fail(name, err);
-            } else {
-                // See `fail` above
-                println!("{err}");
-                println!(
-                    "{}",
-                    format!("Skipping device {name}: Not installed\n").red()
-                );
-                skip_count += 1;
+    if args.resolution != Resolution::STANDARD {
+        logln!(
+            "{}",
+            format!(
+                "WARNING: ROMs with {}x{} images are only supported by cores that read the image resolution",
+                args.resolution.width, args.resolution.height
+            )
+            .yellow()
+        );
+    }
+
+    if args.multi_res.len() > MAX_ADDITIONAL_RESOLUTIONS {
+        Args::command()
+            .error(
+                ErrorKind::TooManyValues,
+                format!(
+                    "At most {MAX_ADDITIONAL_RESOLUTIONS} additional resolutions are supported"
+                ),
+            )
+            .exit();
+    }
+
+    if !args.multi_res.is_empty() {
+        let added_bytes: usize = args
+            .multi_res
+            .iter()
+            .map(|resolution| {
+                // Interleaved RGB background and mask, then the mask map
+                resolution.width * resolution.height * 6 + mask_map_length(*resolution)
+            })
+            .sum();
+
+        logln!(
+            "{}",
+            format!(
+                "WARNING: Additional resolutions add {:.1} MiB to every ROM",
+                added_bytes as f64 / (1024.0 * 1024.0)
+            )
+            .yellow()
+        );
+    }
+
+    let io_limit = args.parallel_io_limit.map_or_else(
+        || {
+            let limit = io_limit::default_limit(mame_path);
+
+            if io_limit::is_network_path(mame_path) {
+                logln!("MAME directory appears to be on network storage. Limiting concurrent reads to {limit}");
             }
-            continue;
-        }
 
-        let (layout_manifest, layout) = match parse_layout(&asset_dir, args.layout.as_ref()) {
-            Ok(layout) => layout,
-            Err(err) => {
-                fail(name, err);
-                continue;
+            limit
+        },
+        |limit| limit.get(),
+    );
+
+    let io_limiter = IoLimiter::new(io_limit);
+
+    let missing = Mutex::new(vec![]);
+    let stats = Mutex::new(vec![]);
+
+    let context = BuildContext {
+        args: &args,
+        installed,
+        mame_path,
+        io_limiter: &io_limiter,
+        fetcher: fetcher.as_ref(),
+        temp_dir: &temp_dir,
+        output_path: &output_path,
+        build_options: &build_options,
+        delta: delta.as_ref(),
+        missing: &missing,
+        stats: &stats,
+    };
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs.map_or(0, |jobs| jobs.get()))
+        .build()
+        .expect("Could not create thread pool");
+
+    // Only draw a progress bar for people watching, so piped output stays line based
+    let progress = if io::stdout().is_terminal() {
+        let bar = ProgressBar::new(platforms.len() as u64).with_style(
+            ProgressStyle::with_template("[{elapsed_precise}] {bar:40} {pos}/{len} {msg}")
+                .expect("Invalid progress bar template"),
+        );
+        bar.enable_steady_tick(Duration::from_millis(250));
+
+        Some(bar)
+    } else {
+        None
+    };
+
+    let outcomes = thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel::<(usize, String)>();
+        let progress = progress.as_ref();
+
+        // Print each device's log once every device before it has been printed, so the output is in manifest order
+        scope.spawn(move || {
+            let mut pending = BTreeMap::new();
+            let mut next_index = 0;
+
+            for (index, log) in receiver {
+                pending.insert(index, log);
+
+                while let Some(log) = pending.remove(&next_index) {
+                    match progress {
+                        // Keep the log above the bar
+                        Some(progress) => {
+                            progress.suspend(|| task_log::write(format_args!("{log}")))
+                        }
+                        None => task_log::write(format_args!("{log}")),
+                    }
+
+                    next_index += 1;
+                }
             }
+        });
+
+        pool.install(|| {
+            platforms
+                .par_iter()
+                .enumerate()
+                .map_with(sender, |sender, (index, (name, platform))| {
+                    if let Some(progress) = progress {
+                        progress.set_message(name.clone());
+                    }
+
+                    let (outcome, log) =
+                        task_log::capture(|| process_platform(name, platform, &context));
+
+                    if let Some(progress) = progress {
+                        progress.inc(1);
+                    }
+
+                    // The printer only finishes once every sender is dropped
+                    sender.send((index, log)).unwrap();
+
+                    outcome
+                })
+                .collect::<Vec<Outcome>>()
+        })
+    });
+
+    let counts = outcomes
+        .iter()
+        .map(|outcome| Counts::of(*outcome))
+        .fold(Counts::default(), Counts::add)
+        + Counts {
+            total: unknown_names,
+            fail: unknown_names,
+            ..Default::default()
         };
 
-        let RenderedData {
-            background_bytes,
-            mask_bytes,
-            pixels_to_mask_id,
-        } = match render::render(
-            &name,
-            &layout,
-            &layout_manifest,
-            &platform,
-            &asset_dir,
-            args.debug,
-        ) {
-            Ok(data) => data,
-            Err(err) => {
-                fail(name, err);
-                continue;
-            }
+    if let Some(progress) = progress {
+        progress.finish_and_clear();
+    }
+
+    logln!("-------------------------");
+    logln!(
+        "Total: {}, Success: {}, Up to date: {}, Fail: {}, Skip: {}, Excluded: {}",
+        counts.total,
+        counts.success,
+        counts.up_to_date,
+        counts.fail,
+        counts.skip,
+        excluded.len()
+    );
+
+    if args.stats {
+        let stats = stats.into_inner().unwrap();
+        let mut totals = EncodeStats::default();
+
+        for game_stats in &stats {
+            totals += *game_stats;
+        }
+
+        logln!("Sizes of {} encoded games:\n{totals}", stats.len());
+    }
+
+    if let Some(delta) = delta {
+        let mut delta = delta.into_inner().unwrap();
+
+        if let Err(err) = delta.finish(&output_path) {
+            logln!("{}", err.red());
+            process::exit(1);
+        }
+
+        logln!(
+            "Delta: Changed: {}, Unchanged: {}, Removed: {}",
+            counts.success - delta.unchanged.len(),
+            delta.unchanged.len(),
+            delta.removed.len()
+        );
+    }
+
+    if let Some(report_path) = &args.missing_report {
+        let mut missing = missing.into_inner().unwrap();
+        missing.sort();
+
+        let mut contents = missing.join("\n");
+
+        if !contents.is_empty() {
+            contents.push('\n');
+        }
+
+        if let Err(err) = fs::write(report_path, contents) {
+            logln!(
+                "{}",
+                format!("Could not write missing report {report_path:?}: {err}").red()
+            );
+            process::exit(1);
+        }
+
+        logln!(
+            "Wrote {} missing games to {}",
+            missing.len(),
+            report_path.display()
+        );
+    }
+
+    if let Some(report_path) = &args.report {
+        let entries = platforms
+            .iter()
+            .zip(&outcomes)
+            .map(|((name, platform), outcome)| ReportEntry {
+                name,
+                status: platform.metadata.status,
+                outcome: *outcome,
+            })
+            .collect::<Vec<_>>();
+
+        let contents = serde_json::to_string_pretty(&entries).expect("Could not serialize report");
+
+        if let Err(err) = fs::write(report_path, contents) {
+            logln!(
+                "{}",
+                format!("Could not write report {report_path:?}: {err}").red()
+            );
+            process::exit(1);
+        }
+
+        logln!(
+            "Wrote a report of {} games to {}",
+            entries.len(),
+            report_path.display()
+        );
+    }
+
+    // Skipped devices, such as those that aren't installed, only fail the run when every device is expected to build
+    if counts.fail > 0 || (args.strict && counts.skip > 0) {
+        process::exit(1);
+    }
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum Outcome {
+    Success,
+    /// The existing ROM didn't need to be rebuilt
+    UpToDate,
+    Skip,
+    Fail,
+}
+
+/// A device's line in the `--report` JSON
+#[derive(Serialize)]
+struct ReportEntry<'a> {
+    name: &'a str,
+    status: Status,
+    outcome: Outcome,
+}
+
+/// Device totals for a run
+#[derive(Default)]
+struct Counts {
+    total: usize,
+    success: usize,
+    up_to_date: usize,
+    skip: usize,
+    fail: usize,
+}
+
+impl Counts {
+    fn of(outcome: Outcome) -> Self {
+        let mut counts = Counts {
+            total: 1,
+            ..Default::default()
         };
 
-        let data_path = encode(
-            background_bytes.data(),
-            mask_bytes.data(),
-            pixels_to_mask_id.as_slice(),
-            platform,
-            &asset_dir,
-            &output_path,
+        match outcome {
+            Outcome::Success => counts.success = 1,
+            Outcome::UpToDate => counts.up_to_date = 1,
+            Outcome::Skip => counts.skip = 1,
+            Outcome::Fail => counts.fail = 1,
+        }
+
+        counts
+    }
+}
+
+impl Add for Counts {
+    type Output = Counts;
+
+    fn add(self, other: Counts) -> Counts {
+        Counts {
+            total: self.total + other.total,
+            success: self.success + other.success,
+            up_to_date: self.up_to_date + other.up_to_date,
+            skip: self.skip + other.skip,
+            fail: self.fail + other.fail,
+        }
+    }
+}
+
+///
+/// Filters, builds, and compares a single device, logging its progress
+///
+fn process_platform(
+    name: &String,
+    platform: &PlatformSpecification,
+    context: &BuildContext,
+) -> Outcome {
+    let BuildContext {
+        args,
+        installed,
+        mame_path,
+        output_path,
+        build_options,
+        delta,
+        missing,
+        ..
+    } = context;
+
+    let fail = |message: String| {
+        logln!("{message}");
+        logln!("{}", format!("Failing device {name}\n").red());
+
+        Outcome::Fail
+    };
+
+    logln!("-------------------------");
+    logln!(
+        "Processing device {} ({})\n",
+        name.green(),
+        platform.metadata.display_name()
+    );
+
+    let status = platform.metadata.status;
+
+    if status < args.min_status {
+        logln!(
+            "{}",
+            format!("Skipping device {name}: Marked {status:?} in the manifest\n").red()
         );
+        return Outcome::Skip;
+    }
+
+    let existing_path = encode_format::output_path(platform, output_path, &build_options.encode);
+    let fingerprint = build_options.fingerprint();
+
+    if args.rebuild_stale && is_up_to_date(&existing_path, fingerprint) {
+        logln!(
+            "Skipping device {}: Already built by this version at {}\n",
+            name.green(),
+            existing_path.display()
+        );
+        return Outcome::UpToDate;
+    }
+
+    // A delta needs every ROM to be built to compare against the reference, a dry run should build everything, and
+    // there's no existing ROM to compare with on stdout
+    if !args.force && !args.dry_run && delta.is_none() && !build_options.encode.to_stdout {
+        let mut sources = source_paths(name, &platform.rom, mame_path);
+        sources.extend(args.manifest_path.iter().cloned());
 
-        match data_path {
-            Ok(path) => {
-                println!(
+        if is_up_to_date(&existing_path, fingerprint) && is_newer_than(&existing_path, &sources) {
+            logln!(
+                "Skipping device {}: Up to date at {}\n",
+                name.green(),
+                existing_path.display()
+            );
+            return Outcome::UpToDate;
+        }
+    }
+
+    if status != Status::Working {
+        logln!(
+            "{}",
+            format!("WARNING: Device {name} is marked {status:?} and may not work correctly\n")
+                .yellow()
+                .bold()
+        );
+    }
+
+    let build = || build_platform(name, platform, context);
+
+    let result = if args.resilient {
+        // Rendering dependencies may panic on malformed assets. Contain it to this device
+        panic::catch_unwind(AssertUnwindSafe(build)).unwrap_or_else(|payload| {
+            Err(BuildError::Other(format!(
+                "Panicked: {}",
+                panic_message(payload.as_ref())
+            )))
+        })
+    } else {
+        build()
+    };
+
+    match result {
+        Ok(path) => {
+            if let Some(delta) = delta {
+                match delta.lock().unwrap().compare(&path) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        logln!("Device {} is unchanged from the reference\n", name.green());
+                        return Outcome::Success;
+                    }
+                    Err(err) => return fail(err),
+                }
+            }
+
+            if args.dry_run {
+                logln!("Device {} would be created\n", name.green());
+            } else {
+                logln!(
                     "Successfully created device {} at {}\n",
                     name.green(),
                     path.display()
                 );
-                success_count += 1;
             }
-            Err(err) => fail(name, err),
+
+            Outcome::Success
+        }
+        Err(BuildError::AssetMissing(err)) if *installed => {
+            // See `fail` above
+            logln!("{err}");
+            logln!(
+                "{}",
+                format!("Skipping device {name}: Not installed\n").red()
+            );
+
+            missing.lock().unwrap().push(format!(
+                "{name}\t{}\t{}",
+                platform.rom.rom, platform.rom.rom_hash
+            ));
+
+            Outcome::Skip
+        }
+        // Only fail if we're not looking for only owned games, or the device is installed but couldn't be built
+        Err(err) => fail(err.to_string()),
+    }
+}
+
+///
+/// Prints two columns for each company, of the `platforms` whose assets are in `mame_path` with a ROM matching the
+/// manifest hash and of those that aren't, with the percentage owned. Assets are extracted into `temp_dir`
+///
+fn print_installed_summary(
+    platforms: &[&(String, &PlatformSpecification)],
+    mame_path: &Path,
+    temp_dir: &Path,
+) {
+    // (owned, missing) names for each company
+    let mut companies = BTreeMap::<String, (Vec<&str>, Vec<&str>)>::new();
+
+    for (name, platform) in platforms {
+        let asset_dir = temp_dir.join(name);
+
+        let owned = match get_assets(name, &platform.rom, mame_path, &asset_dir) {
+            Ok(()) => find_rom_by_hash(&platform.rom.rom_hash, &asset_dir).is_ok(),
+            Err(BuildError::AssetMissing(_)) => false,
+            Err(err) => {
+                // Installed, but broken, so not usable
+                logln!("{}", format!("{name}: {err}").red());
+                false
+            }
+        };
+
+        let (owned_names, missing_names) = companies
+            .entry(platform.metadata.company.clone())
+            .or_default();
+
+        if owned {
+            owned_names.push(name);
+        } else {
+            missing_names.push(name);
+        }
+    }
+
+    let percentage = |owned: usize, total: usize| owned as f64 / total.max(1) as f64 * 100.0;
+
+    let width = platforms
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or_default()
+        .max("Owned".len());
+
+    for (company, (owned, missing)) in &companies {
+        let total = owned.len() + missing.len();
+
+        logln!(
+            "\n{} ({}/{total}, {:.0}%)",
+            company.bold(),
+            owned.len(),
+            percentage(owned.len(), total)
+        );
+        logln!("  {:width$}  Missing", "Owned");
+
+        for row in 0..owned.len().max(missing.len()) {
+            let owned_name = owned.get(row).copied().unwrap_or_default();
+            let missing_name = missing.get(row).copied().unwrap_or_default();
+
+            if missing_name.is_empty() {
+                logln!("  {}", owned_name.green());
+            } else {
+                logln!(
+                    "  {}  {}",
+                    format!("{owned_name:width$}").green(),
+                    missing_name.red()
+                );
+            }
         }
     }
 
-    println!("-------------------------");
-    println!(
-        "Total: {platform_count}, Success: {success_count}, Fail: {fail_count}, Skip: {skip_count}",
+    let owned = companies
+        .values()
+        .map(|(owned, _)| owned.len())
+        .sum::<usize>();
+
+    logln!(
+        "\nOwned {owned} of {} games ({:.0}%)",
+        platforms.len(),
+        percentage(owned, platforms.len())
     );
 }
+
+///
+/// Prints the number of each distinct value, most common first
+///
+fn print_breakdown(title: &str, values: impl Iterator<Item = String>) {
+    let mut counts = BTreeMap::<String, usize>::new();
+
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+
+    let mut counts = counts.into_iter().collect::<Vec<(String, usize)>>();
+    counts.sort_by_key(|(_, count)| Reverse(*count));
+
+    logln!("\n{title}:");
+
+    for (value, count) in counts {
+        logln!("  {value}: {count}");
+    }
+}
+
+fn parse_byte(value: &str) -> Result<u8, String> {
+    let value = value.trim();
+
+    let parsed = if let Some(hex) = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        u8::from_str_radix(hex, 16)
+    } else {
+        value.parse::<u8>()
+    };
+
+    parsed.map_err(|_| format!("\"{value}\" is not a byte (0-255 or 0x00-0xFF)"))
+}
+
+/// Settings shared by every device in a run
+struct BuildContext<'a> {
+    args: &'a Args,
+    /// Skip devices that aren't in the MAME directory instead of failing them
+    installed: bool,
+    mame_path: &'a Path,
+    io_limiter: &'a IoLimiter,
+    fetcher: Option<&'a Fetcher>,
+    temp_dir: &'a Path,
+    output_path: &'a Path,
+    build_options: &'a BuildOptions,
+    delta: Option<&'a Mutex<Delta>>,
+    /// A line for each device skipped as not installed, for `--missing-report`
+    missing: &'a Mutex<Vec<String>>,
+    /// The size breakdown of each encoded device, for `--stats`
+    stats: &'a Mutex<Vec<EncodeStats>>,
+}
+
+///
+/// Extracts, renders, and encodes a single device, returning the path of the created ROM
+///
+fn build_platform(
+    name: &str,
+    platform: &PlatformSpecification,
+    context: &BuildContext,
+) -> Result<PathBuf, BuildError> {
+    let BuildContext {
+        args,
+        installed,
+        mame_path,
+        io_limiter,
+        fetcher,
+        temp_dir,
+        output_path,
+        build_options,
+        ..
+    } = context;
+
+    let asset_dir = match &args.cache_dir {
+        Some(cache_dir) => cache_dir
+            .join(name)
+            .join(platform.rom.rom_hash.to_lowercase()),
+        None => temp_dir.join(name),
+    };
+
+    if let Some(export_dir) = &args.export_inputs {
+        input_export::export_inputs(name, platform, export_dir)?;
+    }
+
+    let sources = source_paths(name, &platform.rom, mame_path);
+
+    let stage_start = Instant::now();
+
+    if args.cache_dir.is_some() && is_cached(&asset_dir, &platform.rom.rom_hash, &sources) {
+        logln!("Using cached assets at {asset_dir:?}");
+    } else {
+        let _permit = io_limiter.acquire();
+
+        if args.cache_dir.is_some() {
+            // Don't mix stale files into the refreshed entry
+            clear_cache(&asset_dir)?;
+        }
+
+        match get_assets(name, &platform.rom, mame_path, &asset_dir) {
+            Ok(()) => {}
+            Err(err @ BuildError::AssetMissing(_)) => {
+                match fetcher.filter(|fetcher| fetcher.fetches_archives() && !installed) {
+                    Some(fetcher) => {
+                        logln!("{err}");
+
+                        fetcher
+                            .fetch_archives(name, platform, mame_path, &asset_dir)
+                            .map_err(BuildError::FetchFailed)?;
+                    }
+                    None => return Err(err),
+                }
+            }
+            Err(err) => return Err(err),
+        }
+
+        if let Some(fetcher) = fetcher {
+            fetcher
+                .fetch_missing(name, platform, &asset_dir)
+                .map_err(BuildError::FetchFailed)?;
+        }
+
+        if args.cache_dir.is_some() {
+            mark_cached(&asset_dir, &platform.rom.rom_hash)?;
+        }
+    }
+
+    debug!("Prepared assets in {:?}", stage_start.elapsed());
+
+    let output = encode_game(name, platform, &asset_dir, output_path, build_options)?;
+    let path = output.path;
+
+    if args.verify {
+        encode_format::verify(&path, platform, &build_options.encode).map_err(|err| {
+            BuildError::Other(format!("Verification of {} failed: {err}", path.display()))
+        })?;
+
+        logln!("Verified {}", path.display());
+    }
+
+    if args.input_map_report {
+        logln!("Input mapping of {name}:");
+
+        for line in encode_format::input_map_report(platform, &build_options.encode)? {
+            logln!("  {line}");
+        }
+    }
+
+    if args.stats {
+        logln!("{}", output.stats);
+        context.stats.lock().unwrap().push(output.stats);
+    }
+
+    Ok(path)
+}
+
+///
+/// Whether `path` was modified after every one of `sources`. Missing files and timestamps are never considered newer
+///
+#[allow(clippy::diverging_sub_expression)]
+fn is_newer_than(path: &Path, sources: &[PathBuf]) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+
+    guard!(let Ok(output_modified) = modified(path) else {
+        return false;
+    });
+
+    sources.iter().all(|source| {
+        modified(source).is_ok_and(|source_modified| source_modified < output_modified)
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Unknown panic".to_string()
+    }
+}
+
+fn parse_color(value: &str) -> Result<Color, String> {
+    let value = value.trim();
+    let hex = value.strip_prefix('#').unwrap_or(value);
+
+    let error = || format!("\"{value}\" is not an RRGGBBAA hex color");
+
+    if hex.len() != 8 || !hex.is_ascii() {
+        return Err(error());
+    }
+
+    let mut channels = [0u8; 4];
+
+    for (i, channel) in channels.iter_mut().enumerate() {
+        *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| error())?;
+    }
+
+    let [red, green, blue, alpha] = channels;
+
+    Ok(Color::from_rgba8(red, green, blue, alpha))
+}
+
+fn parse_gamma(value: &str) -> Result<f32, String> {
+    match value.trim().parse::<f32>() {
+        Ok(gamma) if gamma.is_finite() && gamma > 0.0 => Ok(gamma),
+        _ => Err(format!("\"{value}\" is not a gamma above 0")),
+    }
+}
+
+fn parse_brightness(value: &str) -> Result<f32, String> {
+    match value.trim().parse::<f32>() {
+        Ok(brightness) if brightness.is_finite() && brightness >= 0.0 => Ok(brightness),
+        _ => Err(format!("\"{value}\" is not a brightness of 0 or more")),
+    }
+}
+
+#[allow(clippy::diverging_sub_expression)]
+fn parse_resolution(value: &str) -> Result<Resolution, String> {
+    let value = value.trim();
+
+    let error = || format!("\"{value}\" is not a WIDTHxHEIGHT resolution");
+
+    let lowercase = value.to_lowercase();
+
+    guard!(let Some((width, height)) = lowercase.split_once('x') else {
+        return Err(error());
+    });
+
+    guard!(let (Ok(width), Ok(height)) = (width.trim().parse::<usize>(), height.trim().parse::<usize>()) else {
+        return Err(error());
+    });
+
+    // Mask entries and the resolution table store coordinates in 10 bits
+    if width == 0 || height == 0 || width >= 1 << 10 || height >= 1 << 10 {
+        return Err(format!(
+            "Resolution {width}x{height} must be between 1x1 and 1023x1023"
+        ));
+    }
+
+    Ok(Resolution { width, height })
+}
+
+fn parse_extension(value: &str) -> Result<String, String> {
+    let extension = value.trim().trim_start_matches('.');
+
+    if extension.is_empty() {
+        return Err("The output extension cannot be empty".to_string());
+    }
+
+    if extension.chars().any(|c| {
+        matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control()
+    }) {
+        return Err(format!(
+            "\"{value}\" is not a valid file extension. It cannot contain path separators or reserved characters"
+        ));
+    }
+
+    Ok(extension.to_string())
+}
+
+/// Unwraps a path argument that's only optional when auditing, exiting with a usage error if it's missing
+#[allow(clippy::diverging_sub_expression)]
+fn required_path<'a>(path: &'a Option<PathBuf>, name: &str) -> &'a Path {
+    guard!(let Some(path) = path else {
+        Args::command()
+            .error(ErrorKind::MissingRequiredArgument, format!("{name} is required"))
+            .exit();
+    });
+
+    path
+}
+
+/// `required_path` for a directory that must already exist, so a mistyped path fails once rather than per device
+fn required_dir<'a>(path: &'a Option<PathBuf>, name: &str) -> &'a Path {
+    let path = required_path(path, name);
+
+    if !path.is_dir() {
+        Args::command()
+            .error(
+                ErrorKind::ValueValidation,
+                format!("{name} {path:?} is not a directory"),
+            )
+            .exit();
+    }
+
+    path
+}
+
+///
+/// Checks the output directory exists (creating it if requested) and can be written to before anything is built,
+/// returning its canonical path
+///
+fn prepare_output_dir(path: &Path, create: bool, dry_run: bool) -> PathBuf {
+    if !path.exists() {
+        if !create {
+            Args::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("--output-path {path:?} does not exist. Pass --create-output-dir to create it"),
+                )
+                .exit();
+        }
+
+        if let Err(err) = fs::create_dir_all(path) {
+            logln!(
+                "{}",
+                format!("Could not create output directory {path:?}: {err}").red()
+            );
+            process::exit(1);
+        }
+    }
+
+    if !path.is_dir() {
+        Args::command()
+            .error(
+                ErrorKind::ValueValidation,
+                format!("--output-path {path:?} is not a directory"),
+            )
+            .exit();
+    }
+
+    if !dry_run {
+        let probe_path = path.join(".gnw-write-test");
+
+        if let Err(err) = fs::write(&probe_path, []) {
+            logln!(
+                "{}",
+                format!("Could not write to output directory {path:?}: {err}").red()
+            );
+            process::exit(1);
+        }
+
+        let _ = fs::remove_file(&probe_path);
+    }
+
+    path.canonicalize().unwrap_or_else(|err| {
+        logln!(
+            "{}",
+            format!("Could not resolve output directory {path:?}: {err}").red()
+        );
+        process::exit(1);
+    })
+}
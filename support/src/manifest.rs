@@ -1,5 +1,63 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt, fs,
+    path::PathBuf,
+};
+
 use clap::ValueEnum;
-use serde::Deserialize;
+use colored::Colorize;
+use serde::{
+    de::{
+        self, value::MapAccessDeserializer, DeserializeOwned, IntoDeserializer, MapAccess, Visitor,
+    },
+    Deserialize, Deserializer, Serialize,
+};
+
+use crate::{decode, encode_format::MAX_S_PORTS, task_log::logln};
+
+///
+/// Reads and merges the manifests at `paths` in order, with games in later manifests replacing games of the same name
+/// in earlier ones. Each replaced game is warned about, in case it's unintentional. Manifests ending in `.toml` are
+/// read as TOML, and all others as JSON
+///
+pub fn load_manifests<T: DeserializeOwned>(
+    paths: &[PathBuf],
+) -> Result<HashMap<String, T>, String> {
+    let mut merged = HashMap::new();
+
+    for path in paths {
+        let file =
+            fs::read(path).map_err(|err| format!("Could not read manifest {path:?}: {err}"))?;
+
+        let is_toml = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+
+        let games: HashMap<String, T> = if is_toml {
+            String::from_utf8(file)
+                .map_err(|err| err.to_string())
+                .and_then(|file| toml::from_str(&file).map_err(|err| err.to_string()))
+        } else {
+            serde_json::from_slice(&file).map_err(|err| err.to_string())
+        }
+        .map_err(|err| format!("Could not parse manifest {path:?}: {err}"))?;
+
+        let mut names = games.keys().cloned().collect::<Vec<String>>();
+        names.sort();
+
+        for name in names.iter().filter(|name| merged.contains_key(*name)) {
+            logln!(
+                "{}",
+                format!("WARNING: {name} in {path:?} replaces the earlier manifest's entry")
+                    .yellow()
+            );
+        }
+
+        merged.extend(games);
+    }
+
+    Ok(merged)
+}
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -8,6 +66,8 @@ pub struct PlatformSpecification {
     pub port_map: PlatformPortMapping,
     pub metadata: Metdata,
     pub rom: ROMName,
+    /// A flat PNG in the asset directory drawn as the background, in place of the layout's artwork
+    pub background: Option<String>,
 }
 
 /* ROM */
@@ -17,18 +77,179 @@ pub struct PlatformSpecification {
 pub struct ROMName {
     pub rom: String,
     pub melody: Option<String>,
+    /// Used to find the melody ROM if it isn't found by name
+    pub melody_hash: Option<String>,
+    /// How the melody ROM is divided into banks, for SM511/SM512 devices that select between them
+    pub melody_banks: Option<MelodyBanks>,
     pub rom_owner: Option<String>,
+    /// The romset this device is a clone of. Merged romsets store the clone's files in the parent's archive
+    pub parent: Option<String>,
     pub rom_hash: String,
+    /// The real size of the ROM, for dumps with trailing padding
+    pub rom_size: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MelodyBanks {
+    pub count: u8,
+    /// The size of each bank in bytes
+    pub size: usize,
 }
 
 /* Metdata */
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Metdata {
     // This is a year, as MAME has question marks in some years
     pub year: String,
     pub name: String,
     pub company: String,
+    /// How well the game works with the current core. Unmarked games are assumed to work
+    #[serde(default)]
+    pub status: Status,
+    /// Overrides the name shown to the user. See `display_name`
+    pub display_name: Option<String>,
+    /// Overrides the name of the output file, without extension. See `file_name`
+    pub file_name: Option<String>,
+    /// Overrides the key devices are ordered by. See `sort_name`
+    pub sort_name: Option<String>,
+    /// The territory the game was released in. See `region`
+    pub region: Option<String>,
+}
+
+///
+/// Replaces the characters in a file name, without extension, that can't appear in a filename
+///
+pub fn sanitize_file_name(name: &str) -> String {
+    name.replace(':', " -")
+        .replace(['/', '\\'], "-")
+        .trim()
+        .to_string()
+}
+
+impl Metdata {
+    ///
+    /// The name shown to the user. Falls back to MAME's `name`
+    ///
+    pub fn display_name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.name).trim()
+    }
+
+    ///
+    /// The name of the output file, without extension. Falls back to `display_name` (if it's explicitly set), then
+    /// MAME's `name` with the redundant "Game & Watch:" prefix removed. Characters that can't appear in a filename are
+    /// always replaced
+    ///
+    pub fn file_name(&self) -> String {
+        sanitize_file_name(&self.unsanitized_file_name())
+    }
+
+    ///
+    /// `file_name` before characters that can't appear in a filename are replaced
+    ///
+    pub fn unsanitized_file_name(&self) -> String {
+        match (&self.file_name, &self.display_name) {
+            (Some(file_name), _) => file_name.clone(),
+            (None, Some(display_name)) => display_name.clone(),
+            (None, None) => {
+                if self.name.to_lowercase().starts_with("game & watch:") {
+                    self.name.chars().skip("Game & Watch:".len()).collect()
+                } else {
+                    self.name.clone()
+                }
+            }
+        }
+    }
+
+    ///
+    /// The company as a directory name. Characters that can't appear in a filename on any common platform are replaced,
+    /// and an empty company is `Unknown`
+    ///
+    pub fn company_dir_name(&self) -> String {
+        let name = self
+            .company
+            .chars()
+            .map(|c| match c {
+                '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '-',
+                c if c.is_control() => '-',
+                c => c,
+            })
+            .collect::<String>();
+
+        // Windows drops trailing dots and spaces, which would let two companies share a directory
+        let name = name.trim().trim_end_matches('.').trim_end();
+
+        if name.is_empty() {
+            "Unknown".to_string()
+        } else {
+            name.to_string()
+        }
+    }
+
+    ///
+    /// The release year, or `None` if it isn't fully known
+    ///
+    pub fn year_number(&self) -> Option<u16> {
+        self.year.trim().parse().ok()
+    }
+
+    ///
+    /// The territory the game was released in, or `Region::Unknown` if unspecified. Errors if the region isn't
+    /// recognized
+    ///
+    #[allow(clippy::diverging_sub_expression)]
+    pub fn region(&self) -> Result<Region, String> {
+        guard!(let Some(region) = &self.region else {
+            return Ok(Region::Unknown);
+        });
+
+        Region::from_str(region.trim(), true).map_err(|_| format!("Unrecognized region {region:?}"))
+    }
+
+    ///
+    /// The key devices are ordered by. Falls back to `file_name`, so devices are processed in the same order their
+    /// files are listed
+    ///
+    pub fn sort_name(&self) -> String {
+        match &self.sort_name {
+            Some(sort_name) => sort_name.trim().to_string(),
+            None => self.file_name(),
+        }
+    }
+}
+
+/// The territory a game was released in
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, ValueEnum)]
+pub enum Region {
+    /// Unspecified, or released everywhere under the same name
+    #[default]
+    Unknown,
+    #[value(alias = "jp")]
+    Japan,
+    #[value(alias = "us")]
+    Usa,
+    #[value(alias = "eu")]
+    Europe,
+    Asia,
+    /// The Soviet Union, for the Elektronika clones
+    #[value(alias = "ussr")]
+    Soviet,
+}
+
+/// Ordered from least to most functional
+#[derive(
+    PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Default, Deserialize, Serialize, ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Known not to work with the current core
+    Broken,
+    /// Playable, with known issues
+    Imperfect,
+    #[default]
+    Working,
 }
 
 /* Preset Definition */
@@ -37,9 +258,42 @@ pub struct Metdata {
 pub struct PresetDefinition {
     pub cpu: CPUType,
     pub screen: Screen,
+    /// The number of joysticks on the unit. Units that don't declare it have at most one
+    pub joysticks: Option<u8>,
+}
+
+impl PresetDefinition {
+    /// The number of joysticks on the unit
+    pub fn joysticks(&self) -> u8 {
+        self.joysticks.unwrap_or(1)
+    }
+}
+
+/// The actions the core's input mux (`rtl/input_config.sv`) has no mapping for, on every CPU: the extra buttons,
+/// Service1, the power and volume controls, keypad inputs, and the mode and pause keys
+const UNMAPPED_ACTIONS: &[Action] = &[
+    Action::Button5,
+    Action::Button6,
+    Action::Button7,
+    Action::Button8,
+    Action::Service1,
+    Action::VolumeDown,
+    Action::PowerOn,
+    Action::PowerOff,
+    Action::Keypad,
+    Action::Mode,
+    Action::Pause,
+];
+
+/// What the core can read of a CPU's inputs
+pub struct InputCapabilities {
+    /// The number of S ports the core reads. Ports past this are never strobed
+    pub s_ports: usize,
+    /// Actions the core can't represent
+    pub unsupported: &'static [Action],
 }
 
-#[derive(PartialEq, Clone, Debug, Deserialize, ValueEnum)]
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize, ValueEnum)]
 #[serde(rename_all = "lowercase")]
 pub enum CPUType {
     SM5a,
@@ -56,7 +310,78 @@ pub enum CPUType {
     SM511Tiger2Bit,
 }
 
-#[derive(Debug, Deserialize)]
+impl CPUType {
+    ///
+    /// The number of S strobe lines the CPU uses for its input matrix. Every currently supported CPU strobes S1-S8;
+    /// devices wired with additional lines declare `sPortCount` in the manifest
+    ///
+    pub fn s_port_count(&self) -> usize {
+        8
+    }
+
+    ///
+    /// What the core can read of this CPU's inputs. SM5A based units strobe their input matrix from R1-R3, which the
+    /// core reads as S0-S2, and no CPU's extended S ports are read
+    ///
+    pub fn input_capabilities(&self) -> InputCapabilities {
+        match self {
+            CPUType::SM5a | CPUType::KB1013VK12 => InputCapabilities {
+                s_ports: 3,
+                unsupported: UNMAPPED_ACTIONS,
+            },
+            CPUType::SM510
+            | CPUType::SM511
+            | CPUType::SM512
+            | CPUType::SM530
+            | CPUType::SM510Tiger
+            | CPUType::SM511Tiger1Bit
+            | CPUType::SM511Tiger2Bit => InputCapabilities {
+                s_ports: 8,
+                unsupported: UNMAPPED_ACTIONS,
+            },
+        }
+    }
+
+    ///
+    /// Whether the core can represent `action` for this CPU. A custom code is supported if it decodes to a named
+    /// action that is
+    ///
+    pub fn supports_action(&self, action: &Action) -> bool {
+        // A custom code is read on whichever input line it names
+        if let Action::Custom(code) = action {
+            return match decode::action(*code) {
+                Ok(Some(NamedAction {
+                    action: Action::Custom(_),
+                    ..
+                }))
+                | Ok(None)
+                | Err(_) => false,
+                Ok(Some(named)) => self.supports_action(&named.action),
+            };
+        }
+
+        !self.input_capabilities().unsupported.contains(action)
+    }
+}
+
+///
+/// The size of the internal ROM of `cpu`, which every correctly dumped ROM for it should match. `None` if it isn't
+/// known
+///
+pub fn expected_rom_len(cpu: &CPUType) -> Option<usize> {
+    match cpu {
+        CPUType::SM5a | CPUType::KB1013VK12 => Some(0x740),
+        CPUType::SM510
+        | CPUType::SM511
+        | CPUType::SM512
+        | CPUType::SM530
+        | CPUType::SM510Tiger
+        | CPUType::SM511Tiger1Bit
+        | CPUType::SM511Tiger2Bit => Some(0x1000),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 #[serde(tag = "type")]
 pub enum Screen {
@@ -65,7 +390,7 @@ pub enum Screen {
     DualHorizontal { left: Size, right: Size },
 }
 
-#[derive(PartialEq, Debug, Deserialize)]
+#[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
 pub struct Size {
     pub width: f32,
     pub height: f32,
@@ -77,8 +402,151 @@ pub struct Size {
 #[serde(rename_all = "camelCase")]
 pub struct PlatformPortMapping {
     pub ports: Vec<Port>,
+    #[allow(dead_code)]
     pub include: Option<String>,
-    pub ground_last_index: Option<u8>,
+    /// Wider than the byte it's stored in, so an out of range value is reported with its game by `validate`
+    pub ground_last_index: Option<usize>,
+    /// Overrides the number of S ports of the CPU, for devices with additional strobe lines
+    pub s_port_count: Option<u8>,
+    /// Overrides whether unset `B` and `BA` ports are active low. See `default_active_low`
+    pub default_active_low: Option<bool>,
+}
+
+impl PlatformPortMapping {
+    ///
+    /// Every mapped action, along with a description of the port it's mapped to
+    ///
+    pub fn mapped_actions(&self) -> Vec<(String, &NamedAction)> {
+        let mut actions = vec![];
+
+        for port in &self.ports {
+            match port {
+                Port::S { index, bitmap } => {
+                    for (bit, action) in bitmap.iter().enumerate() {
+                        if let Some(action) = action {
+                            actions.push((format!("S{index} bit {bit}"), action));
+                        }
+                    }
+                }
+                Port::Acl {
+                    bit: Some(action), ..
+                } => actions.push(("ACL".to_string(), action)),
+                Port::B { bit: Some(action) } => actions.push(("B".to_string(), action)),
+                Port::BA { bit: Some(action) } => actions.push(("BA".to_string(), action)),
+                _ => {}
+            }
+        }
+
+        actions
+            .into_iter()
+            .filter(|(_, action)| action.action != Action::Unused)
+            .collect()
+    }
+
+    /// The number of S ports this device strobes for input
+    pub fn s_port_count(&self, cpu: &CPUType) -> usize {
+        self.s_port_count
+            .map_or_else(|| cpu.s_port_count(), |count| count as usize)
+    }
+
+    ///
+    /// Whether unset `B` and `BA` ports are active low. Falls back to `true`, as both normally have a pull-up resistor
+    /// and read high when nothing drives them
+    ///
+    pub fn default_active_low(&self) -> bool {
+        self.default_active_low.unwrap_or(true)
+    }
+
+    ///
+    /// Every problem with the ports that would otherwise only be found when encoding: S and ground indexes outside the
+    /// device's S ports, S indexes mapped more than once, and B, BA, or ACL declared more than once
+    ///
+    pub fn validate(&self, cpu: &CPUType) -> Vec<String> {
+        let s_port_count = self.s_port_count(cpu);
+
+        let mut errors = vec![];
+
+        if s_port_count > MAX_S_PORTS {
+            errors.push(format!(
+                "Device has {s_port_count} S ports, but at most {MAX_S_PORTS} are supported"
+            ));
+        }
+
+        let mut s_indexes = BTreeSet::new();
+        let mut single_ports = BTreeSet::new();
+
+        for port in &self.ports {
+            let single_port = match port {
+                Port::S { index, .. } => {
+                    if *index >= s_port_count {
+                        errors.push(format!(
+                            "Port index {index} is out of bounds for a device with {s_port_count} S ports"
+                        ));
+                    } else if !s_indexes.insert(*index) {
+                        errors.push(format!("S port {index} is mapped more than once"));
+                    }
+
+                    continue;
+                }
+                Port::Acl { .. } => "ACL",
+                Port::B { .. } => "B",
+                Port::BA { .. } => "BA",
+            };
+
+            if !single_ports.insert(single_port) {
+                errors.push(format!("Port {single_port} is declared more than once"));
+            }
+        }
+
+        if let Some(ground_last_index) = self.ground_last_index {
+            if ground_last_index >= s_port_count {
+                errors.push(format!(
+                    "Ground index {ground_last_index} is out of bounds for a device with {s_port_count} S ports"
+                ));
+            }
+        }
+
+        errors
+    }
+
+    ///
+    /// Finds the action bound to a MAME input port tag (`IN.0`, `ACL`, `B`, `BA`) and bit mask, as used by the
+    /// `inputtag`/`inputmask` attributes of layout elements
+    ///
+    #[allow(clippy::diverging_sub_expression)]
+    pub fn action_for_input(&self, tag: &str, mask: u32) -> Option<&NamedAction> {
+        let s_index = tag
+            .strip_prefix("IN.")
+            .and_then(|index| index.parse::<usize>().ok());
+
+        for port in &self.ports {
+            let action = match port {
+                Port::S { index, bitmap } => {
+                    if s_index != Some(*index) {
+                        continue;
+                    }
+
+                    guard!(let Some(bit) = (0..bitmap.len()).find(|bit| mask & (1 << bit) != 0) else {
+                        continue;
+                    });
+
+                    bitmap[bit].as_ref()
+                }
+                Port::Acl { bit, .. } if tag == "ACL" => bit.as_ref(),
+                Port::B { bit } if tag == "B" => bit.as_ref(),
+                Port::BA { bit } if tag == "BA" => bit.as_ref(),
+                _ => continue,
+            };
+
+            if let Some(action) = action {
+                if action.action != Action::Unused {
+                    return Some(action);
+                }
+            }
+        }
+
+        None
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -89,8 +557,11 @@ pub enum Port {
         index: usize,
         bitmap: [Option<NamedAction>; 4],
     },
-    ACL {
+    Acl {
         bit: Option<NamedAction>,
+        /// How the unit's ACL (All Clear) switch resets the CPU
+        #[serde(default)]
+        mode: AclMode,
     },
     B {
         bit: Option<NamedAction>,
@@ -100,14 +571,57 @@ pub enum Port {
     },
 }
 
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AclMode {
+    /// The CPU is held in reset only while ACL is pressed
+    #[default]
+    Momentary,
+    /// A press latches the reset, which is held until ACL is pressed again
+    Latched,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct NamedAction {
+    #[serde(deserialize_with = "deserialize_action")]
     pub action: Action,
     pub active_low: bool,
     pub name: Option<String>,
 }
 
+/// The code deprecated bare `"custom"` actions are read as, which the extractor gave every custom input
+pub const LEGACY_CUSTOM_CODE: u8 = 29;
+
+///
+/// Reads an action, accepting the deprecated bare `"custom"` alongside every current form
+///
+fn deserialize_action<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Action, D::Error> {
+    struct ActionVisitor;
+
+    impl<'de> Visitor<'de> for ActionVisitor {
+        type Value = Action;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("an action name, or a map such as {\"custom\": 30}")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Action, E> {
+            if value == "custom" {
+                return Ok(Action::Custom(LEGACY_CUSTOM_CODE));
+            }
+
+            Action::deserialize(value.into_deserializer())
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Action, A::Error> {
+            Action::deserialize(MapAccessDeserializer::new(map))
+        }
+    }
+
+    deserializer.deserialize_any(ActionVisitor)
+}
+
 #[derive(PartialEq, Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum Action {
@@ -140,6 +654,76 @@ pub enum Action {
     PowerOn,
     PowerOff,
     Keypad,
-    Custom,
+    /// A raw core input code, written as `{"custom": 30}`, for inputs with no named action. The deprecated bare
+    /// `"custom"` is read as `LEGACY_CUSTOM_CODE`
+    Custom(u8),
+    Mode,
+    Pause,
+    Time,
+    Alarm,
     Unused,
 }
+
+impl Action {
+    ///
+    /// Which joystick of the unit this action is on, counting from 1. `None` for actions that aren't on a joystick
+    ///
+    pub fn joystick(&self) -> Option<u8> {
+        match self {
+            Action::JoyUp
+            | Action::JoyDown
+            | Action::JoyLeft
+            | Action::JoyRight
+            | Action::LeftJoyUp
+            | Action::LeftJoyDown
+            | Action::LeftJoyLeft
+            | Action::LeftJoyRight => Some(1),
+            Action::RightJoyUp
+            | Action::RightJoyDown
+            | Action::RightJoyLeft
+            | Action::RightJoyRight => Some(2),
+            _ => None,
+        }
+    }
+
+    ///
+    /// The MAME input type this action is extracted from
+    ///
+    pub fn mame_input_type(&self) -> &'static str {
+        match self {
+            Action::JoyUp => "IPT_JOYSTICK_UP",
+            Action::JoyDown => "IPT_JOYSTICK_DOWN",
+            Action::JoyLeft => "IPT_JOYSTICK_LEFT",
+            Action::JoyRight => "IPT_JOYSTICK_RIGHT",
+            Action::LeftJoyUp => "IPT_JOYSTICKLEFT_UP",
+            Action::LeftJoyDown => "IPT_JOYSTICKLEFT_DOWN",
+            Action::LeftJoyLeft => "IPT_JOYSTICKLEFT_LEFT",
+            Action::LeftJoyRight => "IPT_JOYSTICKLEFT_RIGHT",
+            Action::RightJoyUp => "IPT_JOYSTICKRIGHT_UP",
+            Action::RightJoyDown => "IPT_JOYSTICKRIGHT_DOWN",
+            Action::RightJoyLeft => "IPT_JOYSTICKRIGHT_LEFT",
+            Action::RightJoyRight => "IPT_JOYSTICKRIGHT_RIGHT",
+            Action::Button1 => "IPT_BUTTON1",
+            Action::Button2 => "IPT_BUTTON2",
+            Action::Button3 => "IPT_BUTTON3",
+            Action::Button4 => "IPT_BUTTON4",
+            Action::Button5 => "IPT_BUTTON5",
+            Action::Button6 => "IPT_BUTTON6",
+            Action::Button7 => "IPT_BUTTON7",
+            Action::Button8 => "IPT_BUTTON8",
+            Action::Select => "IPT_SELECT",
+            Action::Start1 => "IPT_START1",
+            Action::Start2 => "IPT_START2",
+            Action::Service1 => "IPT_SERVICE1",
+            Action::Service2 => "IPT_SERVICE2",
+            Action::VolumeDown => "IPT_VOLUME_DOWN",
+            Action::PowerOn => "IPT_POWER_ON",
+            Action::PowerOff => "IPT_POWER_OFF",
+            Action::Keypad => "IPT_KEYPAD",
+            Action::Custom(_) => "IPT_CUSTOM",
+            // MAME has no dedicated input types for these keys, and names them on custom inputs instead
+            Action::Mode | Action::Pause | Action::Time | Action::Alarm => "IPT_CUSTOM",
+            Action::Unused => "IPT_UNUSED",
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::{fmt::Write, fs, path::Path};
+
+use crate::{
+    decode::{mask_entries, MaskEntry},
+    encode_format::{build_mask_map, MaskEntryFormat},
+    error::BuildError,
+    manifest::PlatformSpecification,
+    render::RenderedData,
+};
+
+///
+/// Writes the mask entries of the rendered primary images to `export_dir` as text, one `id, start_x, y, length` line
+/// per entry in encoded order, so stray runs can be found and compared against the layout. Each line is annotated
+/// with the `segment.column.row` title of its id, as written in the MAME SVG
+///
+pub fn export_mask(
+    platform_name: &str,
+    platform: &PlatformSpecification,
+    rendered: &RenderedData,
+    export_dir: &Path,
+) -> Result<(), BuildError> {
+    let format = MaskEntryFormat::for_ids(&rendered.pixels_to_mask_id);
+    let mask_block = build_mask_map(
+        platform_name,
+        &rendered.pixels_to_mask_id,
+        rendered.resolution,
+        format,
+    )
+    .map_err(BuildError::MaskOverflow)?;
+    let entries = mask_entries(&mask_block, format);
+
+    let mut output = String::new();
+
+    // Writing to a String can't fail
+    writeln!(
+        output,
+        "// {} ({platform_name}), {}x{} images, {} entries",
+        platform.metadata.display_name(),
+        rendered.resolution.width,
+        rendered.resolution.height,
+        entries.len()
+    )
+    .unwrap();
+    writeln!(output, "// id, start_x, y, length").unwrap();
+
+    for MaskEntry { id, x, y, length } in entries {
+        writeln!(
+            output,
+            "{id:#05x}, {x}, {y}, {length} // {}.{}.{}",
+            id >> 6,
+            (id >> 2) & 0xF,
+            id & 0x3
+        )
+        .unwrap();
+    }
+
+    fs::create_dir_all(export_dir).map_err(|err| {
+        BuildError::io(
+            format!("Could not create mask dump directory {export_dir:?}"),
+            err,
+        )
+    })?;
+
+    let path = export_dir.join(format!("{platform_name}_mask.txt"));
+
+    fs::write(&path, output)
+        .map_err(|err| BuildError::io(format!("Could not write mask to {path:?}"), err))
+}
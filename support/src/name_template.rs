@@ -0,0 +1,123 @@
+use crate::manifest::{sanitize_file_name, PlatformSpecification};
+
+///
+/// A pattern for the names of the created ROMs, such as `{company}_{name:lower}`. Each placeholder is replaced with a
+/// value from the game's manifest entry, optionally lowercased or uppercased with a `:lower` or `:upper` suffix
+///
+#[derive(Clone, Debug)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Text(String),
+    Field { field: Field, case: Case },
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    /// The default file name, without the "Game & Watch:" prefix
+    Name,
+    Company,
+    Cpu,
+    Year,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Case {
+    Unchanged,
+    Lower,
+    Upper,
+}
+
+const FIELD_NAMES: &str = "{name}, {company}, {cpu}, {year}";
+
+impl NameTemplate {
+    #[allow(clippy::diverging_sub_expression)]
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = vec![];
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+
+            guard!(let Some(length) = rest[start..].find('}') else {
+                return Err(format!("Unclosed placeholder in name template \"{template}\""));
+            });
+
+            let placeholder = &rest[start + 1..start + length];
+            let (field, case) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+
+            let field = match field.trim() {
+                "name" => Field::Name,
+                "company" => Field::Company,
+                "cpu" => Field::Cpu,
+                "year" => Field::Year,
+                _ => {
+                    return Err(format!(
+                        "Unknown placeholder {{{placeholder}}} in name template. Available placeholders are {FIELD_NAMES}"
+                    ))
+                }
+            };
+
+            let case = match case.trim() {
+                "" => Case::Unchanged,
+                "lower" => Case::Lower,
+                "upper" => Case::Upper,
+                _ => {
+                    return Err(format!(
+                        "Unknown modifier in {{{placeholder}}}. Use :lower or :upper"
+                    ))
+                }
+            };
+
+            parts.push(Part::Field { field, case });
+            rest = &rest[start + length + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+
+        if !parts.iter().any(|part| matches!(part, Part::Field { .. })) {
+            // Every game would be written to the same file
+            return Err(format!(
+                "Name template \"{template}\" must contain a placeholder, such as {{name}}"
+            ));
+        }
+
+        Ok(NameTemplate { parts })
+    }
+
+    ///
+    /// The file name, without extension, for `platform`. The result is sanitized like the default file name
+    ///
+    pub fn expand(&self, platform: &PlatformSpecification) -> String {
+        let name = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Field { field, case } => {
+                    let value = match field {
+                        Field::Name => platform.metadata.unsanitized_file_name().trim().to_string(),
+                        Field::Company => platform.metadata.company.trim().to_string(),
+                        Field::Cpu => format!("{:?}", platform.device.cpu),
+                        Field::Year => platform.metadata.year.trim().to_string(),
+                    };
+
+                    match case {
+                        Case::Unchanged => value,
+                        Case::Lower => value.to_lowercase(),
+                        Case::Upper => value.to_uppercase(),
+                    }
+                }
+            })
+            .collect::<String>();
+
+        sanitize_file_name(&name)
+    }
+}
@@ -1,15 +1,27 @@
-use std::{collections::HashSet, path::Path};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::Path,
+    time::Instant,
+};
 
+use clap::ValueEnum;
+use color_quant::NeuQuant;
 use image::{imageops::FilterType, DynamicImage, ImageBuffer, Rgba};
-use resvg::tiny_skia::{Pixmap, PixmapPaint, PremultipliedColorU8};
+use log::trace;
+use resvg::tiny_skia::{Color, ColorU8, IntRect, Pixmap, PixmapPaint, PremultipliedColorU8};
 use tiny_skia_path::Transform;
 
 use crate::{
+    debug_overlay::draw_input_labels,
+    encode_format::QUANTIZER_SAMPLE_FACTOR,
+    error::BuildError,
     layout::{
         BlendType, Bounds, Element, MameLayout, NameElementChildren, Screen, View, ViewElement,
     },
     manifest::{self, PlatformSpecification, PresetDefinition},
-    svg_manage::build_svg,
+    svg_manage::{build_svg, OverlapTracker},
+    task_log::logln,
     HEIGHT, WIDTH,
 };
 
@@ -17,19 +29,134 @@ pub struct RenderedData {
     pub background_bytes: Pixmap,
     pub mask_bytes: Pixmap,
     pub pixels_to_mask_id: Vec<Option<u16>>,
+    pub input_geometry: Vec<InputGeometry>,
+    /// The size of the images, which is smaller than the canvas when cropped
+    pub resolution: Resolution,
+    /// The position of the top left of the images on the canvas. Only non-zero when cropped
+    pub offset: (usize, usize),
+    /// A warning for each pair of segments with different mask ids that overlap
+    pub overlap_warnings: Vec<String>,
+}
+
+/// The size of the canvas a device is rendered to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Resolution {
+    /// The resolution of the primary images, which every core supports
+    pub const STANDARD: Resolution = Resolution {
+        width: WIDTH,
+        height: HEIGHT,
+    };
+}
+
+/// The position of a clickable physical input from the layout, in canvas coordinates
+#[derive(Clone, Debug)]
+pub struct InputGeometry {
+    /// The MAME input port tag, such as `IN.0` or `ACL`
+    pub tag: String,
+    pub mask: u32,
+    pub dimensions: ImageDimensions,
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    /// Write debug PNGs of the rendered images to the asset directory
+    pub debug: bool,
+    /// The color the canvas is initialized to, before any element is drawn. Transparent by default
+    pub clear_color: Color,
+    /// The gamma the background's colors are corrected by. 1 leaves them unchanged
+    pub gamma: f32,
+    /// Multiplies the background's colors after gamma correction. 1 leaves them unchanged
+    pub brightness: f32,
+    /// Dither the background to a palette of this many colors, for backgrounds reduced to a palette when encoded
+    pub dither_colors: Option<usize>,
+    pub mirror: Mirror,
+    /// Applied after mirroring
+    pub rotation: Rotation,
+    pub mask_tiebreak: MaskTiebreak,
+    /// The canvas size. Layouts are scaled to fit it, preserving their aspect ratio
+    pub resolution: Resolution,
+    /// Crop the images to the bounding box of their visible pixels
+    pub crop: bool,
+    /// Fail the render if segments with different mask ids overlap, instead of returning warnings
+    pub strict_overlap: bool,
+}
+
+/// Which mask id a pixel is assigned when more than one segment covers it
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum MaskTiebreak {
+    /// The segment drawn last wins
+    DrawOrder,
+    LowestId,
+    HighestId,
+}
+
+impl MaskTiebreak {
+    pub fn resolve(&self, existing: Option<u16>, new: u16) -> u16 {
+        match (self, existing) {
+            (MaskTiebreak::LowestId, Some(existing)) => existing.min(new),
+            (MaskTiebreak::HighestId, Some(existing)) => existing.max(new),
+            _ => new,
+        }
+    }
+}
+
+/// Flips the rendered device, for clones that mirror the original layout
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Mirror {
+    None,
+    /// Flip left to right
+    Horizontal,
+    /// Flip top to bottom
+    Vertical,
+}
+
+/// Rotates the rendered device clockwise, for cores that expect their images in a different orientation
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Rotation {
+    #[value(name = "0")]
+    None,
+    #[value(name = "90")]
+    Clockwise90,
+    #[value(name = "180")]
+    Clockwise180,
+    #[value(name = "270")]
+    Clockwise270,
+}
+
+impl Rotation {
+    /// The size of a buffer of `resolution` once rotated
+    pub fn rotate_resolution(&self, resolution: Resolution) -> Resolution {
+        match self {
+            Rotation::None | Rotation::Clockwise180 => resolution,
+            Rotation::Clockwise90 | Rotation::Clockwise270 => Resolution {
+                width: resolution.height,
+                height: resolution.width,
+            },
+        }
+    }
 }
 
+#[allow(clippy::diverging_sub_expression)]
 pub fn render(
     platform_name: &str,
     layout: &View,
     layout_manifest: &MameLayout,
     platform: &PlatformSpecification,
     asset_dir: &Path,
-    debug: bool,
-) -> Result<RenderedData, String> {
+    options: &RenderOptions,
+) -> Result<RenderedData, BuildError> {
+    let resolution = options.resolution;
+    let Resolution { width, height } = resolution;
+
     let mut view_bounds: Option<Bounds> = None;
     let mut elements: Vec<&Element> = vec![];
     let mut screens: Vec<&Screen> = vec![];
+    let mut input_elements: Vec<&Element> = vec![];
 
     let mut filtered_items: Vec<&ViewElement> = vec![];
 
@@ -42,14 +169,19 @@ pub fn render(
             ViewElement::Bounds(bounds) => {
                 // Filter out
                 if view_bounds.is_some() {
-                    return Err(format!(
+                    return Err(BuildError::InvalidLayout(format!(
                         "View {} in {platform_name} has multiple bounds. Skipping",
                         layout.name
-                    ));
+                    )));
                 }
                 view_bounds = Some(bounds.to_xy());
             }
             ViewElement::Element(element) | ViewElement::Overlay(element) => {
+                // Inputs frequently share a single ref, so record them before deduplicating
+                if element.input().is_some() {
+                    input_elements.push(element);
+                }
+
                 if already_applied_refs.contains(&element.ref_name) {
                     continue;
                 }
@@ -59,13 +191,13 @@ pub fn render(
                 match element.ref_name.to_lowercase().as_str() {
                     "dust" | "bubbles" | "unit" | "backdrop" => {
                         // Ignore these features
-                        println!("Ignoring element by name {}", element.ref_name);
+                        logln!("Ignoring element by name {}", element.ref_name);
                         continue;
                     }
                     value => {
                         // if value.starts_with("fix") || value.starts_with("gradient") {
                         if value.starts_with("gradient") {
-                            println!("Ignoring element by name {}", element.ref_name);
+                            logln!("Ignoring element by name {}", element.ref_name);
                             continue;
                         }
                     }
@@ -90,8 +222,18 @@ pub fn render(
     let mut max_common_y: Option<i32> = None;
 
     // Calculate max bounds
-    // Only Element nodes are used, as Screen's should not drive the overall picture size (they sometimes overrun it)
-    for bounds in elements.iter().map(|e| e.bounds.to_xy()) {
+    // Only Element nodes are used, as Screen's should not drive the overall picture size (they sometimes overrun it).
+    // Layouts without any artwork, such as those for a PNG background, only have their screens
+    let bounds_items = if elements.is_empty() {
+        screens
+            .iter()
+            .map(|s| s.bounds.to_xy())
+            .collect::<Vec<Bounds>>()
+    } else {
+        elements.iter().map(|e| e.bounds.to_xy()).collect()
+    };
+
+    for bounds in bounds_items {
         if let Some(inner_min_x) = min_x {
             if inner_min_x > bounds.x {
                 min_x = Some(bounds.x);
@@ -143,8 +285,8 @@ pub fn render(
         height: max_height - max_common_y,
     };
 
-    let x_ratio = WIDTH as f32 / view_bounds.width as f32;
-    let y_ratio = HEIGHT as f32 / view_bounds.height as f32;
+    let x_ratio = width as f32 / view_bounds.width as f32;
+    let y_ratio = height as f32 / view_bounds.height as f32;
 
     let (ratio, x_scale) = if x_ratio < y_ratio {
         // Scaling based on X
@@ -155,41 +297,78 @@ pub fn render(
 
     let (x_offset, y_offset) = if !x_scale {
         let scaled_width = view_bounds.width as f32 * ratio;
-        ((WIDTH as i32 - scaled_width.round() as i32) / 2, 0)
+        ((width as i32 - scaled_width.round() as i32) / 2, 0)
     } else {
         let scaled_height = view_bounds.height as f32 * ratio;
-        (0, (HEIGHT as i32 - scaled_height.round() as i32) / 2)
+        (0, (height as i32 - scaled_height.round() as i32) / 2)
     };
 
+    // Record where each physical input sits on the canvas
+    let mut input_geometry: Vec<InputGeometry> = input_elements
+        .iter()
+        .filter_map(|element| {
+            let (tag, mask) = element.input()?;
+            let bounds = normalize_bounds(element.bounds.to_xy(), max_common_x, max_common_y);
+
+            Some(InputGeometry {
+                tag: tag.to_string(),
+                mask,
+                dimensions: ImageDimensions::new(&view_bounds, &bounds, ratio, x_offset, y_offset),
+            })
+        })
+        .collect();
+
     // Keep track of the set of pixels that make up each screen
-    let mut pixels_to_mask_id: Vec<Option<u16>> = vec![None; WIDTH * HEIGHT];
+    let mut pixels_to_mask_id: Vec<Option<u16>> = vec![None; width * height];
+    let mut overlaps = OverlapTracker::new(resolution);
+    // The SVG elements drawn for each mask id, for the debug images
+    let mut segment_elements: BTreeMap<u16, Vec<String>> = BTreeMap::new();
 
-    let mut background_pixmap = Pixmap::new(WIDTH as u32, HEIGHT as u32).unwrap();
-    let mut mask_pixmap = Pixmap::new(WIDTH as u32, HEIGHT as u32).unwrap();
+    let mut background_pixmap = Pixmap::new(width as u32, height as u32).unwrap();
+    // Areas not covered by any element keep this color, such as the base tint of an LCD
+    background_pixmap.fill(options.clear_color);
+
+    let mut mask_pixmap = Pixmap::new(width as u32, height as u32).unwrap();
+
+    if let Some(background) = &platform.background {
+        let file_path = asset_dir.join(background);
+
+        guard!(let Ok(image) = Pixmap::load_png(&file_path) else {
+            return Err(BuildError::AssetMissing(format!("Missing background \"{background}\" which was not at {file_path:?}")));
+        });
+
+        // The background covers the whole layout, in place of its artwork
+        let dimensions =
+            ImageDimensions::new(&view_bounds, &view_bounds, ratio, x_offset, y_offset);
+
+        background_pixmap.draw_pixmap(
+            dimensions.x,
+            dimensions.y,
+            scale_image(image, &dimensions)?.as_ref(),
+            &PixmapPaint::default(),
+            Transform::identity(),
+            None,
+        );
+    }
 
     // We currently ignore offsetting by X/Y at the parent view, so the child positions are subtracted
     // from the parent's offset
     for item in &filtered_items {
+        let item_start = Instant::now();
+
         match item {
             ViewElement::Element(element) | ViewElement::Overlay(element) => {
-                if layout_manifest
-                    .element
-                    .iter()
-                    .find(|e| {
-                        e.name == element.ref_name
-                            && e.items
-                                .iter()
-                                .find(|p| {
-                                    if let NameElementChildren::Image(_) = **p {
-                                        true
-                                    } else {
-                                        false
-                                    }
-                                })
-                                .is_some()
-                    })
-                    .is_none()
-                {
+                if platform.background.is_some() {
+                    // Replaced by the background
+                    continue;
+                }
+
+                if !layout_manifest.element.iter().any(|e| {
+                    e.name == element.ref_name
+                        && e.items
+                            .iter()
+                            .any(|p| matches!(p, NameElementChildren::Image(_)))
+                }) {
                     // There is no defined element with this name OR the defined element does not have image data
                     // Skip
                     continue;
@@ -202,60 +381,18 @@ pub fn render(
                 // A bug in either tiny_skia or image prevents transparency from working correctly when imported
                 // through image, so we import in tiny_skia and convert
                 guard!(let Ok(image) = Pixmap::load_png(&file_path) else {
-                    return Err(format!("Missing element asset \"{}\" which was not at {file_path:?}", element.ref_name));
+                    return Err(BuildError::AssetMissing(format!("Missing element asset \"{}\" which was not at {file_path:?}", element.ref_name)));
                 });
 
-                let image = ImageBuffer::<Rgba<u8>, Vec<u8>>::from_vec(
-                    image.width(),
-                    image.height(),
-                    image.take(),
-                )
-                .expect("Could not convert image data");
-
-                let element_bounds = element.bounds.to_xy();
-
-                let x = if element_bounds.x >= 0 {
-                    // Only normalize to 0 if we started out positive
-                    (element_bounds.x - max_common_x).max(0)
-                } else {
-                    element_bounds.x - max_common_x
-                };
-
-                let y = if element_bounds.y >= 0 {
-                    // Only normalize to 0 if we started out positive
-                    (element_bounds.y - max_common_y).max(0)
-                } else {
-                    element_bounds.y - max_common_y
-                };
-
-                let element_bounds = Bounds {
-                    x,
-                    y,
-                    width: element_bounds.width,
-                    height: element_bounds.height,
-                };
+                let element_bounds =
+                    normalize_bounds(element.bounds.to_xy(), max_common_x, max_common_y);
 
                 let dimensions =
                     ImageDimensions::new(&view_bounds, &element_bounds, ratio, x_offset, y_offset);
 
-                let image: DynamicImage = DynamicImage::ImageRgba8(image).resize_exact(
-                    dimensions.width,
-                    dimensions.height,
-                    FilterType::CatmullRom,
-                );
-
-                // Dimensions might change by a pixel as part of resizing
-                let image_width = image.width();
-                let image_height = image.height();
-
-                guard!(let Some(image_map) = Pixmap::from_vec(
-                    image.into_bytes(),
-                    tiny_skia_path::IntSize::from_wh(image_width, image_height).unwrap(),
-                ) else {
-                    return Err(format!("Could not convert PNG into Pixmap"));
-                });
+                let image_map = scale_image(image, &dimensions)?;
 
-                let mut aligned_image_pixmap = Pixmap::new(WIDTH as u32, HEIGHT as u32).unwrap();
+                let mut aligned_image_pixmap = Pixmap::new(width as u32, height as u32).unwrap();
 
                 // This is inefficient, but I don't want to calculate the bounds changes
                 aligned_image_pixmap.draw_pixmap(
@@ -284,7 +421,7 @@ pub fn render(
                 let pixels = aligned_image_pixmap.pixels();
                 let mask_pixels = mask_pixmap.pixels_mut();
                 let background_pixels = background_pixmap.pixels_mut();
-                for i in 0..WIDTH * HEIGHT {
+                for i in 0..width * height {
                     let pixel = pixels[i];
                     if pixel.alpha() == 0 {
                         continue;
@@ -310,6 +447,12 @@ pub fn render(
                         background_pixels[i] = blend_func(background_pixel, pixel);
                     }
                 }
+
+                trace!(
+                    "Drew element \"{}\" in {:?}",
+                    element.ref_name,
+                    item_start.elapsed()
+                );
             }
             ViewElement::Screen(screen) => {
                 let file_path = asset_dir.join("foo").with_file_name(screen_filename(
@@ -318,43 +461,36 @@ pub fn render(
                     &platform.device,
                 ));
 
-                let alternate_file_path = platform.rom.rom_owner.as_ref().and_then(|parent| {
-                    Some(asset_dir.join("foo").with_file_name(screen_filename(
+                let alternate_file_path = platform.rom.rom_owner.as_ref().map(|parent| {
+                    asset_dir.join("foo").with_file_name(screen_filename(
                         screen.index as usize,
-                        &parent,
+                        parent,
                         &platform.device,
-                    )))
+                    ))
                 });
 
-                let bounds = screen.bounds.to_xy();
-
-                let x = if bounds.x >= 0 {
-                    // Only normalize to 0 if we started out positive
-                    (bounds.x - max_common_x).max(0)
-                } else {
-                    bounds.x - max_common_x
-                };
-
-                let y = if bounds.y >= 0 {
-                    // Only normalize to 0 if we started out positive
-                    (bounds.y - max_common_y).max(0)
-                } else {
-                    bounds.y - max_common_y
-                };
-
-                let bounds = Bounds {
-                    x,
-                    y,
-                    width: bounds.width,
-                    height: bounds.height,
-                };
+                let bounds = normalize_bounds(screen.bounds.to_xy(), max_common_x, max_common_y);
 
                 let dimensions =
                     ImageDimensions::new(&view_bounds, &bounds, ratio, x_offset, y_offset);
 
                 // TODO: We don't really have a way to scale SVGs that won't result in a quality loss
                 // so that isn't handled here
-                let rendered_svg = build_svg(&file_path, &alternate_file_path, &dimensions)?;
+                let rendered_svg = build_svg(
+                    &file_path,
+                    &alternate_file_path,
+                    &dimensions,
+                    resolution,
+                    options.mask_tiebreak,
+                    &mut overlaps,
+                )?;
+
+                for (id, element) in &rendered_svg.elements {
+                    segment_elements
+                        .entry(*id)
+                        .or_default()
+                        .push(element.clone());
+                }
 
                 // Draw actual LCD pixels
                 mask_pixmap.draw_pixmap(
@@ -368,18 +504,28 @@ pub fn render(
                 );
 
                 // Combine this screen into the global pixel ID map
-                // If both have IDs, latest wins
-                for i in 0..WIDTH * HEIGHT {
-                    if let Some(new_svg_id) = rendered_svg.pixel_pos_to_id[i] {
-                        // Use this, replacing any existing pixel
-                        pixels_to_mask_id[i] = Some(new_svg_id);
+                // If both have IDs, the tiebreak decides
+                for (pixel_id, new_svg_id) in pixels_to_mask_id
+                    .iter_mut()
+                    .zip(rendered_svg.pixel_pos_to_id.iter())
+                {
+                    if let Some(new_svg_id) = new_svg_id {
+                        *pixel_id = Some(options.mask_tiebreak.resolve(*pixel_id, *new_svg_id));
                     }
                 }
+
+                trace!("Drew screen {} in {:?}", screen.index, item_start.elapsed());
             }
             ViewElement::Bounds(_) => {}
         }
     }
 
+    let overlap_warnings = overlaps.warnings();
+
+    if options.strict_overlap && !overlap_warnings.is_empty() {
+        return Err(BuildError::InvalidLayout(overlap_warnings.join("\n")));
+    }
+
     let mut output_mask = background_pixmap.clone();
 
     // Draw mask over top of background, so transparency can blend to the correct colors
@@ -392,12 +538,113 @@ pub fn render(
         None,
     );
 
-    if debug {
-        let debug_path = asset_dir.join(format!("{platform_name}.png"));
-        let debug_background_path = asset_dir.join(format!("{platform_name}_background.png"));
-        let debug_mask_path = asset_dir.join(format!("{platform_name}_mask.png"));
+    // Only the background is corrected, as the mask image was composited above
+    correct_colors(
+        background_pixmap.pixels_mut(),
+        options.gamma,
+        options.brightness,
+    );
+
+    if options.mirror != Mirror::None {
+        // Mask ids are unchanged, only their positions move
+        mirror_pixels(background_pixmap.pixels_mut(), options.mirror, resolution);
+        mirror_pixels(mask_pixmap.pixels_mut(), options.mirror, resolution);
+        mirror_pixels(output_mask.pixels_mut(), options.mirror, resolution);
+        mirror_pixels(&mut pixels_to_mask_id, options.mirror, resolution);
+
+        for input in &mut input_geometry {
+            mirror_dimensions(&mut input.dimensions, options.mirror, resolution);
+        }
+    }
 
-        let mut debug_pixmap = Pixmap::new(WIDTH as u32, HEIGHT as u32).unwrap();
+    // Everything past here is in the rotated canvas, which swaps its width and height for quarter turns
+    let canvas = options.rotation.rotate_resolution(resolution);
+
+    if options.rotation != Rotation::None {
+        // As with mirroring, mask ids are unchanged and only their positions move
+        background_pixmap = rotate_pixmap(&background_pixmap, options.rotation);
+        mask_pixmap = rotate_pixmap(&mask_pixmap, options.rotation);
+        output_mask = rotate_pixmap(&output_mask, options.rotation);
+        pixels_to_mask_id = rotate_pixels(&pixels_to_mask_id, options.rotation, resolution);
+
+        for input in &mut input_geometry {
+            rotate_dimensions(&mut input.dimensions, options.rotation, resolution);
+        }
+    }
+
+    let mut image_resolution = canvas;
+    let mut offset = (0, 0);
+
+    if options.crop {
+        if let Some(bounds) = crop_bounds(&[&background_pixmap, &output_mask], canvas) {
+            let crop = |pixmap: &Pixmap| {
+                pixmap
+                    .clone_rect(bounds)
+                    .expect("Crop bounds are within the canvas")
+            };
+
+            background_pixmap = crop(&background_pixmap);
+            mask_pixmap = crop(&mask_pixmap);
+            output_mask = crop(&output_mask);
+
+            let (left, top) = (bounds.x() as usize, bounds.y() as usize);
+            image_resolution = Resolution {
+                width: bounds.width() as usize,
+                height: bounds.height() as usize,
+            };
+
+            pixels_to_mask_id = pixels_to_mask_id
+                .chunks_exact(canvas.width)
+                .skip(top)
+                .take(image_resolution.height)
+                .flat_map(|row| &row[left..left + image_resolution.width])
+                .copied()
+                .collect();
+
+            for input in &mut input_geometry {
+                input.dimensions.x -= left as i32;
+                input.dimensions.y -= top as i32;
+            }
+
+            logln!(
+                "Cropped images to {}x{} at ({left}, {top})",
+                image_resolution.width,
+                image_resolution.height
+            );
+
+            offset = (left, top);
+        }
+    }
+
+    // Dithered last, so the error is only diffused between pixels that are kept
+    let undithered = options.dither_colors.map(|colors| {
+        let undithered = background_pixmap.clone();
+        dither_background(&mut background_pixmap, colors);
+
+        undithered
+    });
+
+    if options.debug {
+        // Non-standard resolutions are distinguished by their size. Kept apart from the artwork, whose element images
+        // could share these names
+        let debug_dir = if resolution == Resolution::STANDARD {
+            asset_dir.join("debug")
+        } else {
+            asset_dir.join(format!("debug_{width}x{height}"))
+        };
+
+        fs::create_dir_all(&debug_dir).map_err(|err| {
+            BuildError::io(
+                format!("Could not create debug image directory {debug_dir:?}"),
+                err,
+            )
+        })?;
+
+        let mut debug_pixmap = Pixmap::new(
+            image_resolution.width as u32,
+            image_resolution.height as u32,
+        )
+        .unwrap();
 
         debug_pixmap.draw_pixmap(
             0,
@@ -417,18 +664,405 @@ pub fn render(
             None,
         );
 
-        debug_pixmap.save_png(&debug_path).unwrap();
-        background_pixmap.save_png(&debug_background_path).unwrap();
-        output_mask.save_png(&debug_mask_path).unwrap();
+        draw_input_labels(&mut debug_pixmap, &input_geometry, &platform.port_map);
+
+        debug_pixmap
+            .save_png(debug_dir.join(format!("{platform_name}.png")))
+            .unwrap();
+        background_pixmap
+            .save_png(debug_dir.join("background.png"))
+            .unwrap();
+        output_mask.save_png(debug_dir.join("mask.png")).unwrap();
+
+        if let Some(undithered) = &undithered {
+            undithered
+                .save_png(debug_dir.join("background_undithered.png"))
+                .unwrap();
+        }
+
+        write_segment_images(
+            &debug_dir.join("masks"),
+            &output_mask,
+            &pixels_to_mask_id,
+            &segment_elements,
+        )?;
     }
 
     Ok(RenderedData {
         background_bytes: background_pixmap,
         mask_bytes: output_mask,
-        pixels_to_mask_id: pixels_to_mask_id,
+        pixels_to_mask_id,
+        input_geometry,
+        resolution: image_resolution,
+        offset,
+        overlap_warnings,
     })
 }
 
+///
+/// The smallest rectangle containing every visible pixel of any of `images`. `None` if that's the whole canvas, or
+/// nothing is visible, as there's nothing to crop
+///
+fn crop_bounds(images: &[&Pixmap], resolution: Resolution) -> Option<IntRect> {
+    let Resolution { width, height } = resolution;
+
+    // Left, top, right, and bottom of the visible pixels, inclusive
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+
+    for y in 0..height {
+        for x in 0..width {
+            if images
+                .iter()
+                .all(|image| image.pixels()[y * width + x].alpha() == 0)
+            {
+                continue;
+            }
+
+            bounds = Some(match bounds {
+                Some((left, top, right, bottom)) => {
+                    (left.min(x), top.min(y), right.max(x), bottom.max(y))
+                }
+                None => (x, y, x, y),
+            });
+        }
+    }
+
+    let (left, top, right, bottom) = bounds?;
+
+    if left == 0 && top == 0 && right == width - 1 && bottom == height - 1 {
+        return None;
+    }
+
+    IntRect::from_ltrb(left as i32, top as i32, right as i32 + 1, bottom as i32 + 1)
+}
+
+///
+/// Writes an image of each mask id to `masks_dir`, showing only the pixels of the mask assigned to it. Each is named
+/// by the id's `segment.column.row` title and the SVG element it was drawn from
+///
+fn write_segment_images(
+    masks_dir: &Path,
+    mask: &Pixmap,
+    pixels_to_mask_id: &[Option<u16>],
+    segment_elements: &BTreeMap<u16, Vec<String>>,
+) -> Result<(), BuildError> {
+    // Segments from an earlier render may no longer exist
+    if masks_dir.exists() {
+        fs::remove_dir_all(masks_dir).map_err(|err| {
+            BuildError::io(
+                format!("Could not clear segment images at {masks_dir:?}"),
+                err,
+            )
+        })?;
+    }
+
+    fs::create_dir_all(masks_dir).map_err(|err| {
+        BuildError::io(
+            format!("Could not create segment images at {masks_dir:?}"),
+            err,
+        )
+    })?;
+
+    let mut pixels_by_id: BTreeMap<u16, Vec<usize>> = BTreeMap::new();
+
+    for (i, id) in pixels_to_mask_id.iter().enumerate() {
+        if let Some(id) = id {
+            pixels_by_id.entry(*id).or_default().push(i);
+        }
+    }
+
+    for (id, pixels) in pixels_by_id {
+        let mut image = Pixmap::new(mask.width(), mask.height()).unwrap();
+
+        for i in pixels {
+            image.pixels_mut()[i] = mask.pixels()[i];
+        }
+
+        // Segments drawn from a titled group have several elements
+        let element = match segment_elements.get(&id).map(Vec::as_slice) {
+            Some([first]) => sanitize_file_name(first),
+            Some([first, rest @ ..]) => format!("{}+{}", sanitize_file_name(first), rest.len()),
+            _ => "unknown".to_string(),
+        };
+
+        let path = masks_dir.join(format!(
+            "{}.{}.{}_{element}.png",
+            id >> 6,
+            (id >> 2) & 0xF,
+            id & 0x3
+        ));
+
+        image.save_png(&path).map_err(|err| {
+            BuildError::io(
+                format!("Could not write segment image {path:?}"),
+                err.into(),
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Replaces the characters of an SVG id that can't appear in a file name
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+///
+/// Applies gamma correction and then brightness to the color channels of `pixels`, leaving alpha unchanged
+///
+fn correct_colors(pixels: &mut [PremultipliedColorU8], gamma: f32, brightness: f32) {
+    if gamma == 1.0 && brightness == 1.0 {
+        return;
+    }
+
+    // Indexed by channel value
+    let table: Vec<u8> = (0..=255)
+        .map(|value| {
+            let corrected = (value as f32 / 255.0).powf(1.0 / gamma) * brightness;
+
+            (corrected * 255.0).round().clamp(0.0, 255.0) as u8
+        })
+        .collect();
+
+    for pixel in pixels {
+        // Corrected on the straight color, so partially transparent pixels aren't darkened
+        let color = pixel.demultiply();
+
+        *pixel = ColorU8::from_rgba(
+            table[color.red() as usize],
+            table[color.green() as usize],
+            table[color.blue() as usize],
+            color.alpha(),
+        )
+        .premultiply();
+    }
+}
+
+///
+/// Resizes a PNG loaded by tiny_skia to `dimensions`
+///
+#[allow(clippy::diverging_sub_expression)]
+fn scale_image(image: Pixmap, dimensions: &ImageDimensions) -> Result<Pixmap, BuildError> {
+    let image =
+        ImageBuffer::<Rgba<u8>, Vec<u8>>::from_vec(image.width(), image.height(), image.take())
+            .expect("Could not convert image data");
+
+    let image: DynamicImage = DynamicImage::ImageRgba8(image).resize_exact(
+        dimensions.width,
+        dimensions.height,
+        FilterType::CatmullRom,
+    );
+
+    // Dimensions might change by a pixel as part of resizing
+    let image_width = image.width();
+    let image_height = image.height();
+
+    guard!(let Some(image_map) = Pixmap::from_vec(
+        image.into_bytes(),
+        tiny_skia_path::IntSize::from_wh(image_width, image_height).unwrap(),
+    ) else {
+        return Err(BuildError::InvalidLayout("Could not convert PNG into Pixmap".to_string()));
+    });
+
+    Ok(image_map)
+}
+
+/// Mirrors a buffer of pixels the size of `resolution` in place
+fn mirror_pixels<T>(pixels: &mut [T], mirror: Mirror, resolution: Resolution) {
+    let Resolution { width, height } = resolution;
+
+    match mirror {
+        Mirror::None => {}
+        Mirror::Horizontal => {
+            for row in pixels.chunks_exact_mut(width) {
+                row.reverse();
+            }
+        }
+        Mirror::Vertical => {
+            for y in 0..height / 2 {
+                let (top, bottom) = pixels.split_at_mut((height - 1 - y) * width);
+                top[y * width..(y + 1) * width].swap_with_slice(&mut bottom[..width]);
+            }
+        }
+    }
+}
+
+fn mirror_dimensions(dimensions: &mut ImageDimensions, mirror: Mirror, resolution: Resolution) {
+    match mirror {
+        Mirror::None => {}
+        Mirror::Horizontal => {
+            dimensions.x = resolution.width as i32 - (dimensions.x + dimensions.width as i32);
+        }
+        Mirror::Vertical => {
+            dimensions.y = resolution.height as i32 - (dimensions.y + dimensions.height as i32);
+        }
+    }
+}
+
+///
+/// Reduces the background to a palette of at most `colors` colors with Floyd–Steinberg dithering, so gradients don't
+/// band once the encoder stores it as palette indices. Like the encoder, the palette is learned from the opaque
+/// colors, and every pixel is made opaque. Backgrounds that already have few enough colors are left alone, as they're
+/// stored exactly
+///
+fn dither_background(background: &mut Pixmap, colors: usize) {
+    let width = background.width() as usize;
+    let opaque = background
+        .data()
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 0xFF])
+        .collect::<Vec<_>>();
+
+    let mut unique = HashSet::new();
+
+    for pixel in opaque.chunks_exact(4) {
+        unique.insert([pixel[0], pixel[1], pixel[2]]);
+
+        if unique.len() > colors {
+            break;
+        }
+    }
+
+    if unique.len() <= colors {
+        return;
+    }
+
+    let quantizer = NeuQuant::new(QUANTIZER_SAMPLE_FACTOR, colors, &opaque);
+    let palette = quantizer.color_map_rgb();
+
+    // The error diffused into each pixel by its already dithered neighbors
+    let mut errors = vec![[0.0f32; 3]; opaque.len() / 4];
+
+    for (i, pixel) in background.data_mut().chunks_exact_mut(4).enumerate() {
+        let (x, y) = (i % width, i / width);
+        let wanted = [0, 1, 2]
+            .map(|channel| (opaque[i * 4 + channel] as f32 + errors[i][channel]).clamp(0.0, 255.0));
+
+        let index = quantizer.index_of(&[wanted[0] as u8, wanted[1] as u8, wanted[2] as u8, 0xFF]);
+        let chosen = &palette[index * 3..index * 3 + 3];
+
+        for (offset_x, offset_y, weight) in [(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)] {
+            let neighbor_x = x as isize + offset_x;
+
+            if neighbor_x < 0 || neighbor_x >= width as isize {
+                continue;
+            }
+
+            if let Some(error) = errors.get_mut((y + offset_y) * width + neighbor_x as usize) {
+                for channel in 0..3 {
+                    error[channel] += (wanted[channel] - chosen[channel] as f32) * weight / 16.0;
+                }
+            }
+        }
+
+        pixel.copy_from_slice(&[chosen[0], chosen[1], chosen[2], 0xFF]);
+    }
+}
+
+///
+/// Rotates a buffer of pixels the size of `resolution` clockwise, returning the rotated buffer. Quarter turns swap its
+/// width and height
+///
+fn rotate_pixels<T: Copy>(pixels: &[T], rotation: Rotation, resolution: Resolution) -> Vec<T> {
+    let Resolution { width, height } = resolution;
+    let rotated = rotation.rotate_resolution(resolution);
+
+    (0..rotated.height)
+        .flat_map(|y| (0..rotated.width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            // The pixel of the original buffer that lands at (x, y)
+            let (source_x, source_y) = match rotation {
+                Rotation::None => (x, y),
+                Rotation::Clockwise90 => (y, height - 1 - x),
+                Rotation::Clockwise180 => (width - 1 - x, height - 1 - y),
+                Rotation::Clockwise270 => (width - 1 - y, x),
+            };
+
+            pixels[source_y * width + source_x]
+        })
+        .collect()
+}
+
+fn rotate_pixmap(pixmap: &Pixmap, rotation: Rotation) -> Pixmap {
+    let resolution = Resolution {
+        width: pixmap.width() as usize,
+        height: pixmap.height() as usize,
+    };
+    let rotated = rotation.rotate_resolution(resolution);
+
+    let mut output = Pixmap::new(rotated.width as u32, rotated.height as u32).unwrap();
+    output
+        .pixels_mut()
+        .copy_from_slice(&rotate_pixels(pixmap.pixels(), rotation, resolution));
+
+    output
+}
+
+fn rotate_dimensions(dimensions: &mut ImageDimensions, rotation: Rotation, resolution: Resolution) {
+    let (width, height) = (resolution.width as i32, resolution.height as i32);
+    let ImageDimensions {
+        x,
+        y,
+        width: element_width,
+        height: element_height,
+    } = *dimensions;
+
+    *dimensions = match rotation {
+        Rotation::None => return,
+        Rotation::Clockwise90 => ImageDimensions {
+            x: height - (y + element_height as i32),
+            y: x,
+            width: element_height,
+            height: element_width,
+        },
+        Rotation::Clockwise180 => ImageDimensions {
+            x: width - (x + element_width as i32),
+            y: height - (y + element_height as i32),
+            width: element_width,
+            height: element_height,
+        },
+        Rotation::Clockwise270 => ImageDimensions {
+            x: y,
+            y: width - (x + element_width as i32),
+            width: element_height,
+            height: element_width,
+        },
+    };
+}
+
+/// Shift bounds so the layout's shared top left offset is removed
+fn normalize_bounds(bounds: Bounds, max_common_x: i32, max_common_y: i32) -> Bounds {
+    let x = if bounds.x >= 0 {
+        // Only normalize to 0 if we started out positive
+        (bounds.x - max_common_x).max(0)
+    } else {
+        bounds.x - max_common_x
+    };
+
+    let y = if bounds.y >= 0 {
+        // Only normalize to 0 if we started out positive
+        (bounds.y - max_common_y).max(0)
+    } else {
+        bounds.y - max_common_y
+    };
+
+    Bounds {
+        x,
+        y,
+        width: bounds.width,
+        height: bounds.height,
+    }
+}
+
 fn alpha_blend_colors(
     background: PremultipliedColorU8,
     foreground: PremultipliedColorU8,
@@ -477,7 +1111,7 @@ fn multiply_blend_colors(
         .expect("Could not convert multiply blend color")
 }
 
-fn screen_filename(index: usize, platform_name: &str, platform: &PresetDefinition) -> String {
+pub fn screen_filename(index: usize, platform_name: &str, platform: &PresetDefinition) -> String {
     let suffix = match platform.screen {
         manifest::Screen::Single { .. } => "",
         manifest::Screen::DualVertical { .. } => {
@@ -515,8 +1149,8 @@ impl ImageDimensions {
         x_offset: i32,
         y_offset: i32,
     ) -> Self {
-        let x = ((bounds.x as i32 - view_bounds.x as i32) as f32 * ratio).round() as i32;
-        let y = ((bounds.y as i32 - view_bounds.y as i32) as f32 * ratio).round() as i32;
+        let x = ((bounds.x - view_bounds.x) as f32 * ratio).round() as i32;
+        let y = ((bounds.y - view_bounds.y) as f32 * ratio).round() as i32;
         let width = (bounds.width as f32 * ratio) as u32;
         let height = (bounds.height as f32 * ratio) as u32;
 
@@ -536,3 +1170,63 @@ impl ImageDimensions {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirror_segment_box() {
+        let resolution = Resolution {
+            width: 6,
+            height: 4,
+        };
+
+        // A 2x2 segment with its top left at (1, 0)
+        let segment = ImageDimensions {
+            x: 1,
+            y: 0,
+            width: 2,
+            height: 2,
+        };
+        let mut pixels = vec![None; resolution.width * resolution.height];
+        for y in 0..2 {
+            for x in 1..3 {
+                pixels[y * resolution.width + x] = Some(5u16);
+            }
+        }
+
+        let lit = |pixels: &[Option<u16>]| {
+            pixels
+                .iter()
+                .enumerate()
+                .filter(|(_, id)| **id == Some(5))
+                .map(|(i, _)| (i % resolution.width, i / resolution.width))
+                .collect::<Vec<_>>()
+        };
+
+        for (mirror, expected_pixels, expected_x, expected_y) in [
+            (Mirror::None, [(1, 0), (2, 0), (1, 1), (2, 1)], 1, 0),
+            (Mirror::Horizontal, [(3, 0), (4, 0), (3, 1), (4, 1)], 3, 0),
+            (Mirror::Vertical, [(1, 2), (2, 2), (1, 3), (2, 3)], 1, 2),
+        ] {
+            let mut mirrored = pixels.clone();
+            mirror_pixels(&mut mirrored, mirror, resolution);
+            assert_eq!(lit(&mirrored), expected_pixels, "{mirror:?}");
+
+            // The segment's box moves with its pixels, keeping its size
+            let mut dimensions = segment.clone();
+            mirror_dimensions(&mut dimensions, mirror, resolution);
+            assert_eq!(
+                (
+                    dimensions.x,
+                    dimensions.y,
+                    dimensions.width,
+                    dimensions.height
+                ),
+                (expected_x, expected_y, 2, 2),
+                "{mirror:?}"
+            );
+        }
+    }
+}
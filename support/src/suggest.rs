@@ -0,0 +1,92 @@
+///
+/// The `count` names closest to `pattern` by edit distance, closest first, for suggesting corrections to a mistyped
+/// game name. Wildcards in `pattern` are ignored, and names are also compared without their set prefix (such as
+/// `gnw_`), so a name given without its prefix still finds its game
+///
+pub fn closest_names<'a>(
+    pattern: &str,
+    names: impl Iterator<Item = &'a String>,
+    count: usize,
+) -> Vec<&'a str> {
+    let target = pattern.replace(['*', '?'], "").to_lowercase();
+
+    let mut names = names
+        .map(|name| {
+            let name_lower = name.to_lowercase();
+            let distance = edit_distance(&target, &name_lower);
+
+            let unprefixed_distance = name_lower
+                .split_once('_')
+                .map_or(distance, |(_, unprefixed)| {
+                    edit_distance(&target, unprefixed)
+                });
+
+            (distance.min(unprefixed_distance), name)
+        })
+        .collect::<Vec<(usize, &String)>>();
+
+    names.sort();
+
+    names
+        .into_iter()
+        .take(count)
+        .map(|(_, name)| name.as_str())
+        .collect()
+}
+
+///
+/// The Levenshtein distance between `a` and `b`: the number of single character insertions, deletions, and
+/// substitutions needed to turn one into the other
+///
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("gnw_dkong", "gnw_dkong"), 0);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("gnw_dkogn", "gnw_dkong"), 2);
+    }
+
+    #[test]
+    fn closest() {
+        let names = [
+            "gnw_dkong",
+            "gnw_dkong2",
+            "gnw_mario",
+            "gnw_ball",
+            "tgaw_dkong",
+        ]
+        .map(String::from);
+
+        assert_eq!(
+            closest_names("gnw_dkng", names.iter(), 3),
+            ["gnw_dkong", "gnw_dkong2", "tgaw_dkong"]
+        );
+        assert_eq!(closest_names("mario", names.iter(), 1), ["gnw_mario"]);
+        assert_eq!(closest_names("GNW_BAL*", names.iter(), 1), ["gnw_ball"]);
+        assert!(closest_names("gnw_ball", names.iter(), 0).is_empty());
+    }
+}
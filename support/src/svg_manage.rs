@@ -1,39 +1,114 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap},
     fs,
     path::PathBuf,
+    time::{Duration, Instant},
 };
 
+use log::trace;
+use rayon::prelude::*;
+
 use resvg::{
     tiny_skia::{self, Pixmap, PixmapPaint, PremultipliedColorU8},
     usvg::{self, NodeKind, Tree, TreeParsing},
     FitTo,
 };
 
-use rctree;
 use svg::{self, node::element::tag::Type};
 use tiny_skia_path::Transform;
 
-use crate::{render::ImageDimensions, HEIGHT, WIDTH};
+use crate::{
+    error::BuildError,
+    render::{ImageDimensions, MaskTiebreak, Resolution},
+    task_log::logln,
+};
 
 pub struct RenderedSVG {
     pub pixmap: Pixmap,
     pub pixel_pos_to_id: Vec<Option<u16>>,
+    /// The mask id and SVG element id of each segment, in document order
+    pub elements: Vec<(u16, String)>,
+}
+
+///
+/// Follows which segment owns each canvas pixel as a device's screens are rendered, to report segments with different
+/// mask ids that overlap. Only one of them can own the pixel, so this is almost always a layout bug
+///
+pub struct OverlapTracker {
+    /// A description of each segment, in the order they were combined
+    segments: Vec<String>,
+    /// The segment owning each pixel, and its mask id
+    owners: Vec<Option<(usize, u16)>>,
+    /// The number of pixels each pair of segments overlap by, keyed by the earlier segment first
+    overlaps: BTreeMap<(usize, usize), usize>,
+}
+
+impl OverlapTracker {
+    pub fn new(resolution: Resolution) -> Self {
+        OverlapTracker {
+            segments: vec![],
+            owners: vec![None; resolution.width * resolution.height],
+            overlaps: BTreeMap::new(),
+        }
+    }
+
+    fn add_segment(&mut self, description: String) -> usize {
+        self.segments.push(description);
+
+        self.segments.len() - 1
+    }
+
+    fn claim(&mut self, pixel: usize, segment: usize, id: u16, tiebreak: MaskTiebreak) {
+        if let Some((owner, existing)) = self.owners[pixel] {
+            if existing != id {
+                *self.overlaps.entry((owner, segment)).or_default() += 1;
+
+                if tiebreak.resolve(Some(existing), id) == existing {
+                    // The existing segment keeps the pixel
+                    return;
+                }
+            }
+        }
+
+        self.owners[pixel] = Some((segment, id));
+    }
+
+    /// A warning for each pair of overlapping segments, in the order they were found
+    pub fn warnings(&self) -> Vec<String> {
+        self.overlaps
+            .iter()
+            .map(|((first, second), pixels)| {
+                format!(
+                    "Segment {} overlaps segment {} by {pixels} pixels",
+                    self.segments[*first], self.segments[*second]
+                )
+            })
+            .collect()
+    }
 }
 
+///
+/// Renders the SVG and maps each pixel to the ID of the segment covering it. IDs come from the segment titles
+/// rather than the order segments are found in, and overlaps are resolved by `tiebreak` in document order, so the
+/// same SVG always produces the same mapping
+///
 pub fn build_svg(
     svg_path: &PathBuf,
     alternate_svg_path: &Option<PathBuf>,
     dimensions: &ImageDimensions,
-) -> Result<RenderedSVG, String> {
+    resolution: Resolution,
+    tiebreak: MaskTiebreak,
+    overlaps: &mut OverlapTracker,
+) -> Result<RenderedSVG, BuildError> {
     // Actual SVG ID (so `path123`) to title field (the segment ID)
-    let svg_error = |path: &PathBuf| format!("Could not load SVG at {path:?}");
+    let svg_error =
+        |path: &PathBuf| BuildError::AssetMissing(format!("Could not load SVG at {path:?}"));
 
-    let contents = if let Ok(contents) = fs::read_to_string(svg_path) {
-        contents
+    let (contents, loaded_path) = if let Ok(contents) = fs::read_to_string(svg_path) {
+        (contents, svg_path)
     } else if let Some(path) = alternate_svg_path {
         if let Ok(contents) = fs::read_to_string(path) {
-            contents
+            (contents, path)
         } else {
             return Err(svg_error(svg_path));
         }
@@ -41,100 +116,158 @@ pub fn build_svg(
         return Err(svg_error(svg_path));
     };
 
-    let svg_id_to_title = correlate_id_to_title(&contents)?;
+    let svg_id_to_title = correlate_id_to_title(&contents)
+        .map_err(|err| BuildError::InvalidLayout(format!("{err} in {loaded_path:?}")))?;
+
+    let file_name = loaded_path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+
+    let (tree, title_trees) = segment_trees(&contents, &svg_id_to_title);
+    let segment_count = title_trees.len();
+    let elements = title_trees
+        .into_iter()
+        .map(|(_, id, svg_id)| (id, svg_id))
+        .collect::<Vec<(u16, String)>>();
+    let descriptions = elements
+        .iter()
+        .map(|(id, svg_id)| format!("\"{svg_id}\" ({id:#06x}) in {file_name}"))
+        .collect::<Vec<String>>();
+
+    // Segments are rasterized in parallel, on the pool the device is built on. The trees can't be shared between
+    // threads, so each worker parses its own copy
+    let segments = (0..segment_count)
+        .into_par_iter()
+        .map_init(
+            || {
+                let (tree, title_trees) = segment_trees(&contents, &svg_id_to_title);
+                let canvas =
+                    Pixmap::new(resolution.width as u32, resolution.height as u32).unwrap();
+
+                (tree, title_trees, canvas)
+            },
+            |(tree, title_trees, canvas), index| {
+                let segment_start = Instant::now();
+                let (title_tree, id, _) = &title_trees[index];
+
+                let covered = rasterize_segment(tree, title_tree, dimensions, resolution, canvas);
+
+                (*id, covered, segment_start.elapsed())
+            },
+        )
+        .collect::<Vec<(u16, Vec<usize>, Duration)>>();
 
-    let tree = usvg::Tree::from_str(&contents, &usvg::Options::default()).unwrap();
+    let mut pixel_pos_to_id: Vec<Option<u16>> = vec![None; resolution.width * resolution.height];
 
-    // Clear unnecessary nodes
-    for node in tree.root.descendants() {
-        if !keep_usvg_node(&node, &svg_id_to_title) {
-            node.detach();
-        }
-    }
+    // Combine in document order, so overlapping segments resolve the same regardless of scheduling
+    for ((id, covered, elapsed), description) in segments.into_iter().zip(descriptions) {
+        let segment = overlaps.add_segment(description);
 
-    pub type Node = rctree::Node<NodeKind>;
+        for i in covered {
+            pixel_pos_to_id[i] = Some(tiebreak.resolve(pixel_pos_to_id[i], id));
+            overlaps.claim(i, segment, id, tiebreak);
+        }
 
-    let mut title_trees: Vec<(Node, u16)> = vec![];
+        trace!("Rendered segment {id:#06x} in {elapsed:?}");
+    }
 
-    // Build sets of subtrees and ids
-    for node in tree.root.descendants() {
-        let element = node.borrow();
+    // This scales proportionally, which is not always what MAME does (gnw_cgrab)
+    let mut render_pixmap = Pixmap::new(dimensions.width, dimensions.height).unwrap();
+    resvg::render(
+        &tree,
+        FitTo::Size(dimensions.width, dimensions.height),
+        tiny_skia::Transform::default(),
+        render_pixmap.as_mut(),
+    )
+    .expect("Could not render SVG to bitmap");
 
-        match *element {
-            usvg::NodeKind::Path(ref path) => {
-                // Check if we care about this path
-                guard!(let Some(title) = svg_id_to_title.get(&path.id) else {
-                    continue;
-                });
+    // This is inefficient, but it transforms the coordinates for us
+    let mut mask_pixmap = Pixmap::new(resolution.width as u32, resolution.height as u32).unwrap();
+    mask_pixmap.draw_pixmap(
+        dimensions.x,
+        dimensions.y,
+        render_pixmap.as_ref(),
+        &PixmapPaint::default(),
+        Transform::identity(),
+        None,
+    );
 
-                let mut owning_tree = Node::new(element.clone());
-                let mut next_parent = node.parent();
+    Ok(RenderedSVG {
+        pixmap: mask_pixmap,
+        pixel_pos_to_id,
+        elements,
+    })
+}
 
-                while let Some(parent) = next_parent {
-                    let new_parent = Node::new(parent.borrow().clone());
-                    new_parent.append(owning_tree);
-                    owning_tree = new_parent;
+pub type Node = rctree::Node<NodeKind>;
 
-                    next_parent = parent.parent();
-                }
+///
+/// Parses the SVG, keeping only the paths that are segments, and splits out a tree for each segment (along with its
+/// ancestors, for their transforms) with its mask id and SVG id, in document order
+///
+#[allow(clippy::diverging_sub_expression)]
+fn segment_trees(
+    contents: &str,
+    svg_id_to_title: &HashMap<String, u16>,
+) -> (Tree, Vec<(Node, u16, String)>) {
+    let tree = usvg::Tree::from_str(contents, &usvg::Options::default()).unwrap();
 
-                title_trees.push((owning_tree, *title));
-            }
-            _ => {}
+    // Clear unnecessary nodes
+    for node in tree.root.descendants() {
+        if !keep_usvg_node(&node, svg_id_to_title) {
+            node.detach();
         }
     }
 
-    let mut id_mask_pixmap = Pixmap::new(WIDTH as u32, HEIGHT as u32).unwrap();
+    let mut title_trees: Vec<(Node, u16, String)> = vec![];
 
-    let mut pixel_pos_to_id: Vec<Option<u16>> = vec![None; WIDTH * HEIGHT];
-
-    // Extract pixel to ID mapping
-    for (title_tree, id) in title_trees {
-        let tree = Tree {
-            size: tree.size,
-            view_box: tree.view_box,
-            root: title_tree,
-        };
+    // Build sets of subtrees and ids
+    for node in tree.root.descendants() {
+        let element = node.borrow();
 
-        let mut render_pixmap = Pixmap::new(dimensions.width, dimensions.height).unwrap();
+        if let usvg::NodeKind::Path(ref path) = *element {
+            // Check if we care about this path
+            guard!(let Some(title) = svg_id_to_title.get(&path.id) else {
+                continue;
+            });
 
-        resvg::render(
-            &tree,
-            FitTo::Size(dimensions.width, dimensions.height),
-            tiny_skia::Transform::default(),
-            render_pixmap.as_mut(),
-        )
-        .expect("Could not render SVG to bitmap");
-
-        // This is very wasteful and exists just to transform the coordinates, but I'm lazy
-        id_mask_pixmap.draw_pixmap(
-            dimensions.x,
-            dimensions.y,
-            render_pixmap.as_ref(),
-            &PixmapPaint::default(),
-            Transform::identity(),
-            None,
-        );
+            let mut owning_tree = Node::new(element.clone());
+            let mut next_parent = node.parent();
 
-        let pixels = id_mask_pixmap.pixels_mut();
+            while let Some(parent) = next_parent {
+                let new_parent = Node::new(parent.borrow().clone());
+                new_parent.append(owning_tree);
+                owning_tree = new_parent;
 
-        for i in 0..WIDTH * HEIGHT {
-            let pixel = pixels[i];
-            if pixel.alpha() == 0 {
-                // Skip this pixel
-                continue;
+                next_parent = parent.parent();
             }
 
-            // Copy id to a pixel indexed array
-            pixel_pos_to_id[i] = Some(id);
-
-            // Zero out this pixel for next render
-            pixels[i] = PremultipliedColorU8::from_rgba(0, 0, 0, 0).unwrap();
+            title_trees.push((owning_tree, *title, path.id.clone()));
         }
     }
 
-    // This scales proportionally, which is not always what MAME does (gnw_cgrab)
+    (tree, title_trees)
+}
+
+///
+/// Renders a single segment, returning the index of every canvas pixel it covers. `canvas` is scratch space the size
+/// of the canvas, which is left cleared
+///
+fn rasterize_segment(
+    tree: &Tree,
+    title_tree: &Node,
+    dimensions: &ImageDimensions,
+    resolution: Resolution,
+    canvas: &mut Pixmap,
+) -> Vec<usize> {
+    let tree = Tree {
+        size: tree.size,
+        view_box: tree.view_box,
+        root: title_tree.clone(),
+    };
+
     let mut render_pixmap = Pixmap::new(dimensions.width, dimensions.height).unwrap();
+
     resvg::render(
         &tree,
         FitTo::Size(dimensions.width, dimensions.height),
@@ -143,9 +276,8 @@ pub fn build_svg(
     )
     .expect("Could not render SVG to bitmap");
 
-    // This is inefficient, but it transforms the coordinates for us
-    let mut mask_pixmap = Pixmap::new(WIDTH as u32, HEIGHT as u32).unwrap();
-    mask_pixmap.draw_pixmap(
+    // This is very wasteful and exists just to transform the coordinates, but I'm lazy
+    canvas.draw_pixmap(
         dimensions.x,
         dimensions.y,
         render_pixmap.as_ref(),
@@ -154,66 +286,87 @@ pub fn build_svg(
         None,
     );
 
-    Ok(RenderedSVG {
-        pixmap: mask_pixmap,
-        pixel_pos_to_id,
-    })
+    // Only the segment's own bounds can have been drawn to
+    let clamp = |start: i32, length: u32, max: usize| {
+        (start.max(0) as usize).min(max)..((start + length as i32).max(0) as usize).min(max)
+    };
+    let rows = clamp(dimensions.y, dimensions.height, resolution.height);
+    let columns = clamp(dimensions.x, dimensions.width, resolution.width);
+
+    let pixels = canvas.pixels_mut();
+    let mut covered = vec![];
+
+    for y in rows {
+        for i in columns.clone().map(|x| y * resolution.width + x) {
+            if pixels[i].alpha() == 0 {
+                continue;
+            }
+
+            covered.push(i);
+
+            // Zero out this pixel for the next segment
+            pixels[i] = PremultipliedColorU8::from_rgba(0, 0, 0, 0).unwrap();
+        }
+    }
+
+    covered
 }
 
 fn parse_title(title: &str) -> Option<u16> {
-    let mut sections = title.split(".");
+    let mut sections = title.split('.');
 
-    guard!(let Ok(segment) = sections.next()?.parse::<u8>() else {
-        println!("Could not parse segment from title {title}");
+    guard!(let Ok(segment) = sections.next()?.parse::<u16>() else {
+        logln!("Could not parse segment from title {title}");
         return None;
     });
 
-    if segment > 15 {
-        println!("Segment {segment} in {title} was out of bounds");
+    // Segments past 15 need the wide mask entry format, which holds 16 bit ids
+    if segment > 0x3FF {
+        logln!("Segment {segment} in {title} was out of bounds");
         return None;
     }
 
-    let segment = segment as u16;
-
     guard!(let Ok(column) = sections.next()?.parse::<u8>() else {
-        println!("Could not parse column from title {title}");
+        logln!("Could not parse column from title {title}");
         return None;
     });
 
     if column > 15 {
-        println!("Column {column} in {title} was out of bounds");
+        logln!("Column {column} in {title} was out of bounds");
         return None;
     }
 
     let column = column as u16;
 
     guard!(let Ok(row_h) = sections.next()?.parse::<u8>() else {
-        println!("Could not parse row_h from title {title}");
+        logln!("Could not parse row_h from title {title}");
         return None;
     });
 
     if row_h > 4 {
-        println!("Row {row_h} in {title} was out of bounds");
+        logln!("Row {row_h} in {title} was out of bounds");
         return None;
     }
 
     let row_h = row_h as u16;
 
-    if sections.next() != None {
-        println!("Title contained too many groups");
+    if sections.next().is_some() {
+        logln!("Title contained too many groups");
     }
 
-    return Some((segment << 6) | (column << 2) | row_h);
+    Some((segment << 6) | (column << 2) | row_h)
 }
 
-fn correlate_id_to_title(contents: &String) -> Result<HashMap<String, u16>, String> {
+#[allow(clippy::diverging_sub_expression)]
+fn correlate_id_to_title(contents: &str) -> Result<HashMap<String, u16>, String> {
     let mut svg_id_to_title: HashMap<String, u16> = HashMap::new();
 
     #[derive(PartialEq, Debug)]
     struct ActiveGroup {
         id: Option<String>,
         title: Option<u16>,
-        paths: HashSet<String>,
+        // Kept in document order, so nothing about the mapping depends on hashing
+        paths: Vec<String>,
     }
 
     #[derive(PartialEq, Debug)]
@@ -233,13 +386,13 @@ fn correlate_id_to_title(contents: &String) -> Result<HashMap<String, u16>, Stri
                 group_stack.push(ActiveGroup {
                     id: attributes.get("id").map(|v| v.clone().into()),
                     title: None,
-                    paths: HashSet::new(),
+                    paths: vec![],
                 });
             }
             svg::parser::Event::Tag("path", Type::Start, attributes) => {
                 let id: Option<String> = attributes.get("id").map(|v| v.clone().into());
 
-                if active_path != None {
+                if active_path.is_some() {
                     return Err(format!("SVG contains invalid nested paths at {id:?}"));
                 }
 
@@ -294,7 +447,7 @@ fn correlate_id_to_title(contents: &String) -> Result<HashMap<String, u16>, Stri
 
                 if let Some(ActiveGroup { paths, .. }) = group_stack.last_mut() {
                     // Mark this path as being a part of the group
-                    paths.insert(id.clone());
+                    paths.push(id.clone());
                 }
 
                 if let Some(title) = path.title {
@@ -332,18 +485,287 @@ fn correlate_id_to_title(contents: &String) -> Result<HashMap<String, u16>, Stri
 }
 
 fn keep_usvg_node(node: &usvg::Node, svg_id_to_title: &HashMap<String, u16>) -> bool {
-    match *node.borrow() {
-        usvg::NodeKind::Path(ref path) => {
-            if path.id.is_empty() {
-                return false;
-            }
+    if let usvg::NodeKind::Path(ref path) = *node.borrow() {
+        if path.id.is_empty() {
+            return false;
+        }
+
+        if !svg_id_to_title.contains_key(&path.id) {
+            return false;
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    const SVG: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="20" viewBox="0 0 40 20">
+        <path id="a" d="M0 0 H12 V12 H0 Z"><title>0.0.0</title></path>
+        <g transform="translate(6 4)">
+            <title>1.2.3</title>
+            <path id="b" d="M0 0 H12 V12 H0 Z" />
+            <path id="c" d="M20 0 H28 V8 H20 Z"><title>15.15.4</title></path>
+        </g>
+        <path id="d" d="M4 6 H30 V10 H4 Z"><title>2.0.1</title></path>
+        <path id="e" d="M0 18 H40 V20 H0 Z" />
+    </svg>"#;
+
+    fn write_svg(name: &str, contents: &str) -> PathBuf {
+        let path =
+            env::temp_dir().join(format!("gnw_svg_manage_{name}_{}.svg", std::process::id()));
+        fs::write(&path, contents).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn overlap_tiebreak() {
+        // `b` is drawn after `a`, but has the lower mask id
+        let path = write_svg(
+            "tiebreak",
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10" viewBox="0 0 20 10">
+                <path id="a" d="M0 0 H12 V10 H0 Z"><title>2.0.0</title></path>
+                <path id="b" d="M8 0 H20 V10 H8 Z"><title>1.0.0</title></path>
+            </svg>"#,
+        );
+
+        let dimensions = ImageDimensions {
+            x: 0,
+            y: 0,
+            width: 20,
+            height: 10,
+        };
+        let resolution = Resolution {
+            width: 20,
+            height: 10,
+        };
 
-            if !svg_id_to_title.contains_key(&path.id) {
-                return false;
+        for (tiebreak, expected) in [
+            (MaskTiebreak::DrawOrder, 0x40),
+            (MaskTiebreak::LowestId, 0x40),
+            (MaskTiebreak::HighestId, 0x80),
+        ] {
+            let mut overlaps = OverlapTracker::new(resolution);
+            let rendered = build_svg(
+                &path,
+                &None,
+                &dimensions,
+                resolution,
+                tiebreak,
+                &mut overlaps,
+            )
+            .unwrap();
+
+            // Outside the overlap, each segment keeps its own id
+            assert_eq!(
+                rendered.pixel_pos_to_id[5 * 20 + 2],
+                Some(0x80),
+                "{tiebreak:?}"
+            );
+            assert_eq!(
+                rendered.pixel_pos_to_id[5 * 20 + 17],
+                Some(0x40),
+                "{tiebreak:?}"
+            );
+            assert_eq!(
+                rendered.pixel_pos_to_id[5 * 20 + 10],
+                Some(expected),
+                "{tiebreak:?}"
+            );
+        }
+
+        // Reversing the draw order changes the winner only for the draw order policy
+        let reversed = write_svg(
+            "tiebreak_reversed",
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10" viewBox="0 0 20 10">
+                <path id="b" d="M8 0 H20 V10 H8 Z"><title>1.0.0</title></path>
+                <path id="a" d="M0 0 H12 V10 H0 Z"><title>2.0.0</title></path>
+            </svg>"#,
+        );
+
+        for (tiebreak, expected) in [
+            (MaskTiebreak::DrawOrder, 0x80),
+            (MaskTiebreak::LowestId, 0x40),
+            (MaskTiebreak::HighestId, 0x80),
+        ] {
+            let mut overlaps = OverlapTracker::new(resolution);
+            let rendered = build_svg(
+                &reversed,
+                &None,
+                &dimensions,
+                resolution,
+                tiebreak,
+                &mut overlaps,
+            )
+            .unwrap();
+
+            assert_eq!(
+                rendered.pixel_pos_to_id[5 * 20 + 10],
+                Some(expected),
+                "{tiebreak:?}"
+            );
+        }
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&reversed).unwrap();
+    }
+
+    #[test]
+    fn parallel_render_matches_single_threaded() {
+        let path = write_svg("parallel", SVG);
+
+        let dimensions = ImageDimensions {
+            x: -3,
+            y: 5,
+            width: 80,
+            height: 40,
+        };
+        let resolution = Resolution {
+            width: 72,
+            height: 48,
+        };
+
+        let render = |threads: usize, tiebreak: MaskTiebreak| {
+            let mut overlaps = OverlapTracker::new(resolution);
+
+            let rendered = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| {
+                    build_svg(
+                        &path,
+                        &None,
+                        &dimensions,
+                        resolution,
+                        tiebreak,
+                        &mut overlaps,
+                    )
+                })
+                .unwrap();
+
+            (rendered, overlaps.warnings())
+        };
+
+        for tiebreak in [
+            MaskTiebreak::DrawOrder,
+            MaskTiebreak::LowestId,
+            MaskTiebreak::HighestId,
+        ] {
+            let (single, single_warnings) = render(1, tiebreak);
+
+            assert!(single.pixel_pos_to_id.iter().any(Option::is_some));
+            assert!(!single_warnings.is_empty());
+
+            for threads in [2, 8] {
+                let (parallel, parallel_warnings) = render(threads, tiebreak);
+
+                assert_eq!(single.pixel_pos_to_id, parallel.pixel_pos_to_id);
+                assert_eq!(single.pixmap.data(), parallel.pixmap.data());
+                assert_eq!(single_warnings, parallel_warnings);
             }
         }
-        _ => {}
+
+        fs::remove_file(&path).unwrap();
     }
 
-    return true;
+    #[test]
+    fn mask_ids_are_stable() {
+        let segment_a = r#"<path id="a" d="M0 0 H10 V10 H0 Z"><title>1.2.3</title></path>"#;
+        let segment_b = r#"<g><title>3.1.0</title><path id="b" d="M20 0 H30 V10 H20 Z" /></g>"#;
+        let svg = |first: &str, second: &str| {
+            format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="20" viewBox="0 0 40 20">{first}{second}</svg>"#
+            )
+        };
+
+        let dimensions = ImageDimensions {
+            x: 0,
+            y: 0,
+            width: 40,
+            height: 20,
+        };
+        let resolution = Resolution {
+            width: 40,
+            height: 20,
+        };
+
+        let render = |name: &str, contents: &str| {
+            let path = write_svg(name, contents);
+            let rendered = build_svg(
+                &path,
+                &None,
+                &dimensions,
+                resolution,
+                MaskTiebreak::DrawOrder,
+                &mut OverlapTracker::new(resolution),
+            )
+            .unwrap();
+            fs::remove_file(&path).unwrap();
+
+            rendered.pixel_pos_to_id
+        };
+
+        let ids = render("stable", &svg(segment_a, segment_b));
+        let id_at = |x: usize, y: usize| ids[y * resolution.width + x];
+
+        // segment.column.row
+        assert_eq!(id_at(5, 5), Some((1 << 6) | (2 << 2) | 3));
+        assert_eq!(id_at(25, 5), Some((3 << 6) | (1 << 2)));
+        assert_eq!(id_at(15, 5), None);
+
+        // Rendering again, or with the segments in the other order, doesn't change the mapping
+        assert_eq!(ids, render("stable_again", &svg(segment_a, segment_b)));
+        assert_eq!(ids, render("stable_swapped", &svg(segment_b, segment_a)));
+    }
+
+    #[test]
+    fn overlap_warnings() {
+        let path = write_svg(
+            "overlap",
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="20" viewBox="0 0 40 20">
+                <path id="a" d="M0 0 H10 V10 H0 Z"><title>1.0.0</title></path>
+                <path id="b" d="M5 0 H15 V10 H5 Z"><title>2.0.0</title></path>
+                <g><title>2.0.0</title><path id="c" d="M10 0 H20 V10 H10 Z" /></g>
+                <path id="d" d="M30 0 H40 V10 H30 Z"><title>3.0.0</title></path>
+            </svg>"#,
+        );
+
+        let dimensions = ImageDimensions {
+            x: 0,
+            y: 0,
+            width: 40,
+            height: 20,
+        };
+        let resolution = Resolution {
+            width: 40,
+            height: 20,
+        };
+
+        let mut overlaps = OverlapTracker::new(resolution);
+        build_svg(
+            &path,
+            &None,
+            &dimensions,
+            resolution,
+            MaskTiebreak::DrawOrder,
+            &mut overlaps,
+        )
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // `b` and `c` share a mask id, so only `a` and `b` overlap
+        let file_name = path.file_name().unwrap().to_string_lossy();
+        assert_eq!(
+            overlaps.warnings(),
+            vec![format!(
+                "Segment \"a\" (0x0040) in {file_name} overlaps segment \"b\" (0x0080) in {file_name} by 50 pixels"
+            )]
+        );
+    }
 }
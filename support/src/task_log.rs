@@ -0,0 +1,138 @@
+use std::{
+    cell::RefCell,
+    env,
+    fmt::{self, Write as _},
+    io::{self, Write as _},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+use log::LevelFilter;
+
+thread_local! {
+    static BUFFER: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Whether output outside of a device's log goes to stderr, leaving stdout for the encoded file
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+///
+/// Prints a line to the log of the device being processed on this thread, or directly to stdout outside of one (or
+/// stderr, after `print_to_stderr`). Use this instead of `println!` in anything run per device, so the logs of devices
+/// built in parallel don't interleave
+///
+#[macro_export]
+macro_rules! logln {
+    () => {
+        $crate::task_log::write(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::task_log::write(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+pub use logln;
+
+pub fn write(args: fmt::Arguments) {
+    BUFFER.with(|buffer| match buffer.borrow_mut().as_mut() {
+        // Writing to a String can't fail
+        Some(buffer) => buffer.write_fmt(args).unwrap(),
+        None => print_direct(args),
+    });
+}
+
+///
+/// Sends everything printed outside of a device's log to stderr from now on, so stdout only carries the ROM written
+/// with `--output-path -`
+///
+pub fn print_to_stderr() {
+    TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+fn print_direct(args: fmt::Arguments) {
+    if TO_STDERR.load(Ordering::Relaxed) {
+        eprint!("{args}");
+    } else {
+        print!("{args}");
+    }
+}
+
+///
+/// Forwards what `env_logger` formats to `write`, so records are kept with the log of the device they belong to
+///
+struct TaskWriter;
+
+impl io::Write for TaskWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write(format_args!("{}", String::from_utf8_lossy(buf)));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+///
+/// Installs the logger. Warnings are always shown, stage timings with a verbosity of 1, and per-element and
+/// per-segment rendering with 2 or more. Records from dependencies are dropped, unless `RUST_LOG` is set, in which
+/// case it takes precedence over the verbosity
+///
+pub fn init(verbosity: u8) {
+    let level = match verbosity {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+
+    let mut builder = env_logger::Builder::new();
+
+    match env::var(env_logger::DEFAULT_FILTER_ENV) {
+        Ok(filters) => builder.parse_filters(&filters),
+        Err(_) => builder
+            .filter_level(LevelFilter::Off)
+            .filter_module(env!("CARGO_CRATE_NAME"), level),
+    };
+
+    // Only fails if a logger is already installed
+    let _ = builder
+        .format(|out, record| writeln!(out, "[{}] {}", record.level(), record.args()))
+        .target(env_logger::Target::Pipe(Box::new(TaskWriter)))
+        .try_init();
+}
+
+///
+/// Runs `task`, returning everything it logged on this thread instead of printing it. Captures can nest, as a thread
+/// waiting on parallel work within one device may pick up another device in the meantime
+///
+pub fn capture<T>(task: impl FnOnce() -> T) -> (T, String) {
+    struct Capture {
+        previous: Option<String>,
+    }
+
+    impl Drop for Capture {
+        fn drop(&mut self) {
+            let output = BUFFER.with(|buffer| buffer.replace(self.previous.take()));
+
+            if thread::panicking() {
+                // Don't lose the context leading up to an uncaught panic
+                if let Some(output) = output {
+                    print_direct(format_args!("{output}"));
+                }
+            }
+        }
+    }
+
+    let capture = Capture {
+        previous: BUFFER.with(|buffer| buffer.replace(Some(String::new()))),
+    };
+
+    let result = task();
+
+    let output = BUFFER
+        .with(|buffer| buffer.borrow_mut().take())
+        .unwrap_or_default();
+    drop(capture);
+
+    (result, output)
+}